@@ -0,0 +1,43 @@
+import { TrzszClient } from './trzsz';
+import { ZmodemReceiver, ZmodemSender } from './zmodem';
+
+export type TransferProtocol = 'zmodem' | 'trzsz';
+/** As seen from the browser: `upload` for rz/trz, `download` for sz/tsz. */
+export type TransferDirection = 'upload' | 'download';
+
+/** What a transfer needs from the terminal it runs in. */
+export interface TransferPeer {
+  /** Protocol data for the remote side. */
+  send(data: Uint8Array): void;
+  /** Resolves once enough sent data has gone out to send more. */
+  drain(): Promise<void>;
+  /** Files to upload, or null if the user cancelled. */
+  chooseFiles(): Promise<File[] | null>;
+  /** Hand a received file to the browser. */
+  save(name: string, data: Blob): void;
+  /** The current step, for the transfer banner. */
+  status(text: string): void;
+  /** A message for the terminal. */
+  print(text: string): void;
+}
+
+export interface TransferHandler {
+  /** Output of the remote side while the transfer runs. */
+  receive(data: Uint8Array): void;
+  /** Stop on this side; resolves with what the remote side needs to stop too. */
+  cancel(): Promise<Uint8Array>;
+  /**
+   * Resolves when the transfer is over, with the output that followed it.
+   * That belongs to the terminal again.
+   */
+  readonly done: Promise<Uint8Array>;
+}
+
+export function startTransfer(
+  protocol: TransferProtocol,
+  direction: TransferDirection,
+  peer: TransferPeer,
+): TransferHandler {
+  if (protocol === 'trzsz') return new TrzszClient(peer);
+  return direction === 'upload' ? new ZmodemSender(peer) : new ZmodemReceiver(peer);
+}
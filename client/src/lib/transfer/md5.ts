@@ -0,0 +1,80 @@
+// MD5, which trzsz uses to check each file; WebCrypto doesn't offer it.
+
+const SHIFTS = [
+  7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+  5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20,
+  4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+  6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+const K = Uint32Array.from({ length: 64 }, (_, i) => Math.floor(Math.abs(Math.sin(i + 1)) * 2 ** 32));
+
+export class Md5 {
+  private state = Uint32Array.of(0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476);
+  private block = new Uint8Array(64);
+  private used = 0;
+  private length = 0;
+
+  update(data: Uint8Array) {
+    this.length += data.length;
+    let offset = 0;
+    while (offset < data.length) {
+      const n = Math.min(64 - this.used, data.length - offset);
+      this.block.set(data.subarray(offset, offset + n), this.used);
+      this.used += n;
+      offset += n;
+      if (this.used === 64) {
+        this.compress();
+        this.used = 0;
+      }
+    }
+  }
+
+  digest(): Uint8Array {
+    const bits = this.length * 8;
+    const padding = new Uint8Array(this.used < 56 ? 56 - this.used : 120 - this.used);
+    padding[0] = 0x80;
+    const length = new Uint8Array(8);
+    const view = new DataView(length.buffer);
+    view.setUint32(0, bits >>> 0, true);
+    view.setUint32(4, Math.floor(bits / 2 ** 32), true);
+    this.update(padding);
+    this.update(length);
+    const out = new Uint8Array(16);
+    const outView = new DataView(out.buffer);
+    this.state.forEach((word, i) => outView.setUint32(i * 4, word, true));
+    return out;
+  }
+
+  private compress() {
+    const view = new DataView(this.block.buffer);
+    const m = Uint32Array.from({ length: 16 }, (_, i) => view.getUint32(i * 4, true));
+    let [a, b, c, d] = this.state;
+    for (let i = 0; i < 64; i++) {
+      let f: number;
+      let g: number;
+      if (i < 16) {
+        f = (b & c) | (~b & d);
+        g = i;
+      } else if (i < 32) {
+        f = (d & b) | (~d & c);
+        g = (5 * i + 1) % 16;
+      } else if (i < 48) {
+        f = b ^ c ^ d;
+        g = (3 * i + 5) % 16;
+      } else {
+        f = c ^ (b | ~d);
+        g = (7 * i) % 16;
+      }
+      const sum = (a + f + K[i] + m[g]) >>> 0;
+      a = d;
+      d = c;
+      c = b;
+      b = (b + ((sum << SHIFTS[i]) | (sum >>> (32 - SHIFTS[i])))) >>> 0;
+    }
+    this.state[0] += a;
+    this.state[1] += b;
+    this.state[2] += c;
+    this.state[3] += d;
+  }
+}
@@ -0,0 +1,229 @@
+// trzsz (https://trzsz.github.io), the client half: answers a remote `trz`
+// (upload) or `tsz` (download). Every message is a `#TYPE:value` line;
+// strings and file data go as base64 of zlib-compressed bytes, and each
+// step is acknowledged with `#SUCC:`. Binary mode (`-b`) and directories
+// (`-d`) are declined.
+
+import type { TransferHandler, TransferPeer } from './index';
+import { concat, deflate, formatBytes, fromBase64, fromUtf8, inflate, toBase64, utf8 } from './bytes';
+import { Md5 } from './md5';
+
+const MAGIC = /::TRZSZ:TRANSFER:([SRD]):(\d+\.\d+\.\d+)/;
+/** The trzsz release whose protocol this speaks. */
+const VERSION = '1.1.5';
+const CHUNK_SIZE = 32 * 1024;
+
+/** Raised once the transfer was cancelled on this side. */
+class Stopped extends Error {}
+/** The remote side reported an error and exits; it needs no answer. */
+class RemoteError extends Error {}
+
+async function encodeBytes(data: Uint8Array): Promise<string> {
+  return toBase64(await deflate(data));
+}
+
+async function decodeBytes(text: string): Promise<Uint8Array> {
+  return inflate(fromBase64(text));
+}
+
+function sameBytes(a: Uint8Array, b: Uint8Array): boolean {
+  return a.length === b.length && a.every((byte, i) => byte === b[i]);
+}
+
+export class TrzszClient implements TransferHandler {
+  readonly done: Promise<Uint8Array>;
+  private buffer = new Uint8Array(0);
+  private over = false;
+  private wake: (() => void) | null = null;
+  private resolveDone!: (rest: Uint8Array) => void;
+
+  constructor(private peer: TransferPeer) {
+    this.done = new Promise((resolve) => (this.resolveDone = resolve));
+    void this.run();
+  }
+
+  receive(data: Uint8Array) {
+    if (this.over) return;
+    this.buffer = concat(this.buffer, data);
+    this.wake?.();
+  }
+
+  async cancel(): Promise<Uint8Array> {
+    this.end(new Uint8Array(0));
+    return utf8(`#fail:${await encodeBytes(utf8('Stopped'))}\n`);
+  }
+
+  private end(rest: Uint8Array) {
+    if (this.over) return;
+    this.over = true;
+    this.wake?.();
+    this.resolveDone(rest);
+  }
+
+  private async run() {
+    let started = false;
+    try {
+      const line = await this.peekLine();
+      const match = MAGIC.exec(line);
+      if (!match) {
+        // Only looked like a transfer: give it all back to the terminal
+        this.end(this.buffer);
+        return;
+      }
+      await this.readLine();
+      started = true;
+      const mode = match[1];
+      if (mode === 'D') {
+        await this.sendAction(false);
+        this.peer.print('Uploading directories (trz -d) is not supported');
+      } else if (mode === 'R') {
+        await this.upload();
+      } else {
+        await this.download();
+      }
+      this.end(this.buffer);
+    } catch (err) {
+      if (err instanceof Stopped) return;
+      const message = err instanceof Error ? err.message : String(err);
+      this.peer.print(`trzsz: ${message}`);
+      if (started && !(err instanceof RemoteError)) {
+        this.peer.send(utf8(`#fail:${await encodeBytes(utf8(message))}\n`));
+      }
+      this.end(new Uint8Array(0));
+    }
+  }
+
+  private async upload() {
+    const files = await this.peer.chooseFiles();
+    if (!files?.length) {
+      await this.sendAction(false);
+      return;
+    }
+    await this.sendAction(true);
+    await this.recvConfig();
+    await this.sendLine('NUM', String(files.length));
+    await this.checkInteger(files.length);
+    const names: string[] = [];
+    for (const file of files) {
+      await this.sendString('NAME', file.name);
+      names.push(await this.recvString('SUCC'));
+      await this.sendLine('SIZE', String(file.size));
+      await this.checkInteger(file.size);
+      const md5 = new Md5();
+      for (let pos = 0; pos < file.size; ) {
+        const chunk = new Uint8Array(await file.slice(pos, pos + CHUNK_SIZE).arrayBuffer());
+        await this.sendLine('DATA', await encodeBytes(chunk));
+        await this.checkInteger(chunk.length);
+        md5.update(chunk);
+        pos += chunk.length;
+        this.peer.status(`Sending ${file.name} ${formatBytes(pos)} / ${formatBytes(file.size)}`);
+      }
+      const digest = md5.digest();
+      await this.sendLine('MD5', await encodeBytes(digest));
+      await this.checkBinary(digest);
+    }
+    await this.sendString('EXIT', `Saved ${names.join(', ')}`);
+    this.peer.print(`Sent ${files.map((f) => f.name).join(', ')}`);
+  }
+
+  private async download() {
+    await this.sendAction(true);
+    const config = await this.recvConfig();
+    if (config.directory) throw new Error('Downloading directories (tsz -d) is not supported');
+    const count = Number(await this.recvCheck('NUM'));
+    await this.sendLine('SUCC', String(count));
+    const names: string[] = [];
+    for (let i = 0; i < count; i++) {
+      const name = (await this.recvString('NAME')).split('/').pop() || 'download';
+      await this.sendString('SUCC', name);
+      const size = Number(await this.recvCheck('SIZE'));
+      await this.sendLine('SUCC', String(size));
+      const md5 = new Md5();
+      const chunks: Uint8Array[] = [];
+      let received = 0;
+      while (received < size) {
+        const data = await decodeBytes(await this.recvCheck('DATA'));
+        chunks.push(data);
+        md5.update(data);
+        received += data.length;
+        await this.sendLine('SUCC', String(data.length));
+        this.peer.status(`Receiving ${name} ${formatBytes(received)} / ${formatBytes(size)}`);
+      }
+      const expected = await decodeBytes(await this.recvCheck('MD5'));
+      const digest = md5.digest();
+      if (!sameBytes(digest, expected)) throw new Error(`${name}: MD5 mismatch`);
+      await this.sendLine('SUCC', await encodeBytes(digest));
+      this.peer.save(name, new Blob(chunks as BlobPart[]));
+      names.push(name);
+    }
+    await this.sendString('EXIT', `Saved ${names.join(', ')} to the browser's downloads`);
+    this.peer.print(`Received ${names.join(', ')}`);
+  }
+
+  private async sendAction(confirm: boolean) {
+    const action = { lang: 'js', confirm, version: VERSION, support_dir: false, binary: false };
+    await this.sendString('ACT', JSON.stringify(action));
+  }
+
+  private async recvConfig(): Promise<{ binary?: boolean; directory?: boolean }> {
+    const config = JSON.parse(await this.recvString('CFG'));
+    if (config.binary) throw new Error('Binary mode (-b) is not supported');
+    return config;
+  }
+
+  private async sendLine(type: string, value: string) {
+    this.peer.send(utf8(`#${type}:${value}\n`));
+    await this.peer.drain();
+  }
+
+  private async sendString(type: string, value: string) {
+    await this.sendLine(type, await encodeBytes(utf8(value)));
+  }
+
+  /** The value of the next line, which must be of `type`. */
+  private async recvCheck(type: string): Promise<string> {
+    const line = await this.readLine();
+    // Terminal noise may come before the message
+    const start = line.lastIndexOf('#');
+    const colon = line.indexOf(':', start);
+    if (start < 0 || colon < 0) throw new Error(`unexpected output: ${line.slice(0, 80)}`);
+    const got = line.slice(start + 1, colon);
+    const value = line.slice(colon + 1);
+    if (got === type) return value;
+    if (got === 'FAIL' || got === 'fail' || got === 'EXIT') {
+      throw new RemoteError(fromUtf8(await decodeBytes(value)));
+    }
+    throw new Error(`expected ${type}, got ${got}`);
+  }
+
+  private async recvString(type: string): Promise<string> {
+    return fromUtf8(await decodeBytes(await this.recvCheck(type)));
+  }
+
+  private async checkInteger(expected: number) {
+    const got = Number(await this.recvCheck('SUCC'));
+    if (got !== expected) throw new Error(`expected ${expected}, got ${got}`);
+  }
+
+  private async checkBinary(expected: Uint8Array) {
+    const got = await decodeBytes(await this.recvCheck('SUCC'));
+    if (!sameBytes(got, expected)) throw new Error('MD5 mismatch');
+  }
+
+  /** The next line without taking it, once it has fully arrived. */
+  private async peekLine(): Promise<string> {
+    for (;;) {
+      if (this.over) throw new Stopped();
+      const newline = this.buffer.indexOf(0x0a);
+      if (newline >= 0) return fromUtf8(this.buffer.subarray(0, newline)).replace(/\r$/, '');
+      await new Promise<void>((resolve) => (this.wake = resolve));
+      this.wake = null;
+    }
+  }
+
+  private async readLine(): Promise<string> {
+    const line = await this.peekLine();
+    this.buffer = this.buffer.subarray(this.buffer.indexOf(0x0a) + 1);
+    return line;
+  }
+}
@@ -0,0 +1,626 @@
+// ZMODEM, enough of it to talk to lrzsz's `rz` and `sz`: binary transfers
+// with CRC-16 or CRC-32, streaming without waiting for acknowledgements, and
+// resuming from the position the receiver asks for after an error.
+
+import type { TransferHandler, TransferPeer } from './index';
+import { concat, formatBytes, fromUtf8, indexOf, utf8 } from './bytes';
+
+const ZPAD = 0x2a;
+const ZDLE = 0x18;
+const ZBIN = 0x41;
+const ZHEX = 0x42;
+const ZBIN32 = 0x43;
+const XON = 0x11;
+
+// Frame types
+const ZRQINIT = 0;
+const ZRINIT = 1;
+const ZSINIT = 2;
+const ZACK = 3;
+const ZFILE = 4;
+const ZSKIP = 5;
+const ZNAK = 6;
+const ZABORT = 7;
+const ZFIN = 8;
+const ZRPOS = 9;
+const ZDATA = 10;
+const ZEOF = 11;
+const ZFERR = 12;
+const ZCAN = 16;
+
+// How a data subpacket ends
+const ZCRCE = 0x68; // end of frame, a header follows
+const ZCRCG = 0x69; // more data follows
+const ZCRCQ = 0x6a; // more data follows, acknowledge it
+const ZCRCW = 0x6b; // end of frame, acknowledge it
+const ZRUB0 = 0x6c;
+const ZRUB1 = 0x6d;
+
+// ZRINIT capabilities, in ZF0
+const CANFDX = 0x01;
+const CANOVIO = 0x02;
+const CANFC32 = 0x20;
+const ESCCTL = 0x40;
+// ZFILE conversion option, in ZF0: binary, as is
+const ZCBIN = 1;
+
+const SUBPACKET_SIZE = 8192;
+/** Longer than any subpacket a sender produces; anything longer is junk. */
+const MAX_SUBPACKET = 64 * 1024;
+/** Five CANs in a row: the other side gave up. */
+const REMOTE_ABORT = new Uint8Array([ZDLE, ZDLE, ZDLE, ZDLE, ZDLE]);
+/** "Over and out", the very last thing the sending side writes. */
+const OVER_AND_OUT = utf8('OO');
+
+const CRC16_TABLE = new Uint16Array(256).map((_, i) => {
+  let crc = i << 8;
+  for (let bit = 0; bit < 8; bit++) crc = crc & 0x8000 ? (crc << 1) ^ 0x1021 : crc << 1;
+  return crc & 0xffff;
+});
+
+const CRC32_TABLE = new Uint32Array(256).map((_, i) => {
+  let crc = i;
+  for (let bit = 0; bit < 8; bit++) crc = crc & 1 ? (crc >>> 1) ^ 0xedb88320 : crc >>> 1;
+  return crc >>> 0;
+});
+
+function crc16(data: Uint8Array): number {
+  let crc = 0;
+  for (const byte of data) crc = ((crc << 8) & 0xffff) ^ CRC16_TABLE[((crc >> 8) ^ byte) & 0xff];
+  return crc;
+}
+
+function crc32(data: Uint8Array): number {
+  let crc = 0xffffffff;
+  for (const byte of data) crc = (crc >>> 8) ^ CRC32_TABLE[(crc ^ byte) & 0xff];
+  return (crc ^ 0xffffffff) >>> 0;
+}
+
+/** The CRC of `data` as its bytes go on the wire. */
+function checksum(data: Uint8Array, use32: boolean): number[] {
+  if (use32) {
+    const crc = crc32(data);
+    return [crc & 0xff, (crc >>> 8) & 0xff, (crc >>> 16) & 0xff, crc >>> 24];
+  }
+  const crc = crc16(data);
+  return [crc >> 8, crc & 0xff];
+}
+
+interface Header {
+  type: number;
+  /** ZP0..ZP3 for a position, or ZF3..ZF0 for flags. */
+  args: Uint8Array;
+}
+
+function position(header: Header): number {
+  const [p0, p1, p2, p3] = header.args;
+  return p0 + p1 * 0x100 + p2 * 0x10000 + p3 * 0x1000000;
+}
+
+function positionArgs(pos: number): number[] {
+  return [pos & 0xff, (pos >>> 8) & 0xff, (pos >>> 16) & 0xff, (pos >>> 24) & 0xff];
+}
+
+function isFlowControl(byte: number): boolean {
+  return byte === 0x11 || byte === 0x13 || byte === 0x91 || byte === 0x93;
+}
+
+function mustEscape(byte: number, escapeControl: boolean): boolean {
+  switch (byte) {
+    case ZDLE:
+    case 0x10:
+    case 0x11:
+    case 0x13:
+    case 0x90:
+    case 0x91:
+    case 0x93:
+    // CR only needs it after '@' (telnet), but escaping it always is allowed
+    case 0x0d:
+    case 0x8d:
+      return true;
+    default:
+      return escapeControl && (byte & 0x60) === 0;
+  }
+}
+
+function escape(out: number[], data: ArrayLike<number>, escapeControl: boolean) {
+  for (let i = 0; i < data.length; i++) {
+    const byte = data[i];
+    if (mustEscape(byte, escapeControl)) out.push(ZDLE, byte ^ 0x40);
+    else out.push(byte);
+  }
+}
+
+function unescapeByte(byte: number): number | undefined {
+  if (byte === ZRUB0) return 0x7f;
+  if (byte === ZRUB1) return 0xff;
+  if ((byte & 0x60) === 0x40) return byte ^ 0x40;
+  return undefined;
+}
+
+function hexHeader(type: number, args: number[]): Uint8Array {
+  const body = [type, ...args];
+  const hex = [...body, ...checksum(Uint8Array.from(body), false)]
+    .map((byte) => byte.toString(16).padStart(2, '0'))
+    .join('');
+  const out = [ZPAD, ZPAD, ZDLE, ZHEX, ...utf8(hex), 0x0d, 0x8a];
+  // lrzsz leaves it off after these two, which end an exchange
+  if (type !== ZFIN && type !== ZACK) out.push(XON);
+  return Uint8Array.from(out);
+}
+
+function binaryHeader(type: number, args: number[], use32: boolean, escapeControl: boolean): Uint8Array {
+  const body = Uint8Array.from([type, ...args]);
+  const out = [ZPAD, ZDLE, use32 ? ZBIN32 : ZBIN];
+  escape(out, body, escapeControl);
+  escape(out, checksum(body, use32), escapeControl);
+  return Uint8Array.from(out);
+}
+
+function subpacket(data: Uint8Array, end: number, use32: boolean, escapeControl: boolean): Uint8Array {
+  const out: number[] = [];
+  escape(out, data, escapeControl);
+  out.push(ZDLE, end);
+  escape(out, checksum(concat(data, Uint8Array.of(end)), use32), escapeControl);
+  if (end === ZCRCW) out.push(XON);
+  return Uint8Array.from(out);
+}
+
+/**
+ * Headers and data subpackets from the other side, parsed as they arrive.
+ * The parse methods return null until the whole thing is buffered, and
+ * undefined when it's corrupt.
+ */
+class Decoder {
+  private buffer = new Uint8Array(0);
+  /** Whether subpackets carry a CRC-32, as set by the last binary header. */
+  private use32 = false;
+
+  push(data: Uint8Array) {
+    this.buffer = concat(this.buffer, data);
+  }
+
+  aborted(): boolean {
+    return indexOf(this.buffer, REMOTE_ABORT) >= 0;
+  }
+
+  /** Everything not parsed yet. */
+  rest(): Uint8Array {
+    return this.buffer;
+  }
+
+  /** The next header, skipping anything before it. */
+  header(): Header | null {
+    for (;;) {
+      const start = this.buffer.indexOf(ZPAD);
+      if (start < 0) {
+        // Keep a few bytes so an abort split across reads is still seen
+        this.buffer = this.buffer.subarray(Math.max(0, this.buffer.length - (REMOTE_ABORT.length - 1)));
+        return null;
+      }
+      let i = start;
+      while (i < this.buffer.length && this.buffer[i] === ZPAD) i++;
+      if (i + 1 >= this.buffer.length) {
+        this.buffer = this.buffer.subarray(start);
+        return null;
+      }
+      if (this.buffer[i] !== ZDLE) {
+        this.buffer = this.buffer.subarray(i);
+        continue;
+      }
+      const kind = this.buffer[i + 1];
+      let parsed: { header: Header; end: number } | null | undefined;
+      if (kind === ZHEX) {
+        parsed = this.hexHeader(i + 2);
+      } else if (kind === ZBIN || kind === ZBIN32) {
+        parsed = this.binaryHeader(i + 2, kind === ZBIN32);
+      } else {
+        this.buffer = this.buffer.subarray(i + 1);
+        continue;
+      }
+      if (parsed === null) {
+        this.buffer = this.buffer.subarray(start);
+        return null;
+      }
+      if (parsed === undefined) {
+        // Corrupt; the other side repeats itself after a timeout
+        this.buffer = this.buffer.subarray(i + 2);
+        continue;
+      }
+      this.buffer = this.buffer.subarray(parsed.end);
+      return parsed.header;
+    }
+  }
+
+  /** The next data subpacket, which must start right away. */
+  subpacket(): { data: Uint8Array; end: number } | null | undefined {
+    const data: number[] = [];
+    let i = 0;
+    for (;;) {
+      if (i >= this.buffer.length) {
+        if (data.length <= MAX_SUBPACKET) return null;
+        this.buffer = this.buffer.subarray(i);
+        return undefined;
+      }
+      const byte = this.buffer[i++];
+      if (isFlowControl(byte)) continue;
+      if (byte !== ZDLE) {
+        data.push(byte);
+        continue;
+      }
+      if (i >= this.buffer.length) return null;
+      const next = this.buffer[i++];
+      if (next >= ZCRCE && next <= ZCRCW) {
+        const body = Uint8Array.from(data);
+        const sum = this.unescape(i, this.use32 ? 4 : 2);
+        if (sum === null) return null;
+        if (sum === undefined) {
+          this.buffer = this.buffer.subarray(i);
+          return undefined;
+        }
+        this.buffer = this.buffer.subarray(sum.end);
+        const expected = checksum(concat(body, Uint8Array.of(next)), this.use32);
+        if (expected.some((byte, k) => sum.bytes[k] !== byte)) return undefined;
+        return { data: body, end: next };
+      }
+      const value = unescapeByte(next);
+      if (value === undefined) {
+        this.buffer = this.buffer.subarray(i);
+        return undefined;
+      }
+      data.push(value);
+    }
+  }
+
+  private hexHeader(at: number): { header: Header; end: number } | null | undefined {
+    if (at + 14 > this.buffer.length) return null;
+    const text = String.fromCharCode(...Array.from(this.buffer.subarray(at, at + 14), (c) => c & 0x7f));
+    if (!/^[0-9a-f]{14}$/i.test(text)) return undefined;
+    const bytes = Uint8Array.from({ length: 7 }, (_, k) => parseInt(text.slice(k * 2, k * 2 + 2), 16));
+    const body = bytes.subarray(0, 5);
+    const crc = crc16(body);
+    if (bytes[5] !== crc >> 8 || bytes[6] !== (crc & 0xff)) return undefined;
+    // CR, LF and XON after it
+    let end = at + 14;
+    while (end < this.buffer.length && end < at + 17 && [0x0d, 0x8d, 0x0a, 0x8a, XON].includes(this.buffer[end])) {
+      end++;
+    }
+    return { header: { type: body[0], args: body.slice(1) }, end };
+  }
+
+  private binaryHeader(at: number, use32: boolean): { header: Header; end: number } | null | undefined {
+    const decoded = this.unescape(at, use32 ? 9 : 7);
+    if (!decoded) return decoded;
+    const body = decoded.bytes.subarray(0, 5);
+    const expected = checksum(body, use32);
+    if (expected.some((byte, k) => decoded.bytes[5 + k] !== byte)) return undefined;
+    this.use32 = use32;
+    return { header: { type: body[0], args: body.slice(1) }, end: decoded.end };
+  }
+
+  /** `count` escaped bytes starting at `at`. */
+  private unescape(at: number, count: number): { bytes: Uint8Array; end: number } | null | undefined {
+    const bytes = new Uint8Array(count);
+    let n = 0;
+    let i = at;
+    while (n < count) {
+      if (i >= this.buffer.length) return null;
+      const byte = this.buffer[i++];
+      if (isFlowControl(byte)) continue;
+      if (byte !== ZDLE) {
+        bytes[n++] = byte;
+        continue;
+      }
+      if (i >= this.buffer.length) return null;
+      const value = unescapeByte(this.buffer[i++]);
+      if (value === undefined) return undefined;
+      bytes[n++] = value;
+    }
+    return { bytes, end: i };
+  }
+}
+
+abstract class ZmodemEndpoint implements TransferHandler {
+  readonly done: Promise<Uint8Array>;
+  protected decoder = new Decoder();
+  protected over = false;
+  private resolveDone!: (rest: Uint8Array) => void;
+
+  constructor(protected peer: TransferPeer) {
+    this.done = new Promise((resolve) => (this.resolveDone = resolve));
+  }
+
+  receive(data: Uint8Array) {
+    if (this.over) return;
+    this.decoder.push(data);
+    if (this.decoder.aborted()) {
+      this.peer.print('ZMODEM transfer aborted by the remote side');
+      this.end(new Uint8Array(0));
+      return;
+    }
+    this.process();
+  }
+
+  async cancel(): Promise<Uint8Array> {
+    this.end(new Uint8Array(0));
+    // The server follows up with the ZMODEM abort sequence
+    return new Uint8Array(0);
+  }
+
+  protected end(rest: Uint8Array) {
+    if (this.over) return;
+    this.over = true;
+    this.resolveDone(rest);
+  }
+
+  protected abstract process(): void;
+}
+
+/** Receives files from a remote `sz`. */
+export class ZmodemReceiver extends ZmodemEndpoint {
+  private state: 'header' | 'sinit' | 'file' | 'data' | 'finishing' = 'header';
+  private file: { name: string; size: number; chunks: Uint8Array[]; received: number } | null = null;
+  private saved: string[] = [];
+
+  protected process() {
+    while (!this.over) {
+      if (this.state === 'finishing') {
+        const rest = this.decoder.rest();
+        if (rest[0] === ZPAD) {
+          // The ZFIN again, if ours was late
+          const header = this.decoder.header();
+          if (!header) return;
+          if (header.type === ZFIN) this.peer.send(hexHeader(ZFIN, [0, 0, 0, 0]));
+          continue;
+        }
+        if (rest.length < OVER_AND_OUT.length) return;
+        this.end(indexOf(rest, OVER_AND_OUT) === 0 ? rest.subarray(OVER_AND_OUT.length) : rest);
+        return;
+      }
+      if (this.state === 'header') {
+        const header = this.decoder.header();
+        if (!header) return;
+        this.onHeader(header);
+        continue;
+      }
+      const packet = this.decoder.subpacket();
+      if (packet === null) return;
+      if (packet === undefined) {
+        this.resync();
+        continue;
+      }
+      this.onSubpacket(packet.data, packet.end);
+    }
+  }
+
+  private onHeader(header: Header) {
+    switch (header.type) {
+      case ZRQINIT:
+        this.peer.send(hexHeader(ZRINIT, [0, 0, 0, CANFDX | CANOVIO | CANFC32]));
+        break;
+      case ZSINIT:
+        this.state = 'sinit';
+        break;
+      case ZFILE:
+        this.state = 'file';
+        break;
+      case ZDATA:
+        if (!this.file) {
+          this.peer.send(hexHeader(ZRINIT, [0, 0, 0, CANFDX | CANOVIO | CANFC32]));
+        } else if (position(header) !== this.file.received) {
+          this.resync();
+        } else {
+          this.state = 'data';
+        }
+        break;
+      case ZEOF:
+        if (this.file && position(header) === this.file.received) {
+          this.peer.save(this.file.name, new Blob(this.file.chunks as BlobPart[]));
+          this.saved.push(this.file.name);
+          this.file = null;
+          this.peer.send(hexHeader(ZRINIT, [0, 0, 0, CANFDX | CANOVIO | CANFC32]));
+        }
+        break;
+      case ZFIN:
+        this.peer.send(hexHeader(ZFIN, [0, 0, 0, 0]));
+        if (this.saved.length) this.peer.print(`Received ${this.saved.join(', ')}`);
+        this.state = 'finishing';
+        break;
+      case ZCAN:
+      case ZABORT:
+      case ZFERR:
+        this.peer.print('ZMODEM transfer aborted by the remote side');
+        this.end(new Uint8Array(0));
+        break;
+    }
+  }
+
+  private onSubpacket(data: Uint8Array, end: number) {
+    switch (this.state) {
+      case 'sinit':
+        this.peer.send(hexHeader(ZACK, [0, 0, 0, 0]));
+        this.state = 'header';
+        break;
+      case 'file': {
+        // "name\0size mtime mode ...\0"
+        const nul = data.indexOf(0);
+        const name = fromUtf8(data.subarray(0, nul < 0 ? data.length : nul)).split('/').pop() || 'download';
+        const size = parseInt(fromUtf8(data.subarray(nul + 1)).split(' ')[0], 10) || 0;
+        this.file = { name, size, chunks: [], received: 0 };
+        this.peer.status(`Receiving ${name}`);
+        this.peer.send(hexHeader(ZRPOS, positionArgs(0)));
+        this.state = 'header';
+        break;
+      }
+      case 'data': {
+        const file = this.file!;
+        file.chunks.push(data);
+        file.received += data.length;
+        this.peer.status(`Receiving ${file.name} ${formatBytes(file.received)} / ${formatBytes(file.size)}`);
+        if (end === ZCRCQ || end === ZCRCW) this.peer.send(hexHeader(ZACK, positionArgs(file.received)));
+        if (end === ZCRCE || end === ZCRCW) this.state = 'header';
+        break;
+      }
+    }
+  }
+
+  /** Ask the sender to go back to what arrived intact. */
+  private resync() {
+    this.state = 'header';
+    if (this.file) this.peer.send(hexHeader(ZRPOS, positionArgs(this.file.received)));
+  }
+}
+
+/** Sends files to a remote `rz`. */
+export class ZmodemSender extends ZmodemEndpoint {
+  private state: 'init' | 'choosing' | 'offered' | 'sending' | 'eof' | 'fin' = 'init';
+  private files: File[] = [];
+  private index = 0;
+  private sent: string[] = [];
+  private use32 = false;
+  private escapeControl = false;
+  private streaming = false;
+  /** Where the receiver wants the data from, once it asked mid-stream. */
+  private restartAt: number | null = null;
+
+  protected process() {
+    while (!this.over) {
+      const header = this.decoder.header();
+      if (!header) return;
+      this.onHeader(header);
+    }
+  }
+
+  private onHeader(header: Header) {
+    switch (header.type) {
+      case ZRINIT:
+        if (this.state === 'init') {
+          const flags = header.args[3];
+          this.use32 = (flags & CANFC32) !== 0;
+          this.escapeControl = (flags & ESCCTL) !== 0;
+          this.state = 'choosing';
+          void this.choose();
+        } else if (this.state === 'offered') {
+          this.offer();
+        } else if (this.state === 'eof') {
+          this.sent.push(this.files[this.index].name);
+          this.next();
+        }
+        break;
+      case ZRPOS:
+        if (this.state === 'offered' || this.state === 'sending' || this.state === 'eof') {
+          this.sendFrom(position(header));
+        }
+        break;
+      case ZSKIP:
+        if (this.state === 'offered') {
+          this.peer.print(`${this.files[this.index].name} skipped by the receiver`);
+          this.next();
+        }
+        break;
+      case ZNAK:
+        if (this.state === 'offered') this.offer();
+        else if (this.state === 'eof') this.sendEof();
+        break;
+      case ZFIN:
+        if (this.state === 'fin') {
+          this.peer.send(OVER_AND_OUT);
+          if (this.sent.length) this.peer.print(`Sent ${this.sent.join(', ')}`);
+          this.end(this.decoder.rest());
+        }
+        break;
+      case ZCAN:
+      case ZABORT:
+      case ZFERR:
+        this.peer.print('ZMODEM transfer aborted by the remote side');
+        this.end(new Uint8Array(0));
+        break;
+    }
+  }
+
+  private async choose() {
+    const files = await this.peer.chooseFiles();
+    if (this.over) return;
+    if (!files?.length) {
+      this.finish();
+      return;
+    }
+    this.files = files;
+    this.index = 0;
+    this.offer();
+  }
+
+  private offer() {
+    const file = this.files[this.index];
+    const left = this.files.slice(this.index);
+    const leftBytes = left.reduce((n, f) => n + f.size, 0);
+    const mtime = Math.floor(file.lastModified / 1000).toString(8);
+    // Mode 0 leaves the permissions to rz's umask
+    const info = utf8(`${file.name}\0${file.size} ${mtime} 0 0 ${left.length} ${leftBytes}\0`);
+    this.state = 'offered';
+    this.peer.status(`Offering ${file.name}`);
+    this.peer.send(
+      concat(
+        binaryHeader(ZFILE, [0, 0, 0, ZCBIN], this.use32, this.escapeControl),
+        subpacket(info, ZCRCW, this.use32, this.escapeControl),
+      ),
+    );
+  }
+
+  private next() {
+    this.index++;
+    if (this.index < this.files.length) this.offer();
+    else this.finish();
+  }
+
+  private finish() {
+    this.state = 'fin';
+    this.peer.send(hexHeader(ZFIN, [0, 0, 0, 0]));
+  }
+
+  private sendFrom(pos: number) {
+    this.state = 'sending';
+    if (this.streaming) {
+      this.restartAt = pos;
+      return;
+    }
+    this.streaming = true;
+    this.stream(pos)
+      .catch((err) => {
+        this.peer.print(`ZMODEM upload failed: ${err instanceof Error ? err.message : err}`);
+        this.end(new Uint8Array(0));
+      })
+      .finally(() => (this.streaming = false));
+  }
+
+  private async stream(start: number) {
+    const file = this.files[this.index];
+    let pos = start;
+    this.peer.send(binaryHeader(ZDATA, positionArgs(pos), this.use32, this.escapeControl));
+    for (;;) {
+      if (this.over) return;
+      if (this.restartAt !== null) {
+        pos = this.restartAt;
+        this.restartAt = null;
+        this.peer.send(binaryHeader(ZDATA, positionArgs(pos), this.use32, this.escapeControl));
+      }
+      const chunk = new Uint8Array(await file.slice(pos, pos + SUBPACKET_SIZE).arrayBuffer());
+      if (this.over) return;
+      if (this.restartAt !== null) continue;
+      pos += chunk.length;
+      const last = pos >= file.size;
+      // An empty file still gets its (empty) end of frame
+      this.peer.send(subpacket(chunk, last ? ZCRCE : ZCRCG, this.use32, this.escapeControl));
+      this.peer.status(`Sending ${file.name} ${formatBytes(pos)} / ${formatBytes(file.size)}`);
+      if (last) break;
+      await this.peer.drain();
+    }
+    this.sendEof();
+  }
+
+  private sendEof() {
+    this.state = 'eof';
+    const file = this.files[this.index];
+    this.peer.send(binaryHeader(ZEOF, positionArgs(file.size), this.use32, this.escapeControl));
+  }
+}
@@ -0,0 +1,157 @@
+use crate::config::AccessRuleConfig;
+use crate::error::AppError;
+use globset::{GlobBuilder, GlobMatcher};
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Tracing target for denied accesses, so they can be routed or filtered
+/// separately (`RUST_LOG=literm_server::audit=info`).
+const AUDIT_TARGET: &str = "literm_server::audit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Access {
+    /// Neither readable nor listed anywhere.
+    Deny,
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Read,
+    Write,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Op::Read => "read",
+            Op::Write => "write",
+        })
+    }
+}
+
+struct Rule {
+    pattern: String,
+    matcher: GlobMatcher,
+    access: Access,
+}
+
+/// The `[[fs.rules]]` from the config, checked for every path the API
+/// touches.
+///
+/// A pattern is matched against the path relative to each sandbox anchor
+/// (`root_dir`, the allowed roots and the mounts) containing it, or against
+/// the absolute path if it starts with `/`. A rule matching a directory
+/// covers everything below it. When several rules match, the last one wins;
+/// paths no rule matches are writable.
+#[derive(Default)]
+pub struct AccessRules {
+    rules: Vec<Rule>,
+    anchors: Vec<PathBuf>,
+}
+
+impl AccessRules {
+    pub fn new(rules: &[AccessRuleConfig], anchors: Vec<PathBuf>) -> std::io::Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let glob = GlobBuilder::new(&rule.pattern)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            format!("invalid fs rule {:?}: {e}", rule.pattern),
+                        )
+                    })?;
+                Ok(Rule {
+                    pattern: rule.pattern.clone(),
+                    matcher: glob.compile_matcher(),
+                    access: rule.access,
+                })
+            })
+            .collect::<std::io::Result<_>>()?;
+        Ok(Self { rules, anchors })
+    }
+
+    /// The effective access to `path`, and the rule that decided it.
+    fn decide(&self, path: &Path) -> (Access, Option<&Rule>) {
+        let mut decision = (Access::Write, None);
+        for rule in &self.rules {
+            if self.matches(rule, path) {
+                decision = (rule.access, Some(rule));
+            }
+        }
+        decision
+    }
+
+    fn matches(&self, rule: &Rule, path: &Path) -> bool {
+        if rule.pattern.starts_with('/') {
+            return path.ancestors().any(|p| rule.matcher.is_match(p));
+        }
+        self.anchors.iter().any(|anchor| {
+            let Ok(rel) = path.strip_prefix(anchor) else {
+                return false;
+            };
+            rel.ancestors()
+                .take_while(|p| !p.as_os_str().is_empty())
+                .any(|p| rule.matcher.is_match(p))
+        })
+    }
+
+    pub fn access(&self, path: &Path) -> Access {
+        if self.rules.is_empty() {
+            return Access::Write;
+        }
+        self.decide(path).0
+    }
+
+    /// Denied paths are left out of listings, searches, archives and events.
+    pub fn is_hidden(&self, path: &Path) -> bool {
+        self.access(path) == Access::Deny
+    }
+
+    /// Fail with 403 if `op` on `path` is not allowed, and record the attempt
+    /// in the audit log.
+    pub fn check(&self, path: &Path, op: Op) -> Result<(), AppError> {
+        if self.rules.is_empty() {
+            return Ok(());
+        }
+        let (access, rule) = self.decide(path);
+        let allowed = match op {
+            Op::Read => access >= Access::Read,
+            Op::Write => access == Access::Write,
+        };
+        if allowed {
+            return Ok(());
+        }
+        let pattern = rule.map(|r| r.pattern.as_str()).unwrap_or_default();
+        tracing::warn!(
+            target: AUDIT_TARGET,
+            "denied {op} of {} by rule {pattern:?}",
+            path.display()
+        );
+        Err(AppError::Forbidden(match access {
+            Access::Deny => format!("access denied by rule {pattern:?}"),
+            _ => format!("read-only by rule {pattern:?}"),
+        }))
+    }
+
+    /// [`check`](Self::check) every entry below `dir` as well, for operations
+    /// that act on a whole tree. Blocking.
+    pub fn check_tree(&self, dir: &Path, op: Op) -> Result<(), AppError> {
+        self.check(dir, op)?;
+        if self.rules.iter().all(|rule| rule.access == Access::Write) {
+            return Ok(());
+        }
+        for entry in WalkDir::new(dir).follow_links(false).min_depth(1) {
+            let entry = entry.map_err(std::io::Error::from)?;
+            self.check(entry.path(), op)?;
+        }
+        Ok(())
+    }
+}
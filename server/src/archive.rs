@@ -0,0 +1,304 @@
+use crate::{error::AppError, fs::FsRoot, globs::PathGlobs};
+use axum::body::Bytes;
+use flate2::{write::GzEncoder, Compression};
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::sync::mpsc;
+use walkdir::WalkDir;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+/// Archive data is handed to the response body in chunks of this size.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::TarGz => "application/gzip",
+        }
+    }
+}
+
+/// Which entries below the archived directory end up in the archive.
+pub struct ArchiveFilter {
+    pub show_hidden: bool,
+    pub globs: PathGlobs,
+    /// Entries its access rules hide are left out.
+    pub fs: FsRoot,
+}
+
+pub enum EntryKind {
+    Dir,
+    File { size: u64 },
+    Symlink { target: PathBuf },
+}
+
+pub struct ArchiveEntry {
+    pub path: PathBuf,
+    /// Name inside the archive, `/`-separated and prefixed with the
+    /// archived directory's name.
+    pub name: String,
+    pub kind: EntryKind,
+    pub mtime: u64,
+    pub mode: u32,
+}
+
+/// Walk `root` and list what goes into the archive, failing with 413 once
+/// the files add up to more than `max_size` bytes. Blocking.
+pub fn collect(
+    root: &Path,
+    filter: &ArchiveFilter,
+    max_size: u64,
+) -> Result<Vec<ArchiveEntry>, AppError> {
+    let base = root
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "archive".to_string());
+
+    let walker = WalkDir::new(root)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            if entry.depth() == 0 {
+                return true;
+            }
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            let rel = entry.path().strip_prefix(root).unwrap_or(entry.path());
+            (filter.show_hidden || !hidden)
+                && !filter.globs.excluded(rel)
+                && !filter.fs.is_hidden(entry.path())
+        });
+
+    let mut entries = Vec::new();
+    let mut total = 0u64;
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                tracing::warn!("archive: skipping unreadable entry: {err}");
+                continue;
+            }
+        };
+        let rel = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                tracing::warn!("archive: skipping {}: {err}", entry.path().display());
+                continue;
+            }
+        };
+
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_dir() {
+            // Directories only matter on their own when nothing is filtered in.
+            if filter.globs.has_include() {
+                continue;
+            }
+            EntryKind::Dir
+        } else if !filter.globs.included(rel) {
+            continue;
+        } else if file_type.is_symlink() {
+            EntryKind::Symlink {
+                target: fs::read_link(entry.path())?,
+            }
+        } else if file_type.is_file() {
+            total += metadata.len();
+            if total > max_size {
+                return Err(AppError::TooLarge(format!(
+                    "directory exceeds the {max_size} byte archive limit"
+                )));
+            }
+            EntryKind::File {
+                size: metadata.len(),
+            }
+        } else {
+            // Sockets, FIFOs and devices cannot be archived meaningfully.
+            continue;
+        };
+
+        let mut name = base.clone();
+        for component in rel.components() {
+            name.push('/');
+            name.push_str(&component.as_os_str().to_string_lossy());
+        }
+        entries.push(ArchiveEntry {
+            path: entry.path().to_path_buf(),
+            name,
+            kind,
+            mtime: metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            mode: metadata.permissions().mode() & 0o7777,
+        });
+    }
+    Ok(entries)
+}
+
+/// Write `entries` as `format` to `out`. Blocking; returns early with an
+/// error once `out` stops accepting data.
+pub fn write_archive<W: Write>(
+    format: ArchiveFormat,
+    entries: &[ArchiveEntry],
+    out: W,
+) -> io::Result<()> {
+    match format {
+        ArchiveFormat::Zip => write_zip(entries, out),
+        ArchiveFormat::TarGz => write_tar_gz(entries, out),
+    }
+}
+
+fn write_zip<W: Write>(entries: &[ArchiveEntry], out: W) -> io::Result<()> {
+    let mut zip = ZipWriter::new_stream(out);
+    for entry in entries {
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(zip_time(entry.mtime))
+            .unix_permissions(entry.mode);
+        match &entry.kind {
+            // `add_directory` announces a data descriptor it never writes in
+            // streaming mode, which unzip rejects as overlapping entries. An
+            // empty stored file with a trailing slash is read as a directory.
+            EntryKind::Dir => zip.start_file(
+                format!("{}/", entry.name),
+                options.compression_method(CompressionMethod::Stored),
+            )?,
+            EntryKind::Symlink { target } => {
+                zip.add_symlink(entry.name.as_str(), target.to_string_lossy(), options)?
+            }
+            EntryKind::File { size } => {
+                let Some(mut file) = open_entry(entry)? else {
+                    continue;
+                };
+                let options = options.large_file(*size >= u32::MAX as u64);
+                zip.start_file(entry.name.as_str(), options)?;
+                io::copy(&mut file, &mut zip)?;
+            }
+        }
+    }
+    zip.finish()?.flush()
+}
+
+fn write_tar_gz<W: Write>(entries: &[ArchiveEntry], out: W) -> io::Result<()> {
+    let mut tar = tar::Builder::new(GzEncoder::new(out, Compression::default()));
+    tar.follow_symlinks(false);
+    for entry in entries {
+        match tar.append_path_with_name(&entry.path, &entry.name) {
+            Ok(()) => {}
+            // Deleted since the walk; the entry was not written yet.
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                tracing::warn!("archive: {} vanished", entry.path.display());
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    tar.into_inner()?.finish()?.flush()
+}
+
+fn open_entry(entry: &ArchiveEntry) -> io::Result<Option<File>> {
+    match File::open(&entry.path) {
+        Ok(file) => Ok(Some(file)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            tracing::warn!("archive: {} vanished", entry.path.display());
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Zip stores local time as DOS date fields; UTC is the closest we have.
+fn zip_time(secs: u64) -> zip::DateTime {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Days since the epoch to a proleptic Gregorian date (Howard Hinnant).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    u16::try_from(year)
+        .ok()
+        .and_then(|year| {
+            zip::DateTime::from_date_and_time(
+                year,
+                month,
+                day,
+                (rem / 3600) as u8,
+                (rem % 3600 / 60) as u8,
+                (rem % 60) as u8,
+            )
+            .ok()
+        })
+        .unwrap_or_default()
+}
+
+/// Bridges the blocking archive writers to a streaming response body.
+/// Writes fail with `BrokenPipe` once the client has gone away.
+pub struct ChannelWriter {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+    buf: Vec<u8>,
+}
+
+impl ChannelWriter {
+    pub fn new(tx: mpsc::Sender<io::Result<Bytes>>) -> Self {
+        Self {
+            tx,
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(
+            &mut self.buf,
+            Vec::with_capacity(CHUNK_SIZE),
+        ));
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.send()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
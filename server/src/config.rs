@@ -1,3 +1,4 @@
+use crate::access::Access;
 use crate::error::AppError;
 use serde::Deserialize;
 use std::{fs, path::Path};
@@ -8,6 +9,13 @@ pub struct ServerConfig {
     pub port: u16,
     pub root_dir: String,
     pub session_timeout_minutes: u64,
+    /// Directories a session may switch its browsing root into, besides
+    /// `root_dir` itself.
+    #[serde(default)]
+    pub allowed_roots: Vec<String>,
+    /// Where derived data such as line indexes is kept.
+    #[serde(default = "default_cache_dir")]
+    pub cache_dir: String,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -19,6 +27,209 @@ pub struct AuthConfig {
 pub struct FeatureConfig {
     pub enable_hdf5: bool,
     pub enable_watch: bool,
+    #[serde(default = "default_true")]
+    pub enable_tmux: bool,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct TerminalConfig {
+    #[serde(default)]
+    pub profiles: Vec<TerminalProfile>,
+}
+
+/// A named terminal backend selectable with `/ws/term?profile=<name>`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TerminalProfile {
+    pub name: String,
+    #[serde(flatten)]
+    pub backend: TerminalBackend,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TerminalBackend {
+    Local { shell: Option<String> },
+    Ssh(SshProfile),
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SshProfile {
+    pub host: String,
+    #[serde(default = "default_ssh_port")]
+    pub port: u16,
+    pub user: String,
+    pub key_path: String,
+    pub key_passphrase: Option<String>,
+    #[serde(default)]
+    pub known_hosts: KnownHostsPolicy,
+    /// Defaults to `~/.ssh/known_hosts` of the server user.
+    pub known_hosts_path: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KnownHostsPolicy {
+    /// Only connect to hosts already present in known_hosts.
+    #[default]
+    Strict,
+    /// Record unknown hosts on first use, reject changed keys.
+    AcceptNew,
+    /// Skip host key verification entirely.
+    AcceptAny,
+}
+
+/// Reverse proxy to dev servers on this host, served under `/proxy/{port}`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ProxyConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_proxy_min_port")]
+    pub min_port: u16,
+    #[serde(default = "default_proxy_max_port")]
+    pub max_port: u16,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_port: default_proxy_min_port(),
+            max_port: default_proxy_max_port(),
+        }
+    }
+}
+
+impl ProxyConfig {
+    pub fn allows(&self, port: u16) -> bool {
+        self.enabled && (self.min_port..=self.max_port).contains(&port)
+    }
+}
+
+/// Limits for `/api/fs/upload` and the chunked `/api/fs/uploads` API, in bytes.
+#[derive(Debug, Deserialize, Clone)]
+pub struct UploadConfig {
+    #[serde(default = "default_upload_max_file_size")]
+    pub max_file_size: u64,
+    /// Unfinished uploads in total: declared sizes of chunked ones plus what
+    /// multipart ones have received so far.
+    #[serde(default = "default_upload_max_pending_size")]
+    pub max_pending_size: u64,
+    /// Unfinished uploads idle for longer than this are discarded.
+    #[serde(default = "default_upload_expire_minutes")]
+    pub expire_minutes: u64,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            max_file_size: default_upload_max_file_size(),
+            max_pending_size: default_upload_max_pending_size(),
+            expire_minutes: default_upload_expire_minutes(),
+        }
+    }
+}
+
+/// Limits for directory downloads through `/api/fs/archive`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ArchiveConfig {
+    /// Total size of the files going into one archive, in bytes.
+    #[serde(default = "default_archive_max_size")]
+    pub max_size: u64,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            max_size: default_archive_max_size(),
+        }
+    }
+}
+
+/// Background index of file paths behind `/api/fs/find`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct IndexConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Files beyond this count are left out of the index.
+    #[serde(default = "default_index_max_files")]
+    pub max_files: usize,
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_files: default_index_max_files(),
+        }
+    }
+}
+
+/// Limits for text read through `/api/fs/content`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ContentConfig {
+    /// Most bytes of text returned by one request; larger files have to be
+    /// read in line or byte ranges.
+    #[serde(default = "default_content_max_size")]
+    pub max_size: u64,
+}
+
+impl Default for ContentConfig {
+    fn default() -> Self {
+        Self {
+            max_size: default_content_max_size(),
+        }
+    }
+}
+
+/// Rendering and caching of `/api/fs/thumbnail`, and limits shared with
+/// `/api/fs/convert`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ThumbnailConfig {
+    /// Bytes of thumbnails kept in `<cache_dir>/thumbnails`; the least
+    /// recently used are removed beyond that.
+    #[serde(default = "default_thumbnail_max_cache_size")]
+    pub max_cache_size: u64,
+    /// Images decoded at the same time, for thumbnails and conversions;
+    /// further requests wait.
+    #[serde(default = "default_thumbnail_max_concurrent")]
+    pub max_concurrent: usize,
+    /// Larger source files get no thumbnail and are not converted.
+    #[serde(default = "default_thumbnail_max_source_size")]
+    pub max_source_size: u64,
+}
+
+impl Default for ThumbnailConfig {
+    fn default() -> Self {
+        Self {
+            max_cache_size: default_thumbnail_max_cache_size(),
+            max_concurrent: default_thumbnail_max_concurrent(),
+            max_source_size: default_thumbnail_max_source_size(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct FsConfig {
+    /// Named directories outside the browsing root, addressed as `name:/path`.
+    #[serde(default)]
+    pub mounts: Vec<MountConfig>,
+    /// Access control by glob; see [`crate::access::AccessRules`].
+    #[serde(default)]
+    pub rules: Vec<AccessRuleConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MountConfig {
+    pub name: String,
+    pub path: String,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AccessRuleConfig {
+    pub pattern: String,
+    pub access: Access,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -26,6 +237,78 @@ pub struct Config {
     pub server: ServerConfig,
     pub auth: AuthConfig,
     pub features: FeatureConfig,
+    #[serde(default)]
+    pub terminal: TerminalConfig,
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    #[serde(default)]
+    pub upload: UploadConfig,
+    #[serde(default)]
+    pub archive: ArchiveConfig,
+    #[serde(default)]
+    pub index: IndexConfig,
+    #[serde(default)]
+    pub fs: FsConfig,
+    #[serde(default)]
+    pub content: ContentConfig,
+    #[serde(default)]
+    pub thumbnails: ThumbnailConfig,
+}
+
+fn default_cache_dir() -> String {
+    "cache".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_ssh_port() -> u16 {
+    22
+}
+
+fn default_proxy_min_port() -> u16 {
+    1024
+}
+
+fn default_proxy_max_port() -> u16 {
+    65535
+}
+
+fn default_upload_max_file_size() -> u64 {
+    16 * 1024 * 1024 * 1024
+}
+
+fn default_upload_max_pending_size() -> u64 {
+    64 * 1024 * 1024 * 1024
+}
+
+fn default_upload_expire_minutes() -> u64 {
+    24 * 60
+}
+
+fn default_archive_max_size() -> u64 {
+    16 * 1024 * 1024 * 1024
+}
+
+fn default_content_max_size() -> u64 {
+    8 * 1024 * 1024
+}
+
+fn default_thumbnail_max_cache_size() -> u64 {
+    512 * 1024 * 1024
+}
+
+fn default_thumbnail_max_concurrent() -> usize {
+    4
+}
+
+fn default_thumbnail_max_source_size() -> u64 {
+    512 * 1024 * 1024
+}
+
+fn default_index_max_files() -> usize {
+    1_000_000
 }
 
 impl Config {
@@ -34,4 +317,8 @@ impl Config {
         let cfg: Config = toml::from_str(&content)?;
         Ok(cfg)
     }
+
+    pub fn terminal_profile(&self, name: &str) -> Option<&TerminalProfile> {
+        self.terminal.profiles.iter().find(|p| p.name == name)
+    }
 }
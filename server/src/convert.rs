@@ -0,0 +1,451 @@
+use crate::error::AppError;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::error::{DecodingError, ImageFormatHint, LimitError, LimitErrorKind};
+use image::{
+    DynamicImage, ExtendedColorType, ImageDecoder, ImageEncoder, ImageFormat, ImageReader,
+};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+use tiff::decoder::{Decoder as TiffDecoder, DecodingResult, Limits as TiffLimits};
+
+/// Most samples (pixels × channels) decoded for one conversion; they are
+/// held as `f32`.
+const MAX_SAMPLES: u64 = 128 * 1024 * 1024;
+/// Samples looked at to find percentiles; larger images are subsampled.
+const PERCENTILE_SAMPLES: usize = 1 << 20;
+
+/// How sample values are mapped to the 0–255 of the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stretch {
+    /// The full range of the sample type: 0–65535 for 16-bit data, 0.0–1.0
+    /// for floats.
+    #[default]
+    None,
+    /// The smallest to the largest value in the image.
+    MinMax,
+    /// The `low` to the `high` percentile, ignoring outliers.
+    Percentile,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleFormat {
+    Uint,
+    Int,
+    Float,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorKind {
+    Gray,
+    GrayAlpha,
+    Rgb,
+    Rgba,
+    Cmyk,
+    /// Palette-based or otherwise unusual.
+    Other,
+}
+
+/// What an image looks like before conversion.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageMeta {
+    /// `png`, `tiff`, `exr`, `pnm`, ...
+    pub format: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub color: ColorKind,
+    pub channels: u8,
+    /// Bits per sample.
+    pub bit_depth: u8,
+    pub sample_format: SampleFormat,
+    pub has_alpha: bool,
+    /// Pages of a multi-page TIFF, 1 for everything else.
+    pub pages: usize,
+}
+
+/// Decoded pixels, channel-interleaved. Integer samples are scaled to
+/// 0.0–1.0 by the largest value their bit depth can hold; float samples
+/// are kept as they are.
+struct Raster {
+    width: u32,
+    height: u32,
+    channels: u8,
+    has_alpha: bool,
+    samples: Vec<f32>,
+}
+
+/// The result of [`to_png`].
+pub struct Converted {
+    pub png: Vec<u8>,
+    pub meta: ImageMeta,
+    /// The sample values mapped to 0 and 255, with integer samples scaled
+    /// to 0.0–1.0.
+    pub range: (f32, f32),
+}
+
+/// Describe the image at `path` from its header, without decoding pixels.
+/// Blocking.
+pub fn info(path: &Path) -> Result<ImageMeta, AppError> {
+    let reader = ImageReader::open(path)?.with_guessed_format()?;
+    if reader.format() == Some(ImageFormat::Tiff) {
+        let mut decoder = tiff_decoder(path)?;
+        return tiff_meta(&mut decoder);
+    }
+    let format = reader.format();
+    let decoder = reader.into_decoder()?;
+    let (width, height) = decoder.dimensions();
+    Ok(meta_of(
+        format,
+        decoder.original_color_type(),
+        width,
+        height,
+    ))
+}
+
+/// Decode page `page` (0-based) of the image at `path` and encode it as an
+/// 8-bit PNG, stretching contrast as asked and scaling it down to fit in
+/// `max_edge`×`max_edge` if given. Blocking.
+pub fn to_png(
+    path: &Path,
+    page: usize,
+    stretch: Stretch,
+    percentiles: (f32, f32),
+    max_edge: Option<u32>,
+) -> Result<Converted, AppError> {
+    let (raster, meta) = decode(path, page)?;
+    let range = match stretch {
+        Stretch::None => (0.0, 1.0),
+        Stretch::MinMax => raster.value_range(),
+        Stretch::Percentile => raster.percentile_range(percentiles.0, percentiles.1),
+    };
+    let mut image = raster.quantize(range)?;
+    if let Some(edge) = max_edge {
+        if image.width() > edge || image.height() > edge {
+            image = image.resize(edge, edge, image::imageops::FilterType::Triangle);
+        }
+    }
+
+    let mut png = Vec::new();
+    PngEncoder::new_with_quality(&mut png, CompressionType::Fast, FilterType::Adaptive)
+        .write_image(
+            image.as_bytes(),
+            image.width(),
+            image.height(),
+            image.color().into(),
+        )?;
+    Ok(Converted { png, meta, range })
+}
+
+fn decode(path: &Path, page: usize) -> Result<(Raster, ImageMeta), AppError> {
+    let reader = ImageReader::open(path)?.with_guessed_format()?;
+    if reader.format() == Some(ImageFormat::Tiff) {
+        return decode_tiff(path, page);
+    }
+    if page > 0 {
+        return Err(AppError::BadRequest(format!(
+            "page {page} requested, the image has 1"
+        )));
+    }
+    let format = reader.format();
+    let decoder = reader.into_decoder()?;
+    let (width, height) = decoder.dimensions();
+    let meta = meta_of(format, decoder.original_color_type(), width, height);
+    check_samples(&meta)?;
+    let image = DynamicImage::from_decoder(decoder)?;
+
+    let color = image.color();
+    let samples = match (color.has_color(), color.has_alpha()) {
+        (false, false) => image.to_luma32f().into_raw(),
+        (false, true) => image.to_luma_alpha32f().into_raw(),
+        (true, false) => image.to_rgb32f().into_raw(),
+        (true, true) => image.to_rgba32f().into_raw(),
+    };
+    let raster = Raster {
+        width,
+        height,
+        channels: color.channel_count(),
+        has_alpha: color.has_alpha(),
+        samples,
+    };
+    Ok((raster, meta))
+}
+
+fn meta_of(
+    format: Option<ImageFormat>,
+    color: ExtendedColorType,
+    width: u32,
+    height: u32,
+) -> ImageMeta {
+    let channels = color.channel_count();
+    let bit_depth = (color.bits_per_pixel() / u16::from(channels.max(1))) as u8;
+    let kind = match color {
+        ExtendedColorType::Cmyk8 | ExtendedColorType::Cmyk16 => ColorKind::Cmyk,
+        ExtendedColorType::Unknown(_) | ExtendedColorType::A8 => ColorKind::Other,
+        _ => match channels {
+            1 => ColorKind::Gray,
+            2 => ColorKind::GrayAlpha,
+            3 => ColorKind::Rgb,
+            _ => ColorKind::Rgba,
+        },
+    };
+    let has_alpha = matches!(color, ExtendedColorType::A8)
+        || matches!(kind, ColorKind::GrayAlpha | ColorKind::Rgba);
+    let sample_format = match color {
+        ExtendedColorType::Rgb32F | ExtendedColorType::Rgba32F => SampleFormat::Float,
+        _ => SampleFormat::Uint,
+    };
+    ImageMeta {
+        format: format.map_or("unknown", format_name),
+        width,
+        height,
+        color: kind,
+        channels,
+        bit_depth,
+        sample_format,
+        has_alpha,
+        pages: 1,
+    }
+}
+
+fn format_name(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Png => "png",
+        ImageFormat::Jpeg => "jpeg",
+        ImageFormat::Gif => "gif",
+        ImageFormat::WebP => "webp",
+        ImageFormat::Pnm => "pnm",
+        ImageFormat::Tiff => "tiff",
+        ImageFormat::Bmp => "bmp",
+        ImageFormat::OpenExr => "exr",
+        other => other.extensions_str().first().copied().unwrap_or("unknown"),
+    }
+}
+
+/// TIFFs are read with the `tiff` crate directly, which can seek to any
+/// page and hands out samples of any bit depth.
+fn decode_tiff(path: &Path, page: usize) -> Result<(Raster, ImageMeta), AppError> {
+    let mut decoder = tiff_decoder(path)?;
+    let meta = tiff_meta(&mut decoder)?;
+    if page >= meta.pages {
+        return Err(AppError::BadRequest(format!(
+            "page {page} requested, the image has {}",
+            meta.pages
+        )));
+    }
+    decoder.seek_to_image(page).map_err(tiff_error)?;
+    // Pages may differ in size and depth.
+    let page_meta = tiff_page_meta(&mut decoder, meta.pages)?;
+    check_samples(&page_meta)?;
+
+    let bits = page_meta.bit_depth;
+    let samples = match decoder.read_image().map_err(tiff_error)? {
+        DecodingResult::U8(v) => scale_unsigned(&v, bits),
+        DecodingResult::U16(v) => scale_unsigned(&v, bits),
+        DecodingResult::U32(v) => scale_unsigned(&v, bits),
+        DecodingResult::U64(v) => v
+            .iter()
+            .map(|&x| (x as f64 / u64::MAX as f64) as f32)
+            .collect(),
+        DecodingResult::I8(v) => scale_signed(&v, i8::MIN.into(), i8::MAX.into()),
+        DecodingResult::I16(v) => scale_signed(&v, i16::MIN.into(), i16::MAX.into()),
+        DecodingResult::I32(v) => scale_signed(&v, i32::MIN.into(), i32::MAX.into()),
+        DecodingResult::I64(v) => v
+            .iter()
+            .map(|&x| ((x as f64 - i64::MIN as f64) / (i64::MAX as f64 - i64::MIN as f64)) as f32)
+            .collect(),
+        DecodingResult::F16(v) => v.iter().map(|x| x.to_f32()).collect(),
+        DecodingResult::F32(v) => v,
+        DecodingResult::F64(v) => v.iter().map(|&x| x as f32).collect(),
+    };
+    let raster = Raster {
+        width: page_meta.width,
+        height: page_meta.height,
+        channels: page_meta.channels,
+        has_alpha: page_meta.has_alpha,
+        samples,
+    };
+    Ok((raster, page_meta))
+}
+
+fn tiff_decoder(path: &Path) -> Result<TiffDecoder<BufReader<File>>, AppError> {
+    let mut limits = TiffLimits::default();
+    limits.decoding_buffer_size = (MAX_SAMPLES * 2) as usize;
+    let decoder = TiffDecoder::new(BufReader::new(File::open(path)?)).map_err(tiff_error)?;
+    Ok(decoder.with_limits(limits))
+}
+
+/// Metadata of the first page, with the pages counted. Leaves `decoder` at
+/// the last page.
+fn tiff_meta<R: Read + Seek>(decoder: &mut TiffDecoder<R>) -> Result<ImageMeta, AppError> {
+    let first = tiff_page_meta(decoder, 1)?;
+    let mut pages = 1;
+    while decoder.more_images() {
+        decoder.next_image().map_err(tiff_error)?;
+        pages += 1;
+    }
+    Ok(ImageMeta { pages, ..first })
+}
+
+fn tiff_page_meta<R: Read + Seek>(
+    decoder: &mut TiffDecoder<R>,
+    pages: usize,
+) -> Result<ImageMeta, AppError> {
+    use tiff::tags::{SampleFormat as TiffSampleFormat, Tag};
+    use tiff::ColorType;
+
+    let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+    let color = decoder.colortype().map_err(tiff_error)?;
+    let (kind, channels, has_alpha) = match color {
+        ColorType::Gray(_) => (ColorKind::Gray, 1, false),
+        ColorType::GrayA(_) => (ColorKind::GrayAlpha, 2, true),
+        ColorType::RGB(_) => (ColorKind::Rgb, 3, false),
+        ColorType::RGBA(_) => (ColorKind::Rgba, 4, true),
+        other => {
+            return Err(image::ImageError::Unsupported(
+                image::error::UnsupportedError::from_format_and_kind(
+                    ImageFormatHint::Exact(ImageFormat::Tiff),
+                    image::error::UnsupportedErrorKind::GenericFeature(format!("{other:?} TIFF")),
+                ),
+            )
+            .into())
+        }
+    };
+    let sample_format = match decoder
+        .find_tag_unsigned::<u16>(Tag::SampleFormat)
+        .map_err(tiff_error)?
+        .map(TiffSampleFormat::from_u16_exhaustive)
+    {
+        Some(TiffSampleFormat::Int) => SampleFormat::Int,
+        Some(TiffSampleFormat::IEEEFP) => SampleFormat::Float,
+        _ => SampleFormat::Uint,
+    };
+    Ok(ImageMeta {
+        format: "tiff",
+        width,
+        height,
+        color: kind,
+        channels,
+        bit_depth: color.bit_depth(),
+        sample_format,
+        has_alpha,
+        pages,
+    })
+}
+
+fn tiff_error(err: tiff::TiffError) -> AppError {
+    let err = match err {
+        tiff::TiffError::IoError(err) => image::ImageError::IoError(err),
+        tiff::TiffError::LimitsExceeded => {
+            image::ImageError::Limits(LimitError::from_kind(LimitErrorKind::InsufficientMemory))
+        }
+        err => image::ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::Exact(ImageFormat::Tiff),
+            err,
+        )),
+    };
+    err.into()
+}
+
+fn check_samples(meta: &ImageMeta) -> Result<(), AppError> {
+    let samples = u64::from(meta.width) * u64::from(meta.height) * u64::from(meta.channels);
+    if samples > MAX_SAMPLES {
+        return Err(AppError::TooLarge(format!(
+            "{}×{} pixels with {} channels is too large to convert",
+            meta.width, meta.height, meta.channels
+        )));
+    }
+    Ok(())
+}
+
+/// Unsigned samples of `bits` significant bits to 0.0–1.0.
+fn scale_unsigned<T: Copy + Into<f64>>(values: &[T], bits: u8) -> Vec<f32> {
+    let max = ((1u64 << bits.min(63)) - 1) as f64;
+    values.iter().map(|&v| (v.into() / max) as f32).collect()
+}
+
+fn scale_signed<T: Copy + Into<f64>>(values: &[T], min: f64, max: f64) -> Vec<f32> {
+    values
+        .iter()
+        .map(|&v| ((v.into() - min) / (max - min)) as f32)
+        .collect()
+}
+
+impl Raster {
+    fn color_channels(&self) -> usize {
+        usize::from(self.channels) - usize::from(self.has_alpha)
+    }
+
+    /// The finite color samples (not alpha), every `step`th pixel.
+    fn color_samples(&self, step: usize) -> impl Iterator<Item = f32> + '_ {
+        let color = self.color_channels();
+        self.samples
+            .chunks_exact(usize::from(self.channels))
+            .step_by(step.max(1))
+            .flat_map(move |pixel| pixel[..color].iter().copied())
+            .filter(|v| v.is_finite())
+    }
+
+    fn value_range(&self) -> (f32, f32) {
+        self.color_samples(1)
+            .fold(None, |range, v| match range {
+                None => Some((v, v)),
+                Some((lo, hi)) => Some((f32::min(lo, v), f32::max(hi, v))),
+            })
+            .unwrap_or((0.0, 1.0))
+    }
+
+    /// The values at the `low` and `high` percentiles (0–100).
+    fn percentile_range(&self, low: f32, high: f32) -> (f32, f32) {
+        let pixels = self.samples.len() / usize::from(self.channels).max(1);
+        let step = pixels.div_ceil(PERCENTILE_SAMPLES / self.color_channels().max(1));
+        let mut values: Vec<f32> = self.color_samples(step).collect();
+        if values.is_empty() {
+            return (0.0, 1.0);
+        }
+        let mut at = |percent: f32| {
+            let rank = (percent.clamp(0.0, 100.0) / 100.0 * (values.len() - 1) as f32).round();
+            *values
+                .select_nth_unstable_by(rank as usize, f32::total_cmp)
+                .1
+        };
+        let lo = at(low.min(high));
+        let hi = at(high.max(low));
+        (lo, hi)
+    }
+
+    /// 8 bits per sample, `range` spread over 0–255. Alpha is scaled from
+    /// 0.0–1.0 regardless.
+    fn quantize(self, (lo, hi): (f32, f32)) -> Result<DynamicImage, AppError> {
+        let scale = if hi > lo { 255.0 / (hi - lo) } else { 0.0 };
+        let channels = usize::from(self.channels);
+        let alpha = self.has_alpha.then_some(channels - 1);
+        let bytes: Vec<u8> = self
+            .samples
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| {
+                let v = if Some(i % channels) == alpha {
+                    v * 255.0
+                } else {
+                    (v - lo) * scale
+                };
+                // NaN saturates to 0.
+                v.round().clamp(0.0, 255.0) as u8
+            })
+            .collect();
+        let (w, h) = (self.width, self.height);
+        let image = match (channels, self.has_alpha) {
+            (1, _) => image::GrayImage::from_raw(w, h, bytes).map(DynamicImage::ImageLuma8),
+            (2, _) => image::GrayAlphaImage::from_raw(w, h, bytes).map(DynamicImage::ImageLumaA8),
+            (3, _) => image::RgbImage::from_raw(w, h, bytes).map(DynamicImage::ImageRgb8),
+            _ => image::RgbaImage::from_raw(w, h, bytes).map(DynamicImage::ImageRgba8),
+        };
+        image.ok_or_else(|| {
+            AppError::Internal("decoded samples don't match the image dimensions".into())
+        })
+    }
+}
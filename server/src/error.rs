@@ -23,9 +23,27 @@ pub enum AppError {
     #[error("bad request: {0}")]
     BadRequest(String),
 
+    #[error("not found: {0}")]
+    NotFound(String),
+
+    #[error("conflict: {0}")]
+    Conflict(String),
+
+    #[error("too large: {0}")]
+    TooLarge(String),
+
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("bad gateway: {0}")]
+    BadGateway(String),
+
     #[error("internal error: {0}")]
     Internal(String),
 
+    #[error("image error: {0}")]
+    Image(#[from] image::ImageError),
+
     #[error("hdf5 error: {0}")]
     Hdf5(#[from] hdf5::Error),
 }
@@ -40,11 +58,36 @@ impl IntoResponse for AppError {
         let status = match self {
             AppError::Unauthorized => StatusCode::UNAUTHORIZED,
             AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
-            AppError::Config(_)
-            | AppError::Internal(_)
-            | AppError::Io(_)
-            | AppError::Toml(_)
-            | AppError::Hdf5(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::BadGateway(_) => StatusCode::BAD_GATEWAY,
+            AppError::Image(ref err) => match err {
+                image::ImageError::Limits(_) => StatusCode::PAYLOAD_TOO_LARGE,
+                image::ImageError::Parameter(_) => StatusCode::BAD_REQUEST,
+                image::ImageError::IoError(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    StatusCode::NOT_FOUND
+                }
+                image::ImageError::IoError(err)
+                    if err.kind() != std::io::ErrorKind::UnexpectedEof =>
+                {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+                // Not an image, a format without a decoder, or corrupt.
+                _ => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            },
+            AppError::Io(ref err) => match err.kind() {
+                std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+                std::io::ErrorKind::AlreadyExists | std::io::ErrorKind::DirectoryNotEmpty => {
+                    StatusCode::CONFLICT
+                }
+                std::io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            AppError::Config(_) | AppError::Internal(_) | AppError::Toml(_) | AppError::Hdf5(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
         };
 
         let body = Json(ErrorBody {
@@ -0,0 +1,76 @@
+use serde::Serialize;
+use std::path::PathBuf;
+use tokio::sync::broadcast;
+
+const EVENT_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Renamed,
+    Deleted,
+}
+
+/// Something the server did that connected clients may want to know about.
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    /// A filesystem entry changed through the API. Paths are absolute.
+    FsChange {
+        kind: ChangeKind,
+        path: PathBuf,
+        from: Option<PathBuf>,
+    },
+    /// Bytes received so far for an upload; `path` is relative to the root.
+    UploadProgress {
+        id: String,
+        path: String,
+        received: u64,
+        size: Option<u64>,
+    },
+    /// An upload was stored (`error` is `None`) or abandoned.
+    UploadFinished {
+        id: String,
+        path: String,
+        error: Option<String>,
+    },
+    /// Bytes of a large file scanned so far for `/api/fs/lines`. The path
+    /// is absolute.
+    LineIndexProgress {
+        path: PathBuf,
+        indexed: u64,
+        size: u64,
+        done: bool,
+    },
+}
+
+/// In-process fan-out of [`ServerEvent`]s to every `/ws/system` connection.
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<ServerEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(EVENT_CAPACITY);
+        Self { tx }
+    }
+
+    pub fn publish(&self, event: ServerEvent) {
+        // No receivers just means nobody is connected.
+        let _ = self.tx.send(event);
+    }
+
+    pub fn fs_change(&self, kind: ChangeKind, path: PathBuf) {
+        self.publish(ServerEvent::FsChange {
+            kind,
+            path,
+            from: None,
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerEvent> {
+        self.tx.subscribe()
+    }
+}
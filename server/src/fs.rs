@@ -1,50 +1,419 @@
+use crate::access::{AccessRules, Op};
+use crate::config::{FsConfig, ServerConfig};
 use crate::error::AppError;
-use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 
+/// The server-wide sandbox: the roots a client may browse, the configured
+/// mounts and access rules, fixed at startup.
 pub struct FsService {
-    root: Mutex<PathBuf>,
+    default_root: PathBuf,
+    allowed_roots: Vec<PathBuf>,
+    mounts: Arc<[Mount]>,
+    rules: Arc<AccessRules>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Mount {
+    pub name: String,
+    #[serde(skip)]
+    pub path: PathBuf,
+    pub read_only: bool,
 }
 
 impl FsService {
-    pub fn new(root: impl AsRef<Path>) -> std::io::Result<Self> {
-        let canonical_root = std::fs::canonicalize(root)?;
+    /// `root_dir` is where new sessions start; it is always allowed, as is
+    /// everything below each of `allowed_roots`.
+    pub fn new(server: &ServerConfig, config: &FsConfig) -> std::io::Result<Self> {
+        let default_root = std::fs::canonicalize(&server.root_dir)?;
+        let mut roots = vec![default_root.clone()];
+        for allowed in &server.allowed_roots {
+            let canonical = std::fs::canonicalize(allowed).map_err(|err| {
+                std::io::Error::new(err.kind(), format!("allowed root {allowed}: {err}"))
+            })?;
+            if !roots.contains(&canonical) {
+                roots.push(canonical);
+            }
+        }
+
+        let mut resolved: Vec<Mount> = Vec::new();
+        for mount in &config.mounts {
+            let valid_name = !mount.name.is_empty()
+                && mount
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+            if !valid_name || resolved.iter().any(|m| m.name == mount.name) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("invalid or duplicate mount name {:?}", mount.name),
+                ));
+            }
+            let path = std::fs::canonicalize(&mount.path).map_err(|err| {
+                std::io::Error::new(err.kind(), format!("mount {}: {err}", mount.name))
+            })?;
+            resolved.push(Mount {
+                name: mount.name.clone(),
+                path,
+                read_only: mount.read_only,
+            });
+        }
+
+        let anchors = roots
+            .iter()
+            .chain(resolved.iter().map(|m| &m.path))
+            .cloned()
+            .collect();
+        let rules = AccessRules::new(&config.rules, anchors)?;
+
         Ok(Self {
-            root: Mutex::new(canonical_root),
+            default_root,
+            allowed_roots: roots,
+            mounts: resolved.into(),
+            rules: Arc::new(rules),
         })
     }
 
+    pub fn default_root(&self) -> FsRoot {
+        self.view(self.default_root.clone())
+    }
+
+    /// A view rooted at `root`, which must be canonical and already checked
+    /// by [`FsService::check_root`].
+    pub fn view(&self, root: PathBuf) -> FsRoot {
+        FsRoot {
+            root,
+            mounts: self.mounts.clone(),
+            rules: self.rules.clone(),
+        }
+    }
+
+    pub fn rules(&self) -> &AccessRules {
+        &self.rules
+    }
+
+    pub fn allowed_roots(&self) -> &[PathBuf] {
+        &self.allowed_roots
+    }
+
+    /// Check that `requested` lies within one of the allowed roots and return
+    /// its canonical form.
+    pub fn check_root(&self, requested: impl AsRef<Path>) -> Result<PathBuf, AppError> {
+        let canonical = std::fs::canonicalize(requested)?;
+        if !canonical.is_dir() {
+            return Err(AppError::BadRequest("root must be a directory".into()));
+        }
+        if !self
+            .allowed_roots
+            .iter()
+            .any(|allowed| canonical.starts_with(allowed))
+        {
+            return Err(AppError::Forbidden(
+                "root is outside the allowed roots".into(),
+            ));
+        }
+        self.rules.check(&canonical, Op::Read)?;
+        Ok(canonical)
+    }
+}
+
+/// One client's view of the filesystem: paths in requests and responses are
+/// relative to `root`, and may not leave it. A path of the form `name:/rel`
+/// refers to `rel` inside the mount `name` instead.
+///
+/// Every resolved path is checked against the access rules: resolving needs
+/// read access, and [`FsRoot::check_writable`] (part of
+/// [`FsRoot::resolve_new_path`]) write access.
+#[derive(Clone)]
+pub struct FsRoot {
+    root: PathBuf,
+    mounts: Arc<[Mount]>,
+    rules: Arc<AccessRules>,
+}
+
+impl FsRoot {
     pub fn root(&self) -> PathBuf {
-        self.root.lock().unwrap().clone()
+        self.root.clone()
+    }
+
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
     }
 
-    pub fn set_root(&self, new_root: impl AsRef<Path>) -> Result<PathBuf, AppError> {
-        let canonical_root = std::fs::canonicalize(new_root)?;
-        *self.root.lock().unwrap() = canonical_root.clone();
-        Ok(canonical_root)
+    /// The directory `relative` is anchored at, and the rest of the path.
+    fn base<'a>(&self, relative: &'a str) -> (&Path, &'a str) {
+        if let Some((name, rest)) = relative.split_once(':') {
+            if let Some(mount) = self.mounts.iter().find(|m| m.name == name) {
+                return (&mount.path, rest.trim_start_matches('/'));
+            }
+        }
+        (&self.root, relative)
     }
 
     /// Resolve a user-provided relative path against the configured root, ensuring it cannot escape the sandbox.
     pub fn resolve_path(&self, relative: &str) -> Result<PathBuf, AppError> {
-        let root = self.root();
-        let mut candidate = root.clone();
+        let (root, relative) = self.base(relative);
+        let mut candidate = root.to_path_buf();
         if !relative.is_empty() {
             candidate.push(relative);
         }
 
         let canonical = candidate.canonicalize()?;
-        if !canonical.starts_with(&root) {
+        if !canonical.starts_with(root) {
             return Err(AppError::BadRequest("path escapes root_dir".into()));
         }
+        self.rules.check(&canonical, Op::Read)?;
 
         Ok(canonical)
     }
 
+    /// Resolve the target of a create, mkdir, rename or delete, which may not
+    /// exist yet. Fails inside read-only mounts.
+    pub fn resolve_new_path(&self, relative: &str) -> Result<PathBuf, AppError> {
+        let resolved = self.resolve_entry(relative)?;
+        self.check_writable(&resolved)?;
+        Ok(resolved)
+    }
+
+    /// Resolve a path that may not exist yet.
+    ///
+    /// Existing ancestors are canonicalized so symlinks cannot lead outside the
+    /// root; the final component is kept as-is, so a symlink there refers to
+    /// the link itself.
+    pub fn resolve_entry(&self, relative: &str) -> Result<PathBuf, AppError> {
+        let (root, relative) = self.base(relative);
+        let candidate = root.join(relative);
+        if candidate
+            .components()
+            .any(|c| matches!(c, Component::ParentDir))
+        {
+            return Err(AppError::BadRequest("path must not contain '..'".into()));
+        }
+        let (Some(parent), Some(name)) = (candidate.parent(), candidate.file_name()) else {
+            return Err(AppError::BadRequest("path must name an entry".into()));
+        };
+
+        let mut existing = parent;
+        let mut missing = Vec::new();
+        while existing.symlink_metadata().is_err() {
+            let (Some(up), Some(dir)) = (existing.parent(), existing.file_name()) else {
+                break;
+            };
+            missing.push(dir);
+            existing = up;
+        }
+
+        let mut resolved = existing.canonicalize()?;
+        resolved.extend(missing.iter().rev());
+        resolved.push(name);
+        if !resolved.starts_with(root) || resolved == *root {
+            return Err(AppError::BadRequest("path escapes root_dir".into()));
+        }
+        self.rules.check(&resolved, Op::Read)?;
+
+        Ok(resolved)
+    }
+
+    /// Refuse changes below a read-only mount or where the rules allow
+    /// reading only.
+    pub fn check_writable(&self, path: &Path) -> Result<(), AppError> {
+        if let Some(mount) = self
+            .mounts
+            .iter()
+            .find(|m| m.read_only && path.starts_with(&m.path))
+        {
+            return Err(AppError::Forbidden(format!(
+                "mount {} is read-only",
+                mount.name
+            )));
+        }
+        self.rules.check(path, Op::Write)
+    }
+
+    /// Check `op` on `path` and everything below it, for operations on whole
+    /// trees such as a recursive delete. Blocking.
+    pub fn check_tree(&self, path: &Path, op: Op) -> Result<(), AppError> {
+        if op == Op::Write {
+            self.check_writable(path)?;
+        }
+        self.rules.check_tree(path, op)
+    }
+
+    /// Whether an absolute, canonical path lies inside the root or a mount.
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.root) || self.mounts.iter().any(|m| path.starts_with(&m.path))
+    }
+
+    /// Canonicalize `path`, following symlinks, and check that the target
+    /// lies inside the root or a mount and that the rules allow `op` on it.
+    /// Blocking.
+    pub fn check_target(&self, path: &Path, op: Op) -> Result<PathBuf, AppError> {
+        let target = path.canonicalize()?;
+        if !self.contains(&target) {
+            return Err(AppError::Forbidden(
+                "symlink target is outside root_dir".into(),
+            ));
+        }
+        self.rules.check(&target, op)?;
+        Ok(target)
+    }
+
+    /// Whether the rules hide `path` from listings and other results.
+    pub fn is_hidden(&self, path: &Path) -> bool {
+        self.rules.is_hidden(path)
+    }
+
+    /// The client-facing form of `absolute`: relative to the root, or
+    /// `name:/rel` inside a mount.
     pub fn to_relative(&self, absolute: &Path) -> Option<String> {
-        let root = self.root();
-        absolute
-            .strip_prefix(&root)
-            .ok()
-            .map(|p| p.to_string_lossy().to_string())
+        if let Ok(rel) = absolute.strip_prefix(&self.root) {
+            return Some(rel.to_string_lossy().to_string());
+        }
+        self.mounts.iter().find_map(|mount| {
+            let rel = absolute.strip_prefix(&mount.path).ok()?;
+            Some(format!("{}:/{}", mount.name, rel.to_string_lossy()))
+        })
+    }
+}
+
+/// Validator for a file's current contents, derived from its size and
+/// modification time. Quoted, so it can be used as an HTTP `ETag` as is.
+pub fn etag(metadata: &std::fs::Metadata) -> String {
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", metadata.len(), mtime.as_nanos())
+}
+
+pub fn mtime_secs(metadata: &std::fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Copy a file, symlink or directory tree. Symlinks are recreated rather than
+/// followed.
+pub fn copy_recursive(from: &Path, to: &Path) -> std::io::Result<()> {
+    let metadata = std::fs::symlink_metadata(from)?;
+    if metadata.is_symlink() {
+        copy_symlink(from, to)
+    } else if metadata.is_dir() {
+        std::fs::create_dir(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        std::fs::set_permissions(to, metadata.permissions())
+    } else {
+        std::fs::copy(from, to).map(|_| ())
+    }
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(from)?, to)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::copy(from, to).map(|_| ())
+}
+
+/// What a directory entry is, without following symlinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryType {
+    File,
+    Dir,
+    Symlink,
+    Fifo,
+    Socket,
+    BlockDevice,
+    CharDevice,
+}
+
+impl EntryType {
+    pub fn of(file_type: std::fs::FileType) -> Self {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_symlink() {
+            EntryType::Symlink
+        } else if file_type.is_dir() {
+            EntryType::Dir
+        } else if file_type.is_fifo() {
+            EntryType::Fifo
+        } else if file_type.is_socket() {
+            EntryType::Socket
+        } else if file_type.is_block_device() {
+            EntryType::BlockDevice
+        } else if file_type.is_char_device() {
+            EntryType::CharDevice
+        } else {
+            EntryType::File
+        }
+    }
+}
+
+/// User and group names by id, looked up once per listing.
+#[derive(Default)]
+pub struct OwnerNames {
+    users: HashMap<u32, Option<String>>,
+    groups: HashMap<u32, Option<String>>,
+}
+
+impl OwnerNames {
+    pub fn user(&mut self, uid: u32) -> Option<String> {
+        self.users
+            .entry(uid)
+            .or_insert_with(|| lookup_user(uid))
+            .clone()
+    }
+
+    pub fn group(&mut self, gid: u32) -> Option<String> {
+        self.groups
+            .entry(gid)
+            .or_insert_with(|| lookup_group(gid))
+            .clone()
+    }
+}
+
+fn lookup_user(uid: u32) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    // SAFETY: all pointers are valid for the call, and `pw_name` points into
+    // `buf`, which outlives its use.
+    unsafe {
+        let rc = libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result);
+        if rc != 0 || result.is_null() {
+            return None;
+        }
+        Some(
+            CStr::from_ptr(passwd.pw_name)
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+fn lookup_group(gid: u32) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    // SAFETY: as in `lookup_user`.
+    unsafe {
+        let rc = libc::getgrgid_r(gid, &mut group, buf.as_mut_ptr(), buf.len(), &mut result);
+        if rc != 0 || result.is_null() {
+            return None;
+        }
+        Some(CStr::from_ptr(group.gr_name).to_string_lossy().into_owned())
     }
 }
@@ -0,0 +1,75 @@
+use crate::error::AppError;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::path::Path;
+
+/// Include/exclude glob lists as accepted by the tree-walking endpoints
+/// (`?include=*.rs,*.toml&exclude=target`).
+///
+/// Globs are matched against the path relative to the walked directory and
+/// against the bare file name, so `*.log` and `node_modules` work at any
+/// depth.
+#[derive(Clone, Default)]
+pub struct PathGlobs {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+}
+
+impl PathGlobs {
+    pub fn new(include: Option<&str>, exclude: Option<&str>) -> Result<Self, AppError> {
+        Ok(Self {
+            include: include.map(parse_list).transpose()?,
+            exclude: exclude.map(parse_list).transpose()?,
+        })
+    }
+
+    /// Whether only files matching an include glob are wanted.
+    pub fn has_include(&self) -> bool {
+        self.include.is_some()
+    }
+
+    /// Files and whole directories matching an exclude glob are skipped.
+    pub fn excluded(&self, rel: &Path) -> bool {
+        self.exclude.as_ref().is_some_and(|set| matches(set, rel))
+    }
+
+    /// Whether a file passes the include list; always true without one.
+    pub fn included(&self, rel: &Path) -> bool {
+        self.include.as_ref().is_none_or(|set| matches(set, rel))
+    }
+}
+
+fn matches(set: &GlobSet, rel: &Path) -> bool {
+    set.is_match(rel) || rel.file_name().is_some_and(|name| set.is_match(name))
+}
+
+/// Parse a comma-separated glob list. Commas inside `{a,b}` alternations
+/// belong to the glob.
+fn parse_list(patterns: &str) -> Result<GlobSet, AppError> {
+    let mut builder = GlobSetBuilder::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut add = |pattern: &str| -> Result<(), AppError> {
+        let pattern = pattern.trim();
+        if !pattern.is_empty() {
+            let glob = Glob::new(pattern)
+                .map_err(|e| AppError::BadRequest(format!("invalid glob {pattern:?}: {e}")))?;
+            builder.add(glob);
+        }
+        Ok(())
+    };
+    for (i, c) in patterns.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                add(&patterns[start..i])?;
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    add(&patterns[start..])?;
+    builder
+        .build()
+        .map_err(|e| AppError::BadRequest(format!("invalid glob list: {e}")))
+}
@@ -0,0 +1,100 @@
+use crate::{
+    archive::{self, ArchiveFilter, ArchiveFormat, ChannelWriter},
+    error::AppError,
+    fs::FsRoot,
+    globs::PathGlobs,
+    http::serve::{self, Disposition},
+    state::AppState,
+};
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use serde::Deserialize;
+use std::io;
+use tokio::sync::mpsc;
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/api/fs/archive", get(archive_handler))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FsArchiveQuery {
+    pub path: String,
+    #[serde(default)]
+    pub format: ArchiveFormat,
+    pub show_hidden: Option<bool>,
+    /// Comma-separated globs; only matching files are archived.
+    pub include: Option<String>,
+    /// Comma-separated globs for files and directories to leave out.
+    pub exclude: Option<String>,
+}
+
+/// Stream a directory as a zip or tar.gz archive, built on the fly.
+pub async fn archive_handler(
+    State(state): State<AppState>,
+    root: FsRoot,
+    Query(query): Query<FsArchiveQuery>,
+) -> Result<Response, AppError> {
+    let resolved = root.resolve_path(&query.path)?;
+    if !resolved.is_dir() {
+        return Err(AppError::BadRequest("path is not a directory".into()));
+    }
+    let filter = ArchiveFilter {
+        show_hidden: query.show_hidden.unwrap_or(false),
+        globs: PathGlobs::new(query.include.as_deref(), query.exclude.as_deref())?,
+        fs: root,
+    };
+
+    // Walk up front so the size cap can still be reported as a status code.
+    let max_size = state.config.archive.max_size;
+    let root = resolved.clone();
+    let entries = tokio::task::spawn_blocking(move || archive::collect(&root, &filter, max_size))
+        .await
+        .map_err(|e| AppError::Internal(format!("archive walk failed: {e}")))??;
+
+    let format = query.format;
+    let (tx, rx) = mpsc::channel(4);
+    let dir = resolved.display().to_string();
+    tokio::task::spawn_blocking(move || {
+        let writer = ChannelWriter::new(tx.clone());
+        match archive::write_archive(format, &entries, writer) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {
+                tracing::debug!("archive download of {dir} cancelled");
+            }
+            Err(err) => {
+                tracing::warn!("archive of {dir} failed: {err}");
+                // Abort the body so the client sees a truncated download.
+                let _ = tx.blocking_send(Err(err));
+            }
+        }
+    });
+
+    let name = resolved
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "archive".to_string());
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_str(&serve::content_disposition(
+            &format!("{name}.{}", format.extension()),
+            Disposition::Attachment,
+        ))
+        .map_err(|_| AppError::Internal("invalid file name".into()))?,
+    );
+
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
+    Ok((headers, Body::from_stream(stream)).into_response())
+}
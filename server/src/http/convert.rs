@@ -0,0 +1,153 @@
+use crate::{
+    convert::{self, ImageMeta, Stretch},
+    error::AppError,
+    fs::{etag, FsRoot},
+    state::AppState,
+};
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_LOW_PERCENTILE: f32 = 0.5;
+const DEFAULT_HIGH_PERCENTILE: f32 = 99.5;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/api/fs/convert", get(convert_handler))
+        .route("/api/fs/convert/info", get(convert_info_handler))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FsConvertQuery {
+    pub path: String,
+    /// 0-based page of a multi-page TIFF.
+    #[serde(default)]
+    pub page: usize,
+    #[serde(default)]
+    pub stretch: Stretch,
+    /// Percentiles mapped to black and white with `stretch=percentile`.
+    pub low: Option<f32>,
+    pub high: Option<f32>,
+    /// Longest edge of the output; the image is scaled down to fit.
+    pub size: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FsConvertInfoQuery {
+    pub path: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FsConvertInfoResponse {
+    pub ok: bool,
+    pub path: String,
+    pub size: u64,
+    pub image: ImageMeta,
+}
+
+/// An image in any format the server decodes, as an 8-bit PNG the browser
+/// can show. Meant for TIFF, EXR, PPM and high-bit-depth PNG.
+pub async fn convert_handler(
+    State(state): State<AppState>,
+    root: FsRoot,
+    Query(query): Query<FsConvertQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let resolved = root.resolve_path(&query.path)?;
+    let metadata = tokio::fs::metadata(&resolved).await?;
+    if !metadata.is_file() {
+        return Err(AppError::BadRequest("not a file".into()));
+    }
+    let max_source_size = state.config.thumbnails.max_source_size;
+    if metadata.len() > max_source_size {
+        return Err(AppError::TooLarge(format!(
+            "{} bytes is over the limit of {max_source_size} for conversion",
+            metadata.len()
+        )));
+    }
+
+    let low = query.low.unwrap_or(DEFAULT_LOW_PERCENTILE);
+    let high = query.high.unwrap_or(DEFAULT_HIGH_PERCENTILE);
+    // The source's validator plus everything that changes the output.
+    let tag = format!(
+        "\"{}-{}-{:?}-{low}-{high}-{}\"",
+        etag(&metadata).trim_matches('"'),
+        query.page,
+        query.stretch,
+        query.size.unwrap_or(0)
+    );
+    let matches = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.split(',')
+                .any(|t| t.trim().trim_start_matches("W/") == tag)
+        });
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(
+        header::ETAG,
+        HeaderValue::from_str(&tag).map_err(|e| AppError::Internal(e.to_string()))?,
+    );
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    if matches {
+        return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
+    }
+
+    let _permit = state.thumbnails.permit().await?;
+    let converted = tokio::task::spawn_blocking(move || {
+        convert::to_png(
+            &resolved,
+            query.page,
+            query.stretch,
+            (low, high),
+            query.size,
+        )
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("convert task failed: {e}")))??;
+
+    response_headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("image/png"));
+    // The source as it was before conversion.
+    let meta = &converted.meta;
+    let (low_value, high_value) = converted.range;
+    for (name, value) in [
+        ("x-image-width", meta.width.to_string()),
+        ("x-image-height", meta.height.to_string()),
+        ("x-image-bit-depth", meta.bit_depth.to_string()),
+        ("x-image-pages", meta.pages.to_string()),
+        ("x-image-range", format!("{low_value} {high_value}")),
+    ] {
+        let value = HeaderValue::from_str(&value).map_err(|e| AppError::Internal(e.to_string()))?;
+        response_headers.insert(name, value);
+    }
+    Ok((StatusCode::OK, response_headers, converted.png).into_response())
+}
+
+/// Dimensions, bit depth and page count of an image, read from its header.
+pub async fn convert_info_handler(
+    root: FsRoot,
+    Query(query): Query<FsConvertInfoQuery>,
+) -> Result<Json<FsConvertInfoResponse>, AppError> {
+    let resolved = root.resolve_path(&query.path)?;
+    let (size, image) = tokio::task::spawn_blocking(move || {
+        let metadata = std::fs::metadata(&resolved)?;
+        if !metadata.is_file() {
+            return Err(AppError::BadRequest("not a file".into()));
+        }
+        Ok((metadata.len(), convert::info(&resolved)?))
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("image info task failed: {e}")))??;
+
+    Ok(Json(FsConvertInfoResponse {
+        ok: true,
+        path: query.path,
+        size,
+        image,
+    }))
+}
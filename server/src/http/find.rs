@@ -0,0 +1,64 @@
+use crate::{error::AppError, fs::FsRoot, index::FindMatch, state::AppState};
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 500;
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/api/fs/find", get(find_handler))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FsFindQuery {
+    pub q: String,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FsFindResponse {
+    pub ok: bool,
+    /// False while the initial index build is still running.
+    pub ready: bool,
+    /// The index hit `index.max_files`; some files cannot be found.
+    pub truncated: bool,
+    pub indexed: usize,
+    pub results: Vec<FindMatch>,
+}
+
+/// Fuzzy-match file paths under the session's root, best match first.
+pub async fn find_handler(
+    State(state): State<AppState>,
+    root: FsRoot,
+    Query(query): Query<FsFindQuery>,
+) -> Result<Json<FsFindResponse>, AppError> {
+    if !state.config.index.enabled {
+        return Err(AppError::BadRequest("path index disabled".into()));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let Some((index, within)) = state.index_for(&root.root()) else {
+        return Err(AppError::Internal(
+            "no index covers the current root".into(),
+        ));
+    };
+    let base = root.root();
+    let found = tokio::task::spawn_blocking(move || {
+        index.find(query.q.trim(), &within, limit, |path| {
+            !root.is_hidden(&base.join(path))
+        })
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("find failed: {e}")))?;
+
+    Ok(Json(FsFindResponse {
+        ok: true,
+        ready: found.ready,
+        truncated: found.truncated,
+        indexed: found.indexed,
+        results: found.matches,
+    }))
+}
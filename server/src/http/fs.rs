@@ -1,37 +1,155 @@
-use crate::{error::AppError, state::AppState};
+use crate::{
+    access::Op,
+    error::AppError,
+    events::{ChangeKind, ServerEvent},
+    fs::{copy_recursive, etag, mtime_secs, EntryType, FsRoot, Mount, OwnerNames},
+    globs::PathGlobs,
+    http::{
+        login::extract_session_id_from_headers,
+        serve::{self, Disposition},
+    },
+    listing::{self, Candidate, ListOptions, SortBy},
+    sniff::{self, PreviewKind},
+    state::AppState,
+    text::{self, LineEnding, TextRange},
+};
+use async_trait::async_trait;
 use axum::{
-    body::Body,
-    extract::{Query, State},
-    http::{header, HeaderMap, HeaderValue},
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, FromRequestParts, Query, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
-use mime_guess::MimeGuess;
+use encoding_rs::UTF_8;
 use serde::{Deserialize, Serialize};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 use tokio::fs;
-use tokio_util::io::ReaderStream;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// Upper bound for a file saved through `PUT /api/fs/content`.
+const MAX_WRITE_BYTES: usize = 64 * 1024 * 1024;
+/// Largest current file echoed back in a save conflict, for the client's diff.
+const CONFLICT_CONTENT_LIMIT: u64 = 1024 * 1024;
 
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/api/fs/list", get(list_handler))
-        .route("/api/fs/content", get(content_handler))
+        .route("/api/fs/stat", get(stat_handler))
+        .route(
+            "/api/fs/content",
+            get(content_handler)
+                .put(write_content_handler)
+                .layer(DefaultBodyLimit::max(MAX_WRITE_BYTES)),
+        )
         .route("/api/fs/raw", get(raw_handler))
         .route("/api/fs/root", post(change_root_handler))
+        .route("/api/fs/file", post(create_file_handler))
+        .route("/api/fs/mkdir", post(mkdir_handler))
+        .route("/api/fs/rename", post(rename_handler))
+        .route("/api/fs/copy", post(copy_handler))
+        .route("/api/fs/delete", post(delete_handler))
+}
+
+/// Paths in `/api/fs` requests are relative to the requesting session's
+/// root, or to `root_dir` without a session.
+#[async_trait]
+impl FromRequestParts<AppState> for FsRoot {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, AppError> {
+        let session_id = extract_session_id_from_headers(&parts.headers);
+        Ok(state.fs_root(session_id.as_deref()).await)
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct FsListQuery {
     pub path: Option<String>,
     pub show_hidden: Option<bool>,
+    /// Also count the entries of every subdirectory.
+    #[serde(default)]
+    pub child_counts: bool,
+    #[serde(default)]
+    pub sort: SortBy,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub dirs_first: Option<bool>,
+    /// Comma-separated globs; only matching files are listed.
+    pub include: Option<String>,
+    /// Comma-separated globs for files and directories to leave out.
+    pub exclude: Option<String>,
+    /// Judge file types by content, reading the start of every listed file;
+    /// otherwise only extensions are looked at.
+    #[serde(default)]
+    pub sniff: bool,
+    /// Page size; everything when absent.
+    pub limit: Option<usize>,
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub format: ListFormat,
 }
 
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListFormat {
+    #[default]
+    Json,
+    /// One entry per line as soon as it is read, then a `done` line.
+    Ndjson,
+}
+
+/// Metadata of one directory entry. For a symlink that resolves, everything
+/// but `entry_type` and `link` describes its target.
 #[derive(Debug, Serialize)]
 pub struct FsEntry {
     pub name: String,
-    pub entry_type: String,
+    pub entry_type: EntryType,
     pub size: u64,
     pub mtime: u64,
+    /// Nanoseconds to add to `mtime`.
+    pub mtime_nsec: u32,
+    pub etag: String,
+    /// Permission bits, including setuid, setgid and sticky.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    /// Regular files only, detected from content first (see [`sniff::detect`])
+    /// or, in listings not asking for that, from the extension.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,
+    /// How to preview the file; regular files only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<PreviewKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<FsLink>,
+    /// Directories only, when requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub child_count: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FsLink {
+    pub target: String,
+    /// The target does not exist.
+    pub broken: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_type: Option<EntryType>,
 }
 
 #[derive(Debug, Serialize)]
@@ -39,54 +157,332 @@ pub struct FsListResponse {
     pub ok: bool,
     pub path: String,
     pub entries: Vec<FsEntry>,
+    /// Entries passing the filters, across all pages.
+    pub total: usize,
+    /// Pass as `cursor` to get the next page; absent on the last one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    /// The configured mounts, listed with the top level only. Their contents
+    /// are at `<name>:/`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mounts: Vec<Mount>,
 }
 
+/// Lines of an NDJSON listing.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FsListEvent {
+    Entry(FsEntry),
+    Done {
+        total: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        next_cursor: Option<String>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        mounts: Vec<Mount>,
+    },
+}
+
+/// List a directory, sorted and filtered on the server. With `limit`, one
+/// page is returned at a time; only the entries of that page are examined
+/// in full, so the first screen of a huge directory comes back quickly.
 pub async fn list_handler(
-    State(state): State<AppState>,
+    root: FsRoot,
     Query(query): Query<FsListQuery>,
-) -> Result<Json<FsListResponse>, AppError> {
+) -> Result<Response, AppError> {
     let rel = query.path.unwrap_or_default();
-    let resolved = state.fs.resolve_path(&rel)?;
+    let resolved = root.resolve_path(&rel)?;
     let show_hidden = query.show_hidden.unwrap_or(false);
+    let child_counts = query.child_counts.then_some(show_hidden);
+    let sniff = query.sniff;
+    let options = ListOptions {
+        sort: query.sort,
+        descending: matches!(query.order, SortOrder::Desc),
+        dirs_first: query.dirs_first.unwrap_or(true),
+        show_hidden,
+        globs: PathGlobs::new(query.include.as_deref(), query.exclude.as_deref())?,
+    };
+    let limit = query.limit.map(|limit| limit.max(1));
+    let mounts = if rel.is_empty() {
+        root.mounts().to_vec()
+    } else {
+        Vec::new()
+    };
 
-    let mut entries_res = Vec::new();
-    let mut dir = fs::read_dir(&resolved).await?;
-    while let Some(entry) = dir.next_entry().await? {
-        let name = entry.file_name().to_string_lossy().to_string();
-        
-        // Filter hidden files if not requested
-        if !show_hidden && name.starts_with('.') {
-            continue;
-        }
+    let fs_root = root.clone();
+    let (candidates, options) = tokio::task::spawn_blocking(move || {
+        listing::scan(&fs_root, &resolved, &options).map(|candidates| (candidates, options))
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("list task failed: {e}")))??;
+    let total = candidates.len();
+    // Check the cursor before anything is streamed.
+    listing::page(&candidates, &options, query.cursor.as_deref(), limit)?;
 
-        let metadata = entry.metadata().await?;
-        let file_type = if metadata.is_dir() { "dir" } else { "file" };
-        let size = metadata.len();
-        let mtime = metadata
-            .modified()
-            .unwrap_or(SystemTime::UNIX_EPOCH)
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-
-        entries_res.push(FsEntry {
-            name,
-            entry_type: file_type.to_string(),
-            size,
-            mtime,
+    if query.format == ListFormat::Ndjson {
+        let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(32);
+        tokio::task::spawn_blocking(move || {
+            let Ok((page, next_cursor)) =
+                listing::page(&candidates, &options, query.cursor.as_deref(), limit)
+            else {
+                return;
+            };
+            let mut owners = OwnerNames::default();
+            for candidate in page {
+                let Some(entry) =
+                    list_entry(&root, candidate, &mut owners, child_counts, sniff).transpose()
+                else {
+                    continue;
+                };
+                let event = match entry {
+                    Ok(entry) => FsListEvent::Entry(entry),
+                    Err(err) => {
+                        let _ = tx.blocking_send(Err(err));
+                        return;
+                    }
+                };
+                if tx.blocking_send(Ok(ndjson_line(&event))).is_err() {
+                    return;
+                }
+            }
+            let done = FsListEvent::Done {
+                total,
+                next_cursor,
+                mounts,
+            };
+            let _ = tx.blocking_send(Ok(ndjson_line(&done)));
         });
+
+        let stream = futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|chunk| (chunk, rx))
+        });
+        let headers = [(header::CONTENT_TYPE, "application/x-ndjson")];
+        return Ok((headers, Body::from_stream(stream)).into_response());
     }
 
+    let (entries, next_cursor) = tokio::task::spawn_blocking(move || {
+        let (page, next_cursor) =
+            listing::page(&candidates, &options, query.cursor.as_deref(), limit)?;
+        let mut owners = OwnerNames::default();
+        let mut entries = Vec::with_capacity(page.len());
+        for candidate in page {
+            entries.extend(list_entry(&root, candidate, &mut owners, child_counts, sniff)?);
+        }
+        Ok::<_, AppError>((entries, next_cursor))
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("list task failed: {e}")))??;
+
     Ok(Json(FsListResponse {
         ok: true,
         path: rel,
-        entries: entries_res,
+        entries,
+        total,
+        next_cursor,
+        mounts,
+    })
+    .into_response())
+}
+
+/// The full entry for a listed candidate, or `None` if it was deleted in the
+/// meantime.
+fn list_entry(
+    root: &FsRoot,
+    candidate: &Candidate,
+    owners: &mut OwnerNames,
+    child_counts: Option<bool>,
+    sniff: bool,
+) -> std::io::Result<Option<FsEntry>> {
+    match read_entry(
+        root,
+        &candidate.path,
+        candidate.name.clone(),
+        owners,
+        child_counts,
+        sniff,
+    ) {
+        Ok(entry) => Ok(Some(entry)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn ndjson_line(event: &FsListEvent) -> Bytes {
+    let mut line = serde_json::to_vec(event).unwrap_or_default();
+    line.push(b'\n');
+    Bytes::from(line)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FsStatQuery {
+    pub path: String,
+    /// Whether dotfiles count towards a directory's `child_count`.
+    pub show_hidden: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FsStatResponse {
+    pub ok: bool,
+    pub path: String,
+    pub entry: FsEntry,
+}
+
+/// Metadata of a single path, as a listing reports it. A symlink is
+/// described as such, not replaced by its target.
+pub async fn stat_handler(
+    root: FsRoot,
+    Query(query): Query<FsStatQuery>,
+) -> Result<Json<FsStatResponse>, AppError> {
+    let path = if query.path.is_empty() {
+        root.resolve_path("")?
+    } else {
+        root.resolve_entry(&query.path)?
+    };
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let show_hidden = query.show_hidden.unwrap_or(false);
+    let entry = tokio::task::spawn_blocking(move || {
+        read_entry(
+            &root,
+            &path,
+            name,
+            &mut OwnerNames::default(),
+            Some(show_hidden),
+            true,
+        )
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("stat task failed: {e}")))??;
+
+    Ok(Json(FsStatResponse {
+        ok: true,
+        path: query.path,
+        entry,
     }))
 }
 
+/// Describe the entry at `path`. With `child_counts`, directories get the
+/// number of entries a listing would show (the flag says whether dotfiles
+/// are shown). With `sniff`, file types are judged by content; otherwise by
+/// extension only, so listing a directory doesn't open every file in it.
+/// Blocking.
+fn read_entry(
+    root: &FsRoot,
+    path: &Path,
+    name: String,
+    owners: &mut OwnerNames,
+    child_counts: Option<bool>,
+    sniff: bool,
+) -> std::io::Result<FsEntry> {
+    let lstat = std::fs::symlink_metadata(path)?;
+    let entry_type = EntryType::of(lstat.file_type());
+
+    let (metadata, link) = if entry_type == EntryType::Symlink {
+        let target = std::fs::read_link(path)?.to_string_lossy().to_string();
+        let target_metadata = std::fs::metadata(path).ok();
+        let broken = target_metadata.is_none();
+        // Only targets inside the root or a mount, and not hidden by the
+        // access rules, are described; other links show as themselves.
+        let followed = target_metadata.filter(|_| {
+            path.canonicalize()
+                .is_ok_and(|p| root.contains(&p) && !root.is_hidden(&p))
+        });
+        let link = FsLink {
+            target,
+            broken,
+            target_type: followed.as_ref().map(|m| EntryType::of(m.file_type())),
+        };
+        (followed.unwrap_or(lstat), Some(link))
+    } else {
+        (lstat, None)
+    };
+
+    let kind = EntryType::of(metadata.file_type());
+    let file_type = match kind {
+        EntryType::File if sniff => Some(sniff::detect(path)),
+        EntryType::File => sniff::guess(path),
+        _ => None,
+    };
+    let child_count = match child_counts {
+        Some(show_hidden) if kind == EntryType::Dir => count_children(root, path, show_hidden),
+        _ => None,
+    };
+
+    Ok(FsEntry {
+        name,
+        entry_type,
+        size: metadata.len(),
+        mtime: mtime_secs(&metadata),
+        mtime_nsec: metadata.mtime_nsec() as u32,
+        etag: etag(&metadata),
+        mode: metadata.mode() & 0o7777,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        owner: owners.user(metadata.uid()),
+        group: owners.group(metadata.gid()),
+        mime: file_type.as_ref().map(|t| t.mime.clone()),
+        preview: file_type.map(|t| t.preview),
+        link,
+        child_count,
+    })
+}
+
+fn count_children(root: &FsRoot, dir: &Path, show_hidden: bool) -> Option<u64> {
+    let entries = std::fs::read_dir(dir).ok()?;
+    let count = entries
+        .filter_map(Result::ok)
+        .filter(|entry| show_hidden || !entry.file_name().to_string_lossy().starts_with('.'))
+        .filter(|entry| !root.is_hidden(&entry.path()))
+        .count();
+    Some(count as u64)
+}
+
 #[derive(Debug, Deserialize)]
 pub struct FsContentQuery {
     pub path: String,
+    /// Decode with this encoding (WHATWG label) instead of detecting it.
+    pub encoding: Option<String>,
+    /// The first `head` lines.
+    pub head: Option<u64>,
+    /// The last `tail` lines.
+    pub tail: Option<u64>,
+    /// 1-based, inclusive line range.
+    pub start_line: Option<u64>,
+    pub end_line: Option<u64>,
+    /// Byte range.
+    pub offset: Option<u64>,
+    pub length: Option<u64>,
+}
+
+impl FsContentQuery {
+    fn range(&self) -> Result<TextRange, AppError> {
+        let lines = self.start_line.is_some() || self.end_line.is_some();
+        let bytes = self.offset.is_some() || self.length.is_some();
+        let given = [self.head.is_some(), self.tail.is_some(), lines, bytes];
+        if given.iter().filter(|&&given| given).count() > 1 {
+            return Err(AppError::BadRequest(
+                "use only one of head, tail, start_line/end_line and offset/length".into(),
+            ));
+        }
+        Ok(if let Some(n) = self.head {
+            TextRange::Head(n)
+        } else if let Some(n) = self.tail {
+            TextRange::Tail(n)
+        } else if lines {
+            TextRange::Lines {
+                start: self.start_line.unwrap_or(1),
+                end: self.end_line,
+            }
+        } else if bytes {
+            TextRange::Bytes {
+                offset: self.offset.unwrap_or(0),
+                length: self.length,
+            }
+        } else {
+            TextRange::Whole
+        })
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -94,40 +490,326 @@ pub struct FsContentResponse {
     pub ok: bool,
     pub path: String,
     pub content: String,
+    pub mtime: u64,
+    pub etag: String,
+    pub size: u64,
+    pub encoding: String,
+    pub bom: bool,
+    /// The file looks binary and `content` is empty.
+    pub binary: bool,
+    /// Invalid byte sequences were replaced with U+FFFD.
+    pub lossy: bool,
+    pub line_ending: LineEnding,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_lines: Option<u64>,
+    /// 1-based first and last line of `content`, for line ranges.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_line: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_line: Option<u64>,
+    /// `false` when `content` is only part of the file.
+    pub complete: bool,
 }
 
+/// Read a text file, or part of it, decoded to UTF-8. Whole files larger
+/// than `[content] max_size` are refused with 413; ask for a range instead.
 pub async fn content_handler(
     State(state): State<AppState>,
+    root: FsRoot,
     Query(query): Query<FsContentQuery>,
 ) -> Result<Json<FsContentResponse>, AppError> {
-    let resolved = state.fs.resolve_path(&query.path)?;
-    let content = fs::read_to_string(&resolved).await?;
+    let resolved = root.resolve_path(&query.path)?;
+    let range = query.range()?;
+    let encoding = query
+        .encoding
+        .as_deref()
+        .map(text::encoding_for_label)
+        .transpose()?;
+    let metadata = fs::metadata(&resolved).await?;
+    if !metadata.is_file() {
+        return Err(AppError::BadRequest("not a file".into()));
+    }
+    let max_size = state.config.content.max_size;
+    let text =
+        tokio::task::spawn_blocking(move || text::read(&resolved, range, encoding, max_size))
+            .await
+            .map_err(|e| AppError::Internal(format!("read task failed: {e}")))??;
 
     Ok(Json(FsContentResponse {
         ok: true,
         path: query.path,
-        content,
+        content: text.content,
+        mtime: mtime_secs(&metadata),
+        etag: etag(&metadata),
+        size: metadata.len(),
+        encoding: text.encoding.to_string(),
+        bom: text.bom,
+        binary: text.binary,
+        lossy: text.lossy,
+        line_ending: text.line_ending,
+        total_lines: text.total_lines,
+        start_line: text.lines.map(|(first, _)| first),
+        end_line: text.lines.map(|(_, last)| last),
+        complete: text.complete,
     }))
 }
 
-pub async fn raw_handler(
+#[derive(Debug, Deserialize)]
+pub struct FsWriteQuery {
+    pub path: String,
+    /// Alternative to `If-Match`: the `mtime` (seconds) the client last saw.
+    pub expected_mtime: Option<u64>,
+    /// Store the (UTF-8) body in this encoding, by WHATWG label; defaults
+    /// to the encoding of the file being replaced, as GET reports it.
+    pub encoding: Option<String>,
+    /// Start the file with a byte-order mark; defaults to keeping the
+    /// replaced file's.
+    pub bom: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FsVersion {
+    pub etag: String,
+    pub mtime: u64,
+    pub size: u64,
+}
+
+impl FsVersion {
+    fn of(metadata: &std::fs::Metadata) -> Self {
+        Self {
+            etag: etag(metadata),
+            mtime: mtime_secs(metadata),
+            size: metadata.len(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FsWriteResponse {
+    pub ok: bool,
+    pub path: String,
+    #[serde(flatten)]
+    pub version: FsVersion,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FsConflictResponse {
+    pub ok: bool,
+    pub error: String,
+    /// `None` when the file has been deleted.
+    pub current: Option<FsVersion>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+/// Atomically replace (or create) a file with the request body.
+///
+/// Text is stored in the encoding the file already had (or the one asked
+/// for), so a file read through GET and saved back keeps its encoding and
+/// byte-order mark.
+///
+/// `If-Match`, `If-None-Match: *` or `expected_mtime` guard against
+/// overwriting changes made since the client loaded the file; a failed check
+/// returns 409 with the file's current version.
+pub async fn write_content_handler(
     State(state): State<AppState>,
-    Query(query): Query<FsContentQuery>,
-) -> Result<(HeaderMap, Body), AppError> {
-    let resolved = state.fs.resolve_path(&query.path)?;
-    let file = fs::File::open(&resolved).await?;
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
+    root: FsRoot,
+    Query(query): Query<FsWriteQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, AppError> {
+    // Existing files are resolved through symlinks so the link survives the rename.
+    let target = match root.resolve_path(&query.path) {
+        Ok(path) => path,
+        Err(AppError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+            root.resolve_new_path(&query.path)?
+        }
+        Err(err) => return Err(err),
+    };
+    root.check_writable(&target)?;
+    let current = fs::metadata(&target).await.ok();
+    if current.as_ref().is_some_and(|m| m.is_dir()) {
+        return Err(AppError::BadRequest("path is a directory".into()));
+    }
+    if !precondition_holds(&headers, query.expected_mtime, current.as_ref()) {
+        return conflict_response(&target, current.as_ref()).await;
+    }
+
+    let body = encode_body(&query, &target, current.is_some(), body).await?;
+    let tmp = staging_path(&target);
+    if let Err(err) = write_temp(&tmp, &body, current.as_ref()).await {
+        let _ = fs::remove_file(&tmp).await;
+        return Err(err.into());
+    }
+
+    // Check again right before the swap to narrow the window for a racing writer.
+    let latest = fs::metadata(&target).await.ok();
+    if !precondition_holds(&headers, query.expected_mtime, latest.as_ref()) {
+        let _ = fs::remove_file(&tmp).await;
+        return conflict_response(&target, latest.as_ref()).await;
+    }
+    if let Err(err) = fs::rename(&tmp, &target).await {
+        let _ = fs::remove_file(&tmp).await;
+        return Err(err.into());
+    }
+
+    let metadata = fs::metadata(&target).await?;
+    let kind = if current.is_some() {
+        ChangeKind::Modified
+    } else {
+        ChangeKind::Created
+    };
+    state.events.fs_change(kind, target);
+
+    let version = FsVersion::of(&metadata);
+    let mut response = Json(FsWriteResponse {
+        ok: true,
+        path: query.path,
+        version,
+    })
+    .into_response();
+    if let Ok(value) = HeaderValue::from_str(&etag(&metadata)) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+    Ok(response)
+}
+
+/// The bytes to store for a UTF-8 `body`, in the requested encoding or the
+/// one of the file being replaced.
+async fn encode_body(
+    query: &FsWriteQuery,
+    target: &Path,
+    exists: bool,
+    body: Bytes,
+) -> Result<Bytes, AppError> {
+    let requested = query
+        .encoding
+        .as_deref()
+        .map(text::encoding_for_label)
+        .transpose()?;
+    let detected = if exists {
+        let path = target.to_path_buf();
+        tokio::task::spawn_blocking(move || text::detect_file(&path))
+            .await
+            .map_err(|e| AppError::Internal(format!("detect task failed: {e}")))??
+    } else {
+        None
+    };
+    // Binary files and new files without a requested encoding are stored as sent.
+    let Some(encoding) = requested.or(detected.map(|(encoding, _)| encoding)) else {
+        return Ok(body);
+    };
+    let bom = query.bom.unwrap_or(match detected {
+        Some((current, bom)) if current == encoding => bom,
+        _ => false,
+    });
+    if encoding == UTF_8 && !bom {
+        return Ok(body);
+    }
+    let text = std::str::from_utf8(&body)
+        .map_err(|_| AppError::BadRequest("content to re-encode must be UTF-8".into()))?;
+    Ok(Bytes::from(text::encode(text, encoding, bom)?))
+}
 
-    let mime = MimeGuess::from_path(&resolved).first_or_octet_stream();
-    let mut headers = HeaderMap::new();
-    headers.insert(
-        header::CONTENT_TYPE,
-        HeaderValue::from_str(mime.as_ref())
-            .map_err(|_| AppError::Internal("invalid content type".into()))?,
-    );
+async fn write_temp(
+    tmp: &Path,
+    body: &[u8],
+    current: Option<&std::fs::Metadata>,
+) -> std::io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(tmp)
+        .await?;
+    file.write_all(body).await?;
+    file.sync_all().await?;
+    if let Some(current) = current {
+        fs::set_permissions(tmp, current.permissions()).await?;
+    }
+    Ok(())
+}
 
-    Ok((headers, body))
+fn precondition_holds(
+    headers: &HeaderMap,
+    expected_mtime: Option<u64>,
+    current: Option<&std::fs::Metadata>,
+) -> bool {
+    if let Some(if_match) = headers.get(header::IF_MATCH).and_then(|v| v.to_str().ok()) {
+        let Some(current) = current else {
+            return false;
+        };
+        let tag = etag(current);
+        return if_match
+            .split(',')
+            .map(str::trim)
+            .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == tag);
+    }
+    if let Some(if_none_match) = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+    {
+        if if_none_match.trim() == "*" && current.is_some() {
+            return false;
+        }
+    }
+    match expected_mtime {
+        Some(expected) => current.is_some_and(|m| mtime_secs(m) == expected),
+        None => true,
+    }
+}
+
+async fn conflict_response(
+    target: &Path,
+    current: Option<&std::fs::Metadata>,
+) -> Result<Response, AppError> {
+    // Decoded like GET does, so files in other encodings show up too.
+    let content = match current {
+        Some(m) if m.len() <= CONFLICT_CONTENT_LIMIT => {
+            let path = target.to_path_buf();
+            tokio::task::spawn_blocking(move || {
+                text::read(&path, TextRange::Whole, None, CONFLICT_CONTENT_LIMIT)
+            })
+            .await
+            .ok()
+            .and_then(Result::ok)
+            .filter(|text| !text.binary)
+            .map(|text| text.content)
+        }
+        _ => None,
+    };
+    let body = FsConflictResponse {
+        ok: false,
+        error: "file was modified on disk".into(),
+        current: current.map(FsVersion::of),
+        content,
+    };
+    Ok((StatusCode::CONFLICT, Json(body)).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FsRawQuery {
+    pub path: String,
+    /// Ask the browser to save the file instead of displaying it.
+    #[serde(default)]
+    pub download: bool,
+}
+
+pub async fn raw_handler(
+    root: FsRoot,
+    Query(query): Query<FsRawQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let resolved = root.resolve_path(&query.path)?;
+    let file = resolved.clone();
+    let file_type = tokio::task::spawn_blocking(move || sniff::detect(&file))
+        .await
+        .map_err(|e| AppError::Internal(format!("sniff task failed: {e}")))?;
+    let disposition = if query.download {
+        Disposition::Attachment
+    } else {
+        Disposition::Inline
+    };
+    serve::serve_file(&headers, &resolved, &file_type.mime, disposition).await
 }
 
 #[derive(Debug, Deserialize)]
@@ -141,13 +823,280 @@ pub struct ChangeRootResponse {
     pub new_root: String,
 }
 
+/// Switch the calling session's browsing root. Other sessions keep theirs.
 pub async fn change_root_handler(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(req): Json<ChangeRootRequest>,
 ) -> Result<Json<ChangeRootResponse>, AppError> {
-    let new_root = state.fs.set_root(&req.new_root)?;
+    let Some(session_id) = extract_session_id_from_headers(&headers) else {
+        return Err(AppError::Unauthorized);
+    };
+    let new_root = state.fs.check_root(&req.new_root)?;
+    if !state.sessions.set_root(&session_id, new_root.clone()).await {
+        return Err(AppError::Unauthorized);
+    }
     Ok(Json(ChangeRootResponse {
         ok: true,
         new_root: new_root.to_string_lossy().to_string(),
     }))
 }
+
+#[derive(Debug, Serialize)]
+pub struct FsMutationResponse {
+    pub ok: bool,
+    pub path: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateFileRequest {
+    pub path: String,
+    #[serde(default)]
+    pub content: String,
+}
+
+pub async fn create_file_handler(
+    State(state): State<AppState>,
+    root: FsRoot,
+    Json(req): Json<CreateFileRequest>,
+) -> Result<Json<FsMutationResponse>, AppError> {
+    let target = root.resolve_new_path(&req.path)?;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&target)
+        .await?;
+    file.write_all(req.content.as_bytes()).await?;
+    file.flush().await?;
+
+    state.events.fs_change(ChangeKind::Created, target);
+    Ok(Json(FsMutationResponse {
+        ok: true,
+        path: req.path,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MkdirRequest {
+    pub path: String,
+}
+
+/// `mkdir -p`: creating a directory that already exists is not an error.
+pub async fn mkdir_handler(
+    State(state): State<AppState>,
+    root: FsRoot,
+    Json(req): Json<MkdirRequest>,
+) -> Result<Json<FsMutationResponse>, AppError> {
+    let target = root.resolve_new_path(&req.path)?;
+    // Watchers see the outermost directory that didn't exist before.
+    let created = target
+        .ancestors()
+        .take_while(|p| !p.exists())
+        .last()
+        .map(Path::to_path_buf);
+    fs::create_dir_all(&target).await?;
+
+    if let Some(created) = created {
+        state.events.fs_change(ChangeKind::Created, created);
+    }
+    Ok(Json(FsMutationResponse {
+        ok: true,
+        path: req.path,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MoveRequest {
+    pub from: String,
+    pub to: String,
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+pub async fn rename_handler(
+    State(state): State<AppState>,
+    root: FsRoot,
+    Json(req): Json<MoveRequest>,
+) -> Result<Json<FsMutationResponse>, AppError> {
+    let (from, to, replace) = resolve_move(&root, &req, Op::Write).await?;
+    let (src, dst) = (from.clone(), to.clone());
+    tokio::task::spawn_blocking(move || move_entry(&src, &dst, replace))
+        .await
+        .map_err(|e| AppError::Internal(format!("move task failed: {e}")))??;
+
+    state.events.publish(ServerEvent::FsChange {
+        kind: ChangeKind::Renamed,
+        path: to,
+        from: Some(from),
+    });
+    Ok(Json(FsMutationResponse {
+        ok: true,
+        path: req.to,
+    }))
+}
+
+pub async fn copy_handler(
+    State(state): State<AppState>,
+    root: FsRoot,
+    Json(req): Json<MoveRequest>,
+) -> Result<Json<FsMutationResponse>, AppError> {
+    let (from, to, replace) = resolve_move(&root, &req, Op::Read).await?;
+    let dst = to.clone();
+    tokio::task::spawn_blocking(move || copy_into_place(&from, &dst, replace))
+        .await
+        .map_err(|e| AppError::Internal(format!("copy task failed: {e}")))??;
+
+    state.events.fs_change(ChangeKind::Created, to);
+    Ok(Json(FsMutationResponse {
+        ok: true,
+        path: req.to,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteRequest {
+    pub path: String,
+    #[serde(default)]
+    pub recursive: bool,
+}
+
+pub async fn delete_handler(
+    State(state): State<AppState>,
+    root: FsRoot,
+    Json(req): Json<DeleteRequest>,
+) -> Result<Json<FsMutationResponse>, AppError> {
+    let target = root.resolve_new_path(&req.path)?;
+    let metadata = fs::symlink_metadata(&target).await?;
+    if metadata.is_dir() {
+        if req.recursive {
+            check_tree(&root, &target, Op::Write).await?;
+            fs::remove_dir_all(&target).await?;
+        } else {
+            fs::remove_dir(&target).await?;
+        }
+    } else {
+        fs::remove_file(&target).await?;
+    }
+
+    state.events.fs_change(ChangeKind::Deleted, target);
+    Ok(Json(FsMutationResponse {
+        ok: true,
+        path: req.path,
+    }))
+}
+
+/// Resolve both ends of a rename or copy. The source is not followed if it is
+/// a symlink, and an existing target may only be replaced with `overwrite`;
+/// the returned flag says whether there is one to replace.
+///
+/// The whole source tree needs `source_op` access: reading for a copy,
+/// writing for a move.
+async fn resolve_move(
+    root: &FsRoot,
+    req: &MoveRequest,
+    source_op: Op,
+) -> Result<(PathBuf, PathBuf, bool), AppError> {
+    let from = root.resolve_entry(&req.from)?;
+    let to = root.resolve_new_path(&req.to)?;
+    if from == to {
+        return Err(AppError::BadRequest(
+            "source and target are the same".into(),
+        ));
+    }
+    let source = fs::symlink_metadata(&from).await?;
+    if source.is_dir() && to.starts_with(&from) {
+        return Err(AppError::BadRequest(
+            "cannot move or copy a directory into itself".into(),
+        ));
+    }
+    check_tree(root, &from, source_op).await?;
+    let replace = fs::symlink_metadata(&to).await.is_ok();
+    if replace {
+        if !req.overwrite {
+            return Err(AppError::Conflict(format!("{} already exists", req.to)));
+        }
+        check_tree(root, &to, Op::Write).await?;
+    }
+    Ok((from, to, replace))
+}
+
+/// Move `from` to `to`. An existing `to` (when `replace` is set) is only
+/// removed once the new data sits next to it, so a failed move loses nothing.
+fn move_entry(from: &Path, to: &Path, replace: bool) -> std::io::Result<()> {
+    let result = if replace && (is_dir(from) || is_dir(to)) {
+        // rename() can't replace a non-empty directory or change the entry
+        // type: move the source beside the target, then swap it in.
+        let staged = staging_path(to);
+        std::fs::rename(from, &staged).and_then(|()| {
+            swap_in(&staged, to).inspect_err(|_| {
+                let _ = std::fs::rename(&staged, from);
+            })
+        })
+    } else {
+        // Replaces a file atomically
+        std::fs::rename(from, to)
+    };
+    match result {
+        // The target is on another filesystem, so copy and delete instead.
+        Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_into_place(from, to, replace)?;
+            remove_entry(from)
+        }
+        result => result,
+    }
+}
+
+/// Copy `from` beside `to` and move the complete copy into place, replacing
+/// an existing `to` only then.
+fn copy_into_place(from: &Path, to: &Path, replace: bool) -> std::io::Result<()> {
+    let staged = staging_path(to);
+    let result = copy_recursive(from, &staged).and_then(|()| {
+        if replace {
+            swap_in(&staged, to)
+        } else {
+            std::fs::rename(&staged, to)
+        }
+    });
+    if result.is_err() && std::fs::symlink_metadata(&staged).is_ok() {
+        let _ = remove_entry(&staged);
+    }
+    result
+}
+
+/// Replace `to` with `staged`, a sibling on the same filesystem.
+fn swap_in(staged: &Path, to: &Path) -> std::io::Result<()> {
+    if is_dir(staged) || is_dir(to) {
+        remove_entry(to)?;
+    }
+    std::fs::rename(staged, to)
+}
+
+/// A hidden sibling of `path` to put new data in before it takes `path`'s place.
+fn staging_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.{}.tmp", Uuid::new_v4().simple()))
+}
+
+fn is_dir(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok_and(|m| m.is_dir())
+}
+
+/// Check access to a whole tree off the async runtime; large trees take a
+/// while to walk.
+async fn check_tree(root: &FsRoot, path: &Path, op: Op) -> Result<(), AppError> {
+    let (root, path) = (root.clone(), path.to_path_buf());
+    tokio::task::spawn_blocking(move || root.check_tree(&path, op))
+        .await
+        .map_err(|e| AppError::Internal(format!("access check failed: {e}")))?
+}
+
+fn remove_entry(path: &Path) -> std::io::Result<()> {
+    if std::fs::symlink_metadata(path)?.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
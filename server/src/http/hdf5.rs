@@ -1,4 +1,4 @@
-use crate::{error::AppError, state::AppState};
+use crate::{error::AppError, fs::FsRoot, state::AppState};
 use axum::{
     extract::{Query, State},
     routing::get,
@@ -48,13 +48,14 @@ pub struct Hdf5PreviewResponse {
 
 pub async fn preview_handler(
     State(state): State<AppState>,
+    root: FsRoot,
     Query(query): Query<Hdf5PreviewQuery>,
 ) -> Result<Json<Hdf5PreviewResponse>, AppError> {
     if !state.config.features.enable_hdf5 {
         return Err(AppError::Internal("HDF5 support is disabled".into()));
     }
 
-    let resolved = state.fs.resolve_path(&query.path)?;
+    let resolved = root.resolve_path(&query.path)?;
     let data_limit = query.data_limit.unwrap_or(5);
 
     let root_node = tokio::task::spawn_blocking(move || {
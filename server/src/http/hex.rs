@@ -0,0 +1,116 @@
+use crate::{
+    error::AppError,
+    fs::FsRoot,
+    sniff::{self, Magic},
+    state::AppState,
+};
+use axum::{extract::Query, routing::get, Json, Router};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom};
+
+const DEFAULT_LENGTH: u64 = 512;
+const MAX_LENGTH: u64 = 64 * 1024;
+const DEFAULT_WIDTH: usize = 16;
+const MAX_WIDTH: usize = 64;
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/api/fs/hex", get(hex_handler))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FsHexQuery {
+    pub path: String,
+    #[serde(default)]
+    pub offset: u64,
+    pub length: Option<u64>,
+    /// Bytes per row.
+    pub width: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HexRow {
+    pub offset: u64,
+    /// Space-separated two-digit hex values.
+    pub hex: String,
+    /// Printable ASCII, with `.` for everything else.
+    pub ascii: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FsHexResponse {
+    pub ok: bool,
+    pub path: String,
+    pub size: u64,
+    pub offset: u64,
+    /// Bytes returned; less than requested at the end of the file.
+    pub length: u64,
+    pub width: usize,
+    pub rows: Vec<HexRow>,
+    /// The file type recognised from the magic number at its start.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub magic: Option<Magic>,
+}
+
+/// Hex dump of a window of any file. Only the window and the first bytes
+/// (for the magic number) are read, so headers of huge files are cheap.
+pub async fn hex_handler(
+    root: FsRoot,
+    Query(query): Query<FsHexQuery>,
+) -> Result<Json<FsHexResponse>, AppError> {
+    let resolved = root.resolve_path(&query.path)?;
+    let length = query.length.unwrap_or(DEFAULT_LENGTH).min(MAX_LENGTH);
+    let width = query.width.unwrap_or(DEFAULT_WIDTH).clamp(1, MAX_WIDTH);
+    let offset = query.offset;
+
+    let (size, data, magic) = tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&resolved)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(AppError::BadRequest("not a file".into()));
+        }
+        let mut head = Vec::with_capacity(sniff::SNIFF_LEN);
+        (&mut file)
+            .take(sniff::SNIFF_LEN as u64)
+            .read_to_end(&mut head)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut data = Vec::with_capacity(length as usize);
+        file.take(length).read_to_end(&mut data)?;
+        Ok((metadata.len(), data, sniff::magic(&head)))
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("hex task failed: {e}")))??;
+
+    let rows = data
+        .chunks(width)
+        .zip((offset..).step_by(width))
+        .map(|(bytes, offset)| HexRow {
+            offset,
+            hex: bytes
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<Vec<_>>()
+                .join(" "),
+            ascii: bytes
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect(),
+        })
+        .collect();
+
+    Ok(Json(FsHexResponse {
+        ok: true,
+        path: query.path,
+        size,
+        offset,
+        length: data.len() as u64,
+        width,
+        rows,
+        magic,
+    }))
+}
@@ -0,0 +1,50 @@
+use crate::{
+    error::AppError,
+    fs::FsRoot,
+    image_info::{self, ImageInfo},
+    state::AppState,
+};
+use axum::{extract::Query, routing::get, Json, Router};
+use serde::{Deserialize, Serialize};
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/api/fs/image-info", get(image_info_handler))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FsImageInfoQuery {
+    pub path: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FsImageInfoResponse {
+    pub ok: bool,
+    pub path: String,
+    pub size: u64,
+    pub image: ImageInfo,
+}
+
+/// Dimensions, color, frames, EXIF, XMP and PNG text of an image, without
+/// decoding its pixels.
+pub async fn image_info_handler(
+    root: FsRoot,
+    Query(query): Query<FsImageInfoQuery>,
+) -> Result<Json<FsImageInfoResponse>, AppError> {
+    let resolved = root.resolve_path(&query.path)?;
+    let (size, image) = tokio::task::spawn_blocking(move || {
+        let metadata = std::fs::metadata(&resolved)?;
+        if !metadata.is_file() {
+            return Err(AppError::BadRequest("not a file".into()));
+        }
+        Ok((metadata.len(), image_info::read(&resolved)?))
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("image info task failed: {e}")))??;
+
+    Ok(Json(FsImageInfoResponse {
+        ok: true,
+        path: query.path,
+        size,
+        image,
+    }))
+}
@@ -0,0 +1,81 @@
+use crate::{error::AppError, fs::FsRoot, state::AppState};
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_COUNT: usize = 200;
+const MAX_COUNT: usize = 10_000;
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/api/fs/lines", get(lines_handler))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FsLinesQuery {
+    pub path: String,
+    /// 1-based number of the first line.
+    pub from: Option<u64>,
+    pub count: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FsLinesResponse {
+    pub ok: bool,
+    pub path: String,
+    pub from: u64,
+    pub lines: Vec<String>,
+    /// Numbers of the lines cut off at 64 KiB.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub truncated: Vec<u64>,
+    pub total_lines: u64,
+    /// False while indexing, when `total_lines` is an estimate.
+    pub exact: bool,
+    /// The window is past the part indexed so far; retry later.
+    pub pending: bool,
+    /// Bytes indexed so far, out of `size`.
+    pub indexed: u64,
+    pub size: u64,
+}
+
+/// A window of lines from a text file of any size. The first request for a
+/// large file starts building its line index in the background; progress is
+/// reported here and as `line_index_progress` events on `/ws/system`.
+pub async fn lines_handler(
+    State(state): State<AppState>,
+    root: FsRoot,
+    Query(query): Query<FsLinesQuery>,
+) -> Result<Json<FsLinesResponse>, AppError> {
+    let resolved = root.resolve_path(&query.path)?;
+    if !tokio::fs::metadata(&resolved).await?.is_file() {
+        return Err(AppError::BadRequest("not a file".into()));
+    }
+    let from = query.from.unwrap_or(1).max(1);
+    let count = query.count.unwrap_or(DEFAULT_COUNT).clamp(1, MAX_COUNT);
+    let indexes = state.lines.clone();
+    let window = tokio::task::spawn_blocking(move || indexes.window(&resolved, from, count))
+        .await
+        .map_err(|e| AppError::Internal(format!("lines task failed: {e}")))??;
+
+    let truncated = window
+        .lines
+        .iter()
+        .zip(from..)
+        .filter(|((_, cut), _)| *cut)
+        .map(|(_, number)| number)
+        .collect();
+    Ok(Json(FsLinesResponse {
+        ok: true,
+        path: query.path,
+        from,
+        lines: window.lines.into_iter().map(|(line, _)| line).collect(),
+        truncated,
+        total_lines: window.total_lines,
+        exact: window.exact,
+        pending: window.pending,
+        indexed: window.indexed,
+        size: window.size,
+    }))
+}
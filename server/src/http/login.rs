@@ -81,14 +81,7 @@ pub async fn auth_status_handler(
     State(state): State<AppState>,
     headers: HeaderMap,
 ) -> Result<Json<AuthStatusResponse>, AppError> {
-    // Extract session ID from cookies
-    let session_id = extract_session_id_from_headers(&headers);
-
-    let authenticated = if let Some(session_id) = session_id {
-        state.sessions.validate(&session_id).await
-    } else {
-        false
-    };
+    let authenticated = is_authenticated(&state, &headers).await;
 
     Ok(Json(AuthStatusResponse { authenticated }))
 }
@@ -116,8 +109,16 @@ pub async fn logout_handler(
     Ok((headers, Json(LogoutResponse { ok: true })))
 }
 
+/// Whether the request carries a valid session cookie.
+pub(crate) async fn is_authenticated(state: &AppState, headers: &HeaderMap) -> bool {
+    match extract_session_id_from_headers(headers) {
+        Some(session_id) => state.sessions.validate(&session_id).await,
+        None => false,
+    }
+}
+
 // Helper function to extract session ID from cookies
-fn extract_session_id_from_headers(headers: &HeaderMap) -> Option<String> {
+pub(crate) fn extract_session_id_from_headers(headers: &HeaderMap) -> Option<String> {
     if let Some(cookie_header) = headers.get(header::COOKIE) {
         let cookies = cookie_header.to_str().ok().unwrap_or("").split(';');
         for cookie in cookies {
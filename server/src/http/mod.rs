@@ -1,6 +1,20 @@
+pub mod archive;
+pub mod convert;
+pub mod find;
 pub mod fs;
 pub mod hdf5;
+pub mod hex;
+pub mod image_info;
+pub mod lines;
 pub mod login;
+pub mod proxy;
+pub mod search;
+pub mod serve;
+pub mod site;
+pub mod terminal;
+pub mod thumbnail;
+pub mod tmux;
+pub mod upload;
 
 use crate::state::AppState;
 use axum::Router;
@@ -10,4 +24,17 @@ pub fn router() -> Router<AppState> {
         .merge(login::router())
         .merge(fs::router())
         .merge(hdf5::router())
+        .merge(terminal::router())
+        .merge(tmux::router())
+        .merge(proxy::router())
+        .merge(site::router())
+        .merge(upload::router())
+        .merge(archive::router())
+        .merge(search::router())
+        .merge(find::router())
+        .merge(lines::router())
+        .merge(hex::router())
+        .merge(thumbnail::router())
+        .merge(convert::router())
+        .merge(image_info::router())
 }
\ No newline at end of file
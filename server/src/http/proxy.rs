@@ -0,0 +1,202 @@
+use crate::{
+    error::AppError,
+    http::login,
+    proxy::{self, ListeningPort},
+    state::AppState,
+};
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
+use axum::{
+    extract::{Path, Request, State, WebSocketUpgrade},
+    http::{header, HeaderMap, Uri},
+    response::{IntoResponse, Redirect, Response},
+    routing::{any, get},
+    Json, Router,
+};
+use futures::{SinkExt, StreamExt};
+use serde::Serialize;
+use std::collections::HashMap;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::{
+    self,
+    client::IntoClientRequest,
+    protocol::{frame::coding::CloseCode, CloseFrame as UpstreamCloseFrame},
+};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+type UpstreamSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/api/proxy/ports", get(ports_handler))
+        .route("/proxy/:port", any(proxy_root_handler))
+        .route("/proxy/:port/", any(proxy_handler))
+        .route("/proxy/:port/*rest", any(proxy_handler))
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProxyPortsResponse {
+    pub ok: bool,
+    pub ports: Vec<ListeningPort>,
+}
+
+pub async fn ports_handler(
+    State(state): State<AppState>,
+) -> Result<Json<ProxyPortsResponse>, AppError> {
+    let config = state.config.clone();
+    let ports = tokio::task::spawn_blocking(move || proxy::proxyable_ports(&config))
+        .await
+        .map_err(|e| AppError::Internal(format!("port scan failed: {e}")))?;
+    Ok(Json(ProxyPortsResponse { ok: true, ports }))
+}
+
+/// `/proxy/{port}` without the trailing slash would resolve relative URLs
+/// against `/proxy/`, so send the browser to the directory form first.
+pub async fn proxy_root_handler(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> Result<Response, AppError> {
+    let port = authorize(&state, &params, &headers).await?;
+    let query = uri.query().map(|q| format!("?{q}")).unwrap_or_default();
+    Ok(Redirect::temporary(&format!("{}/{query}", proxy::prefix(port))).into_response())
+}
+
+pub async fn proxy_handler(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+    ws: Option<WebSocketUpgrade>,
+    req: Request,
+) -> Result<Response, AppError> {
+    let port = authorize(&state, &params, req.headers()).await?;
+
+    // Use the raw path so percent-encoding reaches the upstream untouched.
+    let prefix = proxy::prefix(port);
+    let path = req.uri().path().strip_prefix(&prefix).unwrap_or("/");
+    let target = match req.uri().query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_string(),
+    };
+
+    if let Some(ws) = ws {
+        let (upstream, protocol) = connect_upstream(port, &target, req.headers())
+            .await
+            .map_err(|e| AppError::BadGateway(format!("{e:#}")))?;
+        let ws = match protocol {
+            Some(protocol) => ws.protocols([protocol]),
+            None => ws,
+        };
+        return Ok(ws.on_upgrade(move |socket| pump(socket, upstream, port)));
+    }
+
+    state
+        .proxy
+        .forward(port, &target, req)
+        .await
+        .map_err(|e| AppError::BadGateway(format!("{e:#}")))
+}
+
+async fn authorize(
+    state: &AppState,
+    params: &HashMap<String, String>,
+    headers: &HeaderMap,
+) -> Result<u16, AppError> {
+    if !login::is_authenticated(state, headers).await {
+        return Err(AppError::Unauthorized);
+    }
+    let port = params
+        .get("port")
+        .and_then(|p| p.parse::<u16>().ok())
+        .ok_or_else(|| AppError::BadRequest("invalid port".into()))?;
+    if !proxy::is_proxyable(&state.config, port) {
+        return Err(AppError::Forbidden(format!("port {port} is not proxied")));
+    }
+    Ok(port)
+}
+
+async fn connect_upstream(
+    port: u16,
+    target: &str,
+    headers: &HeaderMap,
+) -> anyhow::Result<(UpstreamSocket, Option<String>)> {
+    let mut request = format!("ws://127.0.0.1:{port}{target}").into_client_request()?;
+
+    let mut forwarded = headers.clone();
+    proxy::prepare_request_headers(&mut forwarded, port);
+    // tungstenite performs its own handshake and does not support compression.
+    for name in [
+        header::HOST,
+        header::SEC_WEBSOCKET_KEY,
+        header::SEC_WEBSOCKET_VERSION,
+        header::SEC_WEBSOCKET_EXTENSIONS,
+    ] {
+        forwarded.remove(name);
+    }
+    request.headers_mut().extend(forwarded);
+
+    let (socket, response) = tokio_tungstenite::connect_async(request).await?;
+    let protocol = response
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    Ok((socket, protocol))
+}
+
+async fn pump(socket: WebSocket, upstream: UpstreamSocket, port: u16) {
+    tracing::debug!("proxied websocket opened (port {port})");
+
+    let (mut client_tx, mut client_rx) = socket.split();
+    let (mut upstream_tx, mut upstream_rx) = upstream.split();
+
+    loop {
+        tokio::select! {
+            msg = client_rx.next() => {
+                let Some(Ok(msg)) = msg else { break };
+                let closing = matches!(msg, Message::Close(_));
+                if upstream_tx.send(to_upstream(msg)).await.is_err() || closing {
+                    break;
+                }
+            }
+            msg = upstream_rx.next() => {
+                let Some(Ok(msg)) = msg else { break };
+                let Some(msg) = from_upstream(msg) else { continue };
+                let closing = matches!(msg, Message::Close(_));
+                if client_tx.send(msg).await.is_err() || closing {
+                    break;
+                }
+            }
+        }
+    }
+
+    let _ = client_tx.close().await;
+    let _ = upstream_tx.close().await;
+    tracing::debug!("proxied websocket closed (port {port})");
+}
+
+fn to_upstream(msg: Message) -> tungstenite::Message {
+    match msg {
+        Message::Text(text) => tungstenite::Message::Text(text),
+        Message::Binary(data) => tungstenite::Message::Binary(data),
+        Message::Ping(data) => tungstenite::Message::Ping(data),
+        Message::Pong(data) => tungstenite::Message::Pong(data),
+        Message::Close(frame) => tungstenite::Message::Close(frame.map(|f| UpstreamCloseFrame {
+            code: CloseCode::from(f.code),
+            reason: f.reason,
+        })),
+    }
+}
+
+fn from_upstream(msg: tungstenite::Message) -> Option<Message> {
+    Some(match msg {
+        tungstenite::Message::Text(text) => Message::Text(text),
+        tungstenite::Message::Binary(data) => Message::Binary(data),
+        tungstenite::Message::Ping(data) => Message::Ping(data),
+        tungstenite::Message::Pong(data) => Message::Pong(data),
+        tungstenite::Message::Close(frame) => Message::Close(frame.map(|f| CloseFrame {
+            code: f.code.into(),
+            reason: f.reason,
+        })),
+        tungstenite::Message::Frame(_) => return None,
+    })
+}
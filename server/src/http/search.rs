@@ -0,0 +1,113 @@
+use crate::{
+    error::AppError,
+    fs::FsRoot,
+    globs::PathGlobs,
+    search::{self, SearchOptions},
+    state::AppState,
+};
+use axum::{
+    body::{Body, Bytes},
+    extract::Query,
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use regex::RegexBuilder;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+const DEFAULT_MAX_RESULTS: usize = 500;
+const MAX_RESULTS_LIMIT: usize = 10_000;
+const MAX_CONTEXT: usize = 10;
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/api/fs/search", get(search_handler))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FsSearchQuery {
+    pub q: String,
+    pub path: Option<String>,
+    /// Treat `q` as a regular expression instead of a literal.
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(default)]
+    pub case_sensitive: bool,
+    pub show_hidden: Option<bool>,
+    /// Comma-separated globs; only matching files are searched.
+    pub include: Option<String>,
+    /// Comma-separated globs for files and directories to skip.
+    pub exclude: Option<String>,
+    pub context: Option<usize>,
+    pub max_results: Option<usize>,
+}
+
+/// Find in files. Streams one JSON object per line: a `match` for every
+/// matching line as it is found, then a final `done`. Closing the request
+/// stops the search.
+pub async fn search_handler(
+    root: FsRoot,
+    Query(query): Query<FsSearchQuery>,
+) -> Result<Response, AppError> {
+    if query.q.is_empty() {
+        return Err(AppError::BadRequest("empty search query".into()));
+    }
+    let pattern = if query.regex {
+        query.q.clone()
+    } else {
+        regex::escape(&query.q)
+    };
+    let pattern = RegexBuilder::new(&pattern)
+        .case_insensitive(!query.case_sensitive)
+        .build()
+        .map_err(|e| AppError::BadRequest(format!("invalid regex: {e}")))?;
+
+    let dir = root.resolve_path(query.path.as_deref().unwrap_or_default())?;
+    let options = SearchOptions {
+        pattern,
+        globs: PathGlobs::new(query.include.as_deref(), query.exclude.as_deref())?,
+        show_hidden: query.show_hidden.unwrap_or(false),
+        context: query.context.unwrap_or(0).min(MAX_CONTEXT),
+        max_results: query
+            .max_results
+            .unwrap_or(DEFAULT_MAX_RESULTS)
+            .clamp(1, MAX_RESULTS_LIMIT),
+    };
+
+    let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(32);
+    tokio::task::spawn_blocking(move || {
+        let mut cancelled = false;
+        search::search(
+            &root,
+            &dir,
+            &options,
+            || tx.is_closed(),
+            |event| {
+                let mut line = match serde_json::to_vec(&event) {
+                    Ok(line) => line,
+                    Err(err) => {
+                        tracing::warn!("search: failed to encode result: {err}");
+                        return true;
+                    }
+                };
+                line.push(b'\n');
+                cancelled = tx.blocking_send(Ok(Bytes::from(line))).is_err();
+                !cancelled
+            },
+        );
+        if cancelled || tx.is_closed() {
+            tracing::debug!("search in {} cancelled", dir.display());
+        }
+    });
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/x-ndjson"),
+    );
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
+    Ok((headers, Body::from_stream(stream)).into_response())
+}
@@ -0,0 +1,306 @@
+//! File responses with validators and byte ranges, shared by the handlers
+//! that stream files to the browser.
+
+use crate::{error::AppError, fs::etag};
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+/// More ranges than this in one request are served as the whole file.
+const MAX_RANGES: usize = 32;
+
+/// RFC 5987 `attr-char`s that may appear unencoded in `filename*`.
+const FILENAME_ENCODE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    Inline,
+    Attachment,
+}
+
+/// Respond with the file at `path`, honouring `Range`, `If-Range`,
+/// `If-None-Match` and `If-Modified-Since` from the request.
+pub async fn serve_file(
+    request: &HeaderMap,
+    path: &Path,
+    mime: &str,
+    disposition: Disposition,
+) -> Result<Response, AppError> {
+    let metadata = fs::metadata(path).await?;
+    if metadata.is_dir() {
+        return Err(AppError::BadRequest("path is a directory".into()));
+    }
+    let len = metadata.len();
+    let etag = etag(&metadata);
+    let modified = metadata.modified().ok().map(truncate_to_secs);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ETAG, header_value(&etag)?);
+    if let Some(modified) = modified {
+        headers.insert(
+            header::LAST_MODIFIED,
+            header_value(&httpdate::fmt_http_date(modified))?,
+        );
+    }
+    // Always revalidate; unchanged files cost a 304 instead of a download.
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+
+    if not_modified(request, &etag, modified) {
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Some(name) = path.file_name() {
+        headers.insert(
+            header::CONTENT_DISPOSITION,
+            header_value(&content_disposition(&name.to_string_lossy(), disposition))?,
+        );
+    }
+
+    let ranges = match request.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        Some(range) if if_range_holds(request, &etag, modified) => parse_ranges(range, len),
+        _ => RangeRequest::Full,
+    };
+
+    match ranges {
+        RangeRequest::Full => {
+            headers.insert(header::CONTENT_TYPE, header_value(mime)?);
+            headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+            // A file still being appended to must not outgrow Content-Length.
+            let file = fs::File::open(path).await?;
+            let body = Body::from_stream(ReaderStream::new(file.take(len)));
+            Ok((StatusCode::OK, headers, body).into_response())
+        }
+        RangeRequest::Unsatisfiable => {
+            headers.insert(
+                header::CONTENT_RANGE,
+                header_value(&format!("bytes */{len}"))?,
+            );
+            Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response())
+        }
+        RangeRequest::Ranges(ranges) if ranges.len() == 1 => {
+            let (start, end) = ranges[0];
+            headers.insert(header::CONTENT_TYPE, header_value(mime)?);
+            headers.insert(
+                header::CONTENT_RANGE,
+                header_value(&format!("bytes {start}-{end}/{len}"))?,
+            );
+            headers.insert(header::CONTENT_LENGTH, HeaderValue::from(end - start + 1));
+            let body = Body::from_stream(file_segment(path.to_path_buf(), start, end - start + 1));
+            Ok((StatusCode::PARTIAL_CONTENT, headers, body).into_response())
+        }
+        RangeRequest::Ranges(ranges) => {
+            let boundary = uuid::Uuid::new_v4().simple().to_string();
+            let mut segments = Vec::with_capacity(ranges.len() * 2 + 1);
+            let mut total = 0u64;
+            for (start, end) in ranges {
+                let part_header = format!(
+                    "\r\n--{boundary}\r\nContent-Type: {mime}\r\nContent-Range: bytes {start}-{end}/{len}\r\n\r\n"
+                );
+                total += part_header.len() as u64 + (end - start + 1);
+                segments.push(Segment::Bytes(Bytes::from(part_header)));
+                segments.push(Segment::File {
+                    start,
+                    len: end - start + 1,
+                });
+            }
+            let closing = format!("\r\n--{boundary}--\r\n");
+            total += closing.len() as u64;
+            segments.push(Segment::Bytes(Bytes::from(closing)));
+
+            headers.insert(
+                header::CONTENT_TYPE,
+                header_value(&format!("multipart/byteranges; boundary={boundary}"))?,
+            );
+            headers.insert(header::CONTENT_LENGTH, HeaderValue::from(total));
+            let body = Body::from_stream(multipart_body(path.to_path_buf(), segments));
+            Ok((StatusCode::PARTIAL_CONTENT, headers, body).into_response())
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RangeRequest {
+    Full,
+    /// Inclusive `(start, end)` byte offsets, in request order.
+    Ranges(Vec<(u64, u64)>),
+    Unsatisfiable,
+}
+
+enum Segment {
+    Bytes(Bytes),
+    File { start: u64, len: u64 },
+}
+
+/// Parse a `Range` header against a file of `len` bytes. Malformed or
+/// non-byte ranges fall back to the full file, as RFC 9110 allows.
+/// Overlapping and adjacent ranges are merged, so repeating a range can't
+/// make the response larger than the file.
+fn parse_ranges(header: &str, len: u64) -> RangeRequest {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    if spec.trim().is_empty() {
+        return RangeRequest::Full;
+    }
+
+    let mut ranges = Vec::new();
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let Some((start, end)) = part.split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let range = match (start.trim(), end.trim()) {
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => None,
+                Ok(n) if len > 0 => Some((len.saturating_sub(n), len - 1)),
+                Ok(_) => None,
+                Err(_) => return RangeRequest::Full,
+            },
+            (start, end) => {
+                let Ok(start) = start.parse::<u64>() else {
+                    return RangeRequest::Full;
+                };
+                let end = match end {
+                    "" => u64::MAX,
+                    end => match end.parse::<u64>() {
+                        Ok(end) if end >= start => end,
+                        _ => return RangeRequest::Full,
+                    },
+                };
+                (start < len).then(|| (start, end.min(len - 1)))
+            }
+        };
+        ranges.extend(range);
+    }
+
+    let ranges = coalesce(ranges);
+    if ranges.is_empty() {
+        RangeRequest::Unsatisfiable
+    } else if ranges.len() > MAX_RANGES {
+        RangeRequest::Full
+    } else {
+        RangeRequest::Ranges(ranges)
+    }
+}
+
+/// Merge overlapping and adjacent inclusive ranges, in ascending order.
+fn coalesce(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+fn not_modified(request: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = request
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+    {
+        return if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+    match (request_date(request, header::IF_MODIFIED_SINCE), modified) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false,
+    }
+}
+
+/// A range is only served if `If-Range` (when present) still matches.
+fn if_range_holds(request: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    let Some(if_range) = request.get(header::IF_RANGE).and_then(|v| v.to_str().ok()) else {
+        return true;
+    };
+    if if_range.starts_with('"') {
+        return if_range == etag;
+    }
+    match (httpdate::parse_http_date(if_range).ok(), modified) {
+        (Some(date), Some(modified)) => modified == date,
+        _ => false,
+    }
+}
+
+fn request_date(request: &HeaderMap, name: header::HeaderName) -> Option<SystemTime> {
+    request
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+}
+
+/// HTTP dates have one-second resolution.
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+pub fn content_disposition(name: &str, disposition: Disposition) -> String {
+    let kind = match disposition {
+        Disposition::Inline => "inline",
+        Disposition::Attachment => "attachment",
+    };
+    let fallback: String = name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii() && !c.is_ascii_control() => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded = utf8_percent_encode(name, FILENAME_ENCODE);
+    format!("{kind}; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+fn multipart_body(
+    path: PathBuf,
+    segments: Vec<Segment>,
+) -> impl futures::Stream<Item = std::io::Result<Bytes>> {
+    stream::iter(segments).flat_map(move |segment| match segment {
+        Segment::Bytes(bytes) => stream::once(async move { Ok(bytes) }).boxed(),
+        Segment::File { start, len } => file_segment(path.clone(), start, len),
+    })
+}
+
+fn file_segment(path: PathBuf, start: u64, len: u64) -> BoxStream<'static, std::io::Result<Bytes>> {
+    stream::once(async move {
+        let mut file = fs::File::open(&path).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        Ok::<_, std::io::Error>(ReaderStream::new(file.take(len)))
+    })
+    .try_flatten()
+    .boxed()
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, AppError> {
+    HeaderValue::from_str(value).map_err(|_| AppError::Internal("invalid header value".into()))
+}
@@ -0,0 +1,167 @@
+use crate::{access::Op, error::AppError, fs::FsRoot, http::login, site, state::AppState};
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderValue, Uri},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Redirect, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use futures::Stream;
+use mime_guess::MimeGuess;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use tokio::fs;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::io::ReaderStream;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/api/site/mount", post(mount_handler))
+        .route("/api/site/unmount", post(unmount_handler))
+        .route("/api/site/:token/events", get(events_handler))
+        .route("/site/:token", get(site_root_handler))
+        .route("/site/:token/", get(site_handler))
+        .route("/site/:token/*path", get(site_handler))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SiteMountRequest {
+    pub path: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SiteMountResponse {
+    pub ok: bool,
+    pub token: String,
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SiteUnmountRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SiteUnmountResponse {
+    pub ok: bool,
+}
+
+pub async fn mount_handler(
+    State(state): State<AppState>,
+    root: FsRoot,
+    Json(req): Json<SiteMountRequest>,
+) -> Result<Json<SiteMountResponse>, AppError> {
+    let dir = root.resolve_path(&req.path)?;
+    if !dir.is_dir() {
+        return Err(AppError::BadRequest("path is not a directory".into()));
+    }
+    let token = state.sites.mount(dir).await;
+    Ok(Json(SiteMountResponse {
+        ok: true,
+        url: format!("/site/{token}/"),
+        token,
+    }))
+}
+
+pub async fn unmount_handler(
+    State(state): State<AppState>,
+    Json(req): Json<SiteUnmountRequest>,
+) -> Result<Json<SiteUnmountResponse>, AppError> {
+    let ok = state.sites.unmount(&req.token).await;
+    Ok(Json(SiteUnmountResponse { ok }))
+}
+
+pub async fn site_root_handler(Path(token): Path<String>) -> Redirect {
+    Redirect::permanent(&format!("/site/{token}/"))
+}
+
+pub async fn site_handler(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> Result<Response, AppError> {
+    if !login::is_authenticated(&state, &headers).await {
+        return Err(AppError::Unauthorized);
+    }
+    let token = params.get("token").cloned().unwrap_or_default();
+    let rest = params.get("path").map(String::as_str).unwrap_or_default();
+    let mount = state
+        .sites
+        .get(&token)
+        .await
+        .ok_or_else(|| AppError::NotFound(format!("unknown site: {token}")))?;
+
+    let mut file = site::resolve(&mount, rest).map_err(|_| not_found(rest))?;
+    state.fs.rules().check(&file, Op::Read)?;
+    if file.is_dir() {
+        // Relative links in an index page need the directory form of the URL.
+        if !uri.path().ends_with('/') {
+            return Ok(Redirect::permanent(&format!("{}/", uri.path())).into_response());
+        }
+        file.push("index.html");
+        if !file.is_file() {
+            return Err(not_found(rest));
+        }
+        state.fs.rules().check(&file, Op::Read)?;
+    }
+
+    let mime = MimeGuess::from_path(&file).first_or_octet_stream();
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(mime.as_ref())
+            .map_err(|_| AppError::Internal("invalid content type".into()))?,
+    );
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+
+    let body = if mime.essence_str() == "text/html" {
+        let html = fs::read(&file).await?;
+        Body::from(site::inject_live_reload(&html, &token))
+    } else {
+        Body::from_stream(ReaderStream::new(fs::File::open(&file).await?))
+    };
+
+    Ok((headers, body).into_response())
+}
+
+/// Server-sent `reload` events whenever something under the mounted
+/// directory changes.
+pub async fn events_handler(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    if !login::is_authenticated(&state, &headers).await {
+        return Err(AppError::Unauthorized);
+    }
+    if !state.config.features.enable_watch {
+        return Err(AppError::BadRequest("file watching disabled".into()));
+    }
+    let reload = state
+        .sites
+        .subscribe(&token)
+        .await
+        .map_err(|e| AppError::Internal(format!("failed to watch site: {e}")))?
+        .ok_or_else(|| AppError::NotFound(format!("unknown site: {token}")))?;
+
+    // Ends once the site is unmounted; a lagging client still just reloads.
+    let stream = futures::stream::unfold(reload, |mut reload| async move {
+        match reload.recv().await {
+            Ok(()) | Err(RecvError::Lagged(_)) => {
+                Some((Ok(Event::default().event("reload").data("")), reload))
+            }
+            Err(RecvError::Closed) => None,
+        }
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+fn not_found(path: &str) -> AppError {
+    AppError::NotFound(format!("no such file: /{path}"))
+}
@@ -0,0 +1,47 @@
+use crate::{config::TerminalBackend, error::AppError, state::AppState};
+use axum::{extract::State, routing::get, Json, Router};
+use serde::Serialize;
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/api/terminal/profiles", get(profiles_handler))
+}
+
+/// Public view of a terminal profile; credentials stay on the server.
+#[derive(Debug, Serialize)]
+pub struct TerminalProfileInfo {
+    pub name: String,
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TerminalProfilesResponse {
+    pub ok: bool,
+    pub profiles: Vec<TerminalProfileInfo>,
+}
+
+pub async fn profiles_handler(
+    State(state): State<AppState>,
+) -> Result<Json<TerminalProfilesResponse>, AppError> {
+    let profiles = state
+        .config
+        .terminal
+        .profiles
+        .iter()
+        .map(|profile| match &profile.backend {
+            TerminalBackend::Local { .. } => TerminalProfileInfo {
+                name: profile.name.clone(),
+                kind: "local".into(),
+                target: None,
+            },
+            TerminalBackend::Ssh(ssh) => TerminalProfileInfo {
+                name: profile.name.clone(),
+                kind: "ssh".into(),
+                target: Some(format!("{}@{}:{}", ssh.user, ssh.host, ssh.port)),
+            },
+        })
+        .collect();
+
+    Ok(Json(TerminalProfilesResponse { ok: true, profiles }))
+}
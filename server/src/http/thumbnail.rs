@@ -0,0 +1,65 @@
+use crate::{
+    error::AppError,
+    fs::FsRoot,
+    http::serve::{self, Disposition},
+    sniff::{self, PreviewKind},
+    state::AppState,
+    thumbnail::{Outcome, ThumbnailFormat, Thumbnails, DEFAULT_SIZE},
+};
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/api/fs/thumbnail", get(thumbnail_handler))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FsThumbnailQuery {
+    pub path: String,
+    /// Longest edge in pixels, rounded up to one of the rendered sizes.
+    pub size: Option<u32>,
+    #[serde(default)]
+    pub format: ThumbnailFormat,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FsThumbnailFailure {
+    pub ok: bool,
+    pub error: String,
+    /// What to show instead, as in `preview` of a listing entry.
+    pub icon: PreviewKind,
+}
+
+/// A downscaled copy of an image, from the thumbnail cache. Files that
+/// can't be decoded answer 415 with an icon kind to fall back to.
+pub async fn thumbnail_handler(
+    State(state): State<AppState>,
+    root: FsRoot,
+    Query(query): Query<FsThumbnailQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let resolved = root.resolve_path(&query.path)?;
+    let size = Thumbnails::bucket(query.size.unwrap_or(DEFAULT_SIZE));
+    match state.thumbnails.get(&resolved, size, query.format).await? {
+        Outcome::Ready(file) => {
+            serve::serve_file(&headers, &file, query.format.mime(), Disposition::Inline).await
+        }
+        Outcome::Unsupported(error) => {
+            let icon = tokio::task::spawn_blocking(move || sniff::detect(&resolved).preview)
+                .await
+                .map_err(|e| AppError::Internal(format!("sniff task failed: {e}")))?;
+            let body = FsThumbnailFailure {
+                ok: false,
+                error,
+                icon,
+            };
+            Ok((StatusCode::UNSUPPORTED_MEDIA_TYPE, Json(body)).into_response())
+        }
+    }
+}
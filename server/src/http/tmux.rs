@@ -0,0 +1,27 @@
+use crate::{error::AppError, state::AppState, tmux};
+use axum::{extract::State, routing::get, Json, Router};
+use serde::Serialize;
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/api/tmux/sessions", get(sessions_handler))
+}
+
+#[derive(Debug, Serialize)]
+pub struct TmuxSessionsResponse {
+    pub ok: bool,
+    pub sessions: Vec<tmux::TmuxSessionInfo>,
+}
+
+pub async fn sessions_handler(
+    State(state): State<AppState>,
+) -> Result<Json<TmuxSessionsResponse>, AppError> {
+    if !state.config.features.enable_tmux {
+        return Err(AppError::BadRequest("tmux support is disabled".into()));
+    }
+
+    let sessions = tmux::list_sessions()
+        .await
+        .map_err(|e| AppError::Internal(format!("failed to list tmux sessions: {e}")))?;
+
+    Ok(Json(TmuxSessionsResponse { ok: true, sessions }))
+}
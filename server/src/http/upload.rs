@@ -0,0 +1,451 @@
+use crate::{
+    error::AppError,
+    events::{ChangeKind, ServerEvent},
+    fs::FsRoot,
+    state::AppState,
+    upload::{self, ConflictPolicy, PendingUpload, ProgressThrottle},
+};
+use axum::{
+    body::Body,
+    extract::{DefaultBodyLimit, Multipart, Path, Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path as FsPath, PathBuf};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/api/fs/upload",
+            post(multipart_handler).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/api/fs/uploads", post(create_upload_handler))
+        .route(
+            "/api/fs/uploads/:id",
+            get(upload_status_handler)
+                .put(upload_chunk_handler)
+                .delete(cancel_upload_handler),
+        )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadQuery {
+    pub dir: Option<String>,
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadedFile {
+    pub name: String,
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MultipartUploadResponse {
+    pub ok: bool,
+    pub files: Vec<UploadedFile>,
+}
+
+/// Store every file part of a `multipart/form-data` body in `dir`.
+///
+/// A text field named `sha256` before a file part is checked against that
+/// file's digest.
+pub async fn multipart_handler(
+    State(state): State<AppState>,
+    root: FsRoot,
+    Query(query): Query<UploadQuery>,
+    mut multipart: Multipart,
+) -> Result<Json<MultipartUploadResponse>, AppError> {
+    let dir = resolve_dir(&root, query.dir.as_deref())?;
+    let mut expected_sha256 = None;
+    let mut files = Vec::new();
+
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| AppError::BadRequest(format!("invalid multipart body: {e}")))?
+    {
+        let Some(name) = field.file_name().map(str::to_string) else {
+            if field.name() == Some("sha256") {
+                let text = field
+                    .text()
+                    .await
+                    .map_err(|e| AppError::BadRequest(format!("invalid sha256 field: {e}")))?;
+                expected_sha256 = Some(text);
+            }
+            continue;
+        };
+        upload::validate_name(&name)?;
+        root.check_writable(&dir.join(&name))?;
+
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let part = PartFile(Some(upload::part_path(&dir, &name, &id)));
+        let rel = root
+            .to_relative(&dir.join(&name))
+            .unwrap_or_else(|| name.clone());
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(part.path())
+            .await?;
+        let mut hasher = Sha256::new();
+        let mut received = 0u64;
+        let mut pending = state.uploads.stream();
+        let mut throttle = ProgressThrottle::new();
+        while let Some(chunk) = field
+            .chunk()
+            .await
+            .map_err(|e| AppError::BadRequest(format!("upload interrupted: {e}")))?
+        {
+            received += chunk.len() as u64;
+            if received > state.config.upload.max_file_size {
+                return Err(AppError::TooLarge(format!(
+                    "file exceeds the {} byte upload limit",
+                    state.config.upload.max_file_size
+                )));
+            }
+            pending
+                .grow(&state.config.upload, chunk.len() as u64)
+                .await?;
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+            if throttle.due() {
+                state.events.publish(ServerEvent::UploadProgress {
+                    id: id.clone(),
+                    path: rel.clone(),
+                    received,
+                    size: None,
+                });
+            }
+        }
+        file.sync_all().await?;
+        drop(file);
+
+        let sha256 = format!("{:x}", hasher.finalize());
+        if let Some(expected) = expected_sha256.take() {
+            if !upload::checksum_matches(&expected, &sha256) {
+                return Err(AppError::BadRequest(format!(
+                    "checksum mismatch for {name}: got {sha256}"
+                )));
+            }
+        }
+
+        let target = upload::resolve_target(&dir, &name, query.conflict)?;
+        let path = store(&state, &root, &id, part.keep(), target).await?;
+        files.push(UploadedFile {
+            name: path.name,
+            path: path.rel,
+            size: received,
+            sha256,
+        });
+    }
+
+    Ok(Json(MultipartUploadResponse { ok: true, files }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUploadRequest {
+    pub dir: Option<String>,
+    pub name: String,
+    pub size: u64,
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadStatusResponse {
+    pub ok: bool,
+    pub id: String,
+    /// Bytes stored so far; the next chunk must start here.
+    pub offset: u64,
+    pub size: u64,
+    pub complete: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+pub async fn create_upload_handler(
+    State(state): State<AppState>,
+    root: FsRoot,
+    Json(req): Json<CreateUploadRequest>,
+) -> Result<Json<UploadStatusResponse>, AppError> {
+    let dir = resolve_dir(&root, req.dir.as_deref())?;
+    upload::validate_name(&req.name)?;
+    root.check_writable(&dir.join(&req.name))?;
+    // Fail early rather than after gigabytes have been sent.
+    upload::resolve_target(&dir, &req.name, req.conflict)?;
+
+    let pending = PendingUpload::new(dir, req.name, req.size, req.sha256, req.conflict);
+    let id = pending.id.clone();
+    let size = pending.size;
+    let handle = state.uploads.create(&state.config.upload, pending).await?;
+    {
+        let pending = handle.lock().await;
+        if let Err(err) = fs::File::create(&pending.part).await {
+            state.uploads.remove(&id).await;
+            return Err(err.into());
+        }
+    }
+
+    if size == 0 {
+        return finish(&state, &root, &handle).await.map(Json);
+    }
+    Ok(Json(UploadStatusResponse {
+        ok: true,
+        id,
+        offset: 0,
+        size,
+        complete: false,
+        path: None,
+    }))
+}
+
+pub async fn upload_status_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<UploadStatusResponse>, AppError> {
+    let handle = find(&state, &id).await?;
+    let pending = handle.lock().await;
+    let offset = part_len(&pending.part).await?;
+    Ok(Json(UploadStatusResponse {
+        ok: true,
+        id,
+        offset,
+        size: pending.size,
+        complete: false,
+        path: None,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChunkQuery {
+    pub offset: u64,
+}
+
+/// Append the request body at `offset`. A chunk cut short by a dropped
+/// connection keeps what arrived; `GET` reports where to resume.
+pub async fn upload_chunk_handler(
+    State(state): State<AppState>,
+    root: FsRoot,
+    Path(id): Path<String>,
+    Query(query): Query<ChunkQuery>,
+    body: Body,
+) -> Result<Json<UploadStatusResponse>, AppError> {
+    let handle = find(&state, &id).await?;
+    let mut pending = handle.lock().await;
+    pending.touch();
+
+    let offset = part_len(&pending.part).await?;
+    if query.offset != offset {
+        return Err(AppError::Conflict(format!(
+            "upload is at offset {offset}, not {}",
+            query.offset
+        )));
+    }
+
+    let rel = root
+        .to_relative(&pending.dir.join(&pending.name))
+        .unwrap_or_else(|| pending.name.clone());
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(&pending.part)
+        .await?;
+    let mut received = offset;
+    let mut throttle = ProgressThrottle::new();
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| AppError::BadRequest(format!("upload interrupted: {e}")))?;
+        if received + chunk.len() as u64 > pending.size {
+            file.set_len(offset).await?;
+            return Err(AppError::BadRequest(
+                "chunk runs past the declared size".into(),
+            ));
+        }
+        file.write_all(&chunk).await?;
+        received += chunk.len() as u64;
+        if throttle.due() {
+            state.events.publish(ServerEvent::UploadProgress {
+                id: id.clone(),
+                path: rel.clone(),
+                received,
+                size: Some(pending.size),
+            });
+        }
+    }
+    file.sync_all().await?;
+    drop(file);
+    pending.touch();
+
+    state.events.publish(ServerEvent::UploadProgress {
+        id: id.clone(),
+        path: rel,
+        received,
+        size: Some(pending.size),
+    });
+    let size = pending.size;
+    drop(pending);
+
+    if received == size {
+        return finish(&state, &root, &handle).await.map(Json);
+    }
+    Ok(Json(UploadStatusResponse {
+        ok: true,
+        id,
+        offset: received,
+        size,
+        complete: false,
+        path: None,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct CancelUploadResponse {
+    pub ok: bool,
+}
+
+pub async fn cancel_upload_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<CancelUploadResponse>, AppError> {
+    let handle = find(&state, &id).await?;
+    let pending = handle.lock().await;
+    state.uploads.remove(&id).await;
+    let _ = fs::remove_file(&pending.part).await;
+    Ok(Json(CancelUploadResponse { ok: true }))
+}
+
+/// Verify a fully received chunked upload and move it into place. The upload
+/// is forgotten either way; a failed checksum discards the data.
+async fn finish(
+    state: &AppState,
+    root: &FsRoot,
+    handle: &tokio::sync::Mutex<PendingUpload>,
+) -> Result<UploadStatusResponse, AppError> {
+    let pending = handle.lock().await;
+    state.uploads.remove(&pending.id).await;
+    let part = PartFile(Some(pending.part.clone()));
+
+    if let Some(expected) = &pending.sha256 {
+        let path = pending.part.clone();
+        let actual = tokio::task::spawn_blocking(move || upload::sha256_file(&path))
+            .await
+            .map_err(|e| AppError::Internal(format!("checksum task failed: {e}")))??;
+        if !upload::checksum_matches(expected, &actual) {
+            let error = format!("checksum mismatch for {}: got {actual}", pending.name);
+            state.events.publish(ServerEvent::UploadFinished {
+                id: pending.id.clone(),
+                path: pending.name.clone(),
+                error: Some(error.clone()),
+            });
+            return Err(AppError::BadRequest(error));
+        }
+    }
+
+    let target = upload::resolve_target(&pending.dir, &pending.name, pending.conflict)?;
+    let stored = store(state, root, &pending.id, part.keep(), target).await?;
+    Ok(UploadStatusResponse {
+        ok: true,
+        id: pending.id.clone(),
+        offset: pending.size,
+        size: pending.size,
+        complete: true,
+        path: Some(stored.rel),
+    })
+}
+
+struct StoredFile {
+    name: String,
+    rel: String,
+}
+
+/// Rename a finished `.part` file to its target and tell watchers about it.
+async fn store(
+    state: &AppState,
+    root: &FsRoot,
+    id: &str,
+    part: PathBuf,
+    target: PathBuf,
+) -> Result<StoredFile, AppError> {
+    let existed = fs::symlink_metadata(&target).await.is_ok();
+    if let Err(err) = fs::rename(&part, &target).await {
+        let _ = fs::remove_file(&part).await;
+        return Err(err.into());
+    }
+
+    let rel = root.to_relative(&target).unwrap_or_default();
+    let kind = if existed {
+        ChangeKind::Modified
+    } else {
+        ChangeKind::Created
+    };
+    state.events.fs_change(kind, target.clone());
+    state.events.publish(ServerEvent::UploadFinished {
+        id: id.to_string(),
+        path: rel.clone(),
+        error: None,
+    });
+
+    Ok(StoredFile {
+        name: target
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        rel,
+    })
+}
+
+fn resolve_dir(root: &FsRoot, dir: Option<&str>) -> Result<PathBuf, AppError> {
+    let dir = root.resolve_path(dir.unwrap_or_default())?;
+    root.check_writable(&dir)?;
+    if !dir.is_dir() {
+        return Err(AppError::BadRequest(
+            "upload target is not a directory".into(),
+        ));
+    }
+    Ok(dir)
+}
+
+async fn find(
+    state: &AppState,
+    id: &str,
+) -> Result<std::sync::Arc<tokio::sync::Mutex<PendingUpload>>, AppError> {
+    state
+        .uploads
+        .get(id)
+        .await
+        .ok_or_else(|| AppError::NotFound(format!("unknown upload: {id}")))
+}
+
+async fn part_len(part: &FsPath) -> Result<u64, AppError> {
+    Ok(fs::metadata(part).await?.len())
+}
+
+/// Removes a partially written file unless it was handed on with `keep`.
+struct PartFile(Option<PathBuf>);
+
+impl PartFile {
+    fn path(&self) -> &FsPath {
+        self.0.as_deref().expect("part file already kept")
+    }
+
+    fn keep(mut self) -> PathBuf {
+        self.0.take().expect("part file already kept")
+    }
+}
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.0.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
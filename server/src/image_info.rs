@@ -0,0 +1,397 @@
+use crate::convert::{self, ImageMeta};
+use crate::error::AppError;
+use exif::{DateTime, Exif, In, Reader as ExifReader, Tag, Value};
+use flate2::read::ZlibDecoder;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Bytes searched for an XMP packet; it is near the start in JPEG, PNG and
+/// WebP files.
+const XMP_SCAN_LEN: u64 = 4 * 1024 * 1024;
+/// TIFFs keep EXIF in their own directories, which are read from this much
+/// of the start of the file rather than all of it.
+const TIFF_EXIF_LEN: u64 = 16 * 1024 * 1024;
+/// Longest XMP packet or text chunk returned; longer ones are cut off.
+const MAX_TEXT_LEN: usize = 64 * 1024;
+const MAX_TEXT_CHUNKS: usize = 256;
+/// The keyword of the iTXt chunk holding XMP, which is reported as `xmp`.
+const PNG_XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+
+/// Everything known about an image short of its pixels.
+#[derive(Debug, Serialize)]
+pub struct ImageInfo {
+    #[serde(flatten)]
+    pub meta: ImageMeta,
+    /// Frames of an animated GIF, PNG or WebP, or pages of a TIFF.
+    pub frames: usize,
+    /// EXIF orientation, 1 (upright) to 8.
+    pub orientation: u16,
+    /// Dimensions once `orientation` is applied; swapped for 5 to 8.
+    pub display_width: u32,
+    pub display_height: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exif: Option<ExifInfo>,
+    /// The raw XMP packet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xmp: Option<String>,
+    /// `tEXt`, `zTXt` and `iTXt` chunks of a PNG.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub text: Vec<TextChunk>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExifInfo {
+    /// ISO 8601, with the offset when the file records one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub taken_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digitized_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub make: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Decimal degrees, south and west negative.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    /// Every field but the maker note, for display.
+    pub fields: Vec<ExifField>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExifField {
+    pub tag: String,
+    /// `primary`, `thumbnail`, ...
+    pub ifd: String,
+    pub value: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TextChunk {
+    pub keyword: String,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+/// Read the header, EXIF, XMP and text chunks of the image at `path`.
+/// Metadata that is missing or unreadable is left out rather than failing
+/// the whole request. Blocking.
+pub fn read(path: &Path) -> Result<ImageInfo, AppError> {
+    let meta = convert::info(path)?;
+    let mut file = BufReader::new(File::open(path)?);
+
+    let exif = read_exif(&mut file, meta.format);
+    let orientation = exif
+        .as_ref()
+        .and_then(|exif| exif.get_field(Tag::Orientation, In::PRIMARY))
+        .and_then(|field| field.value.get_uint(0))
+        .filter(|o| (1..=8).contains(o))
+        .unwrap_or(1) as u16;
+    let (display_width, display_height) = if orientation >= 5 {
+        (meta.height, meta.width)
+    } else {
+        (meta.width, meta.height)
+    };
+
+    let mut text = Vec::new();
+    let frames = match meta.format {
+        "tiff" => meta.pages,
+        "png" => png_chunks(&mut file, &mut text).unwrap_or(1),
+        "gif" => gif_frames(&mut file).unwrap_or(1),
+        "webp" => webp_frames(&mut file).unwrap_or(1),
+        _ => 1,
+    };
+    let xmp = find_xmp(&mut file).ok().flatten();
+
+    Ok(ImageInfo {
+        meta,
+        frames,
+        orientation,
+        display_width,
+        display_height,
+        exif: exif.as_ref().map(exif_info),
+        xmp,
+        text,
+    })
+}
+
+fn read_exif(file: &mut BufReader<File>, format: &str) -> Option<Exif> {
+    let mut reader = ExifReader::new();
+    reader.continue_on_error(true);
+    file.seek(SeekFrom::Start(0)).ok()?;
+    let result = if format == "tiff" {
+        let mut data = Vec::new();
+        file.take(TIFF_EXIF_LEN).read_to_end(&mut data).ok()?;
+        reader.read_raw(data)
+    } else {
+        reader.read_from_container(file)
+    };
+    result
+        .or_else(|err| err.distill_partial_result(|_| {}))
+        .ok()
+}
+
+fn exif_info(exif: &Exif) -> ExifInfo {
+    let ascii = |tag| match exif.get_field(tag, In::PRIMARY).map(|f| &f.value) {
+        Some(Value::Ascii(values)) => values.first().map(|v| {
+            String::from_utf8_lossy(v)
+                .trim_end_matches('\0')
+                .trim()
+                .to_string()
+        }),
+        _ => None,
+    };
+    let timestamp = |tag, offset_tag| {
+        let field = exif.get_field(tag, In::PRIMARY)?;
+        let Value::Ascii(values) = &field.value else {
+            return None;
+        };
+        let mut time = DateTime::from_ascii(values.first()?).ok()?;
+        if let Some(Value::Ascii(offset)) =
+            exif.get_field(offset_tag, In::PRIMARY).map(|f| &f.value)
+        {
+            if let Some(offset) = offset.first() {
+                let _ = time.parse_offset(offset);
+            }
+        }
+        Some(iso8601(&time))
+    };
+
+    ExifInfo {
+        taken_at: timestamp(Tag::DateTimeOriginal, Tag::OffsetTimeOriginal),
+        digitized_at: timestamp(Tag::DateTimeDigitized, Tag::OffsetTimeDigitized),
+        modified_at: timestamp(Tag::DateTime, Tag::OffsetTime),
+        make: ascii(Tag::Make),
+        model: ascii(Tag::Model),
+        latitude: gps_degrees(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S'),
+        longitude: gps_degrees(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W'),
+        fields: exif
+            .fields()
+            // Vendor-specific binary, often kilobytes of it.
+            .filter(|field| field.tag != Tag::MakerNote)
+            .map(|field| ExifField {
+                tag: field.tag.to_string(),
+                ifd: field.ifd_num.to_string(),
+                value: field.display_value().with_unit(exif).to_string(),
+            })
+            .collect(),
+    }
+}
+
+fn iso8601(time: &DateTime) -> String {
+    let mut out = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        time.year, time.month, time.day, time.hour, time.minute, time.second
+    );
+    if let Some(offset) = time.offset {
+        let sign = if offset < 0 { '-' } else { '+' };
+        let offset = offset.unsigned_abs();
+        out.push_str(&format!("{sign}{:02}:{:02}", offset / 60, offset % 60));
+    }
+    out
+}
+
+/// A GPS coordinate from its degrees/minutes/seconds and hemisphere.
+fn gps_degrees(exif: &Exif, tag: Tag, ref_tag: Tag, negative: u8) -> Option<f64> {
+    let Value::Rational(dms) = &exif.get_field(tag, In::PRIMARY)?.value else {
+        return None;
+    };
+    let degrees = dms
+        .iter()
+        .zip([1.0, 60.0, 3600.0])
+        .map(|(part, scale)| part.to_f64() / scale)
+        .sum::<f64>();
+    let sign = match exif.get_field(ref_tag, In::PRIMARY).map(|f| &f.value) {
+        Some(Value::Ascii(values)) if values.first().and_then(|v| v.first()) == Some(&negative) => {
+            -1.0
+        }
+        _ => 1.0,
+    };
+    degrees.is_finite().then_some(sign * degrees)
+}
+
+/// Walk the chunks of a PNG, collecting text chunks into `text`. Returns
+/// the number of frames: that of the animation control chunk, or 1.
+fn png_chunks(file: &mut BufReader<File>, text: &mut Vec<TextChunk>) -> std::io::Result<usize> {
+    file.seek(SeekFrom::Start(8))?;
+    let mut frames = 1;
+    loop {
+        let mut header = [0; 8];
+        if file.read_exact(&mut header).is_err() {
+            break;
+        }
+        let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as u64;
+        let kind = &header[4..];
+        match kind {
+            b"IEND" => break,
+            b"acTL" if len >= 4 => {
+                let mut data = [0; 4];
+                file.read_exact(&mut data)?;
+                frames = u32::from_be_bytes(data) as usize;
+                file.seek_relative(len as i64 - 4)?;
+            }
+            b"tEXt" | b"zTXt" | b"iTXt" if text.len() < MAX_TEXT_CHUNKS => {
+                let mut data = Vec::new();
+                (&mut *file).take(len).read_to_end(&mut data)?;
+                if let Some(chunk) = text_chunk(kind, &data) {
+                    if chunk.keyword != PNG_XMP_KEYWORD {
+                        text.push(chunk);
+                    }
+                }
+            }
+            _ => file.seek_relative(len as i64)?,
+        }
+        // CRC
+        file.seek_relative(4)?;
+    }
+    Ok(frames)
+}
+
+fn text_chunk(kind: &[u8], data: &[u8]) -> Option<TextChunk> {
+    let (keyword, rest) = split_nul(data)?;
+    let keyword = latin1(keyword);
+    match kind {
+        b"tEXt" => Some(TextChunk {
+            keyword,
+            text: latin1(&rest[..rest.len().min(MAX_TEXT_LEN)]),
+            language: None,
+        }),
+        // Compression method, then zlib data.
+        b"zTXt" => Some(TextChunk {
+            keyword,
+            text: latin1(&inflate(rest.get(1..)?)),
+            language: None,
+        }),
+        _ => {
+            let (&compressed, rest) = rest.split_first()?;
+            let rest = rest.get(1..)?;
+            let (language, rest) = split_nul(rest)?;
+            let (_translated, rest) = split_nul(rest)?;
+            let text = if compressed == 1 {
+                inflate(rest)
+            } else {
+                rest[..rest.len().min(MAX_TEXT_LEN)].to_vec()
+            };
+            Some(TextChunk {
+                keyword,
+                text: String::from_utf8_lossy(&text).into_owned(),
+                language: (!language.is_empty()).then(|| latin1(language)),
+            })
+        }
+    }
+}
+
+fn split_nul(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let nul = data.iter().position(|&b| b == 0)?;
+    Some((&data[..nul], &data[nul + 1..]))
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+/// At most [`MAX_TEXT_LEN`] bytes of zlib data, as far as it inflates.
+fn inflate(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let _ = ZlibDecoder::new(data)
+        .take(MAX_TEXT_LEN as u64)
+        .read_to_end(&mut out);
+    out
+}
+
+/// Count the image descriptors of a GIF, skipping over everything else.
+fn gif_frames(file: &mut BufReader<File>) -> std::io::Result<usize> {
+    file.seek(SeekFrom::Start(6))?;
+    let mut screen = [0; 7];
+    file.read_exact(&mut screen)?;
+    skip_color_table(file, screen[4])?;
+    let mut frames = 0;
+    loop {
+        let mut introducer = [0; 1];
+        if file.read_exact(&mut introducer).is_err() {
+            break;
+        }
+        match introducer[0] {
+            // Extension: label, then data sub-blocks.
+            0x21 => {
+                file.seek_relative(1)?;
+                skip_sub_blocks(file)?;
+            }
+            // Image descriptor, local color table, LZW code size, data.
+            0x2c => {
+                let mut descriptor = [0; 9];
+                file.read_exact(&mut descriptor)?;
+                skip_color_table(file, descriptor[8])?;
+                file.seek_relative(1)?;
+                skip_sub_blocks(file)?;
+                frames += 1;
+            }
+            _ => break,
+        }
+    }
+    Ok(frames.max(1))
+}
+
+fn skip_color_table(file: &mut BufReader<File>, flags: u8) -> std::io::Result<()> {
+    if flags & 0x80 != 0 {
+        file.seek_relative(3 * (2i64 << (flags & 0x07)))?;
+    }
+    Ok(())
+}
+
+fn skip_sub_blocks(file: &mut BufReader<File>) -> std::io::Result<()> {
+    loop {
+        let mut len = [0; 1];
+        file.read_exact(&mut len)?;
+        if len[0] == 0 {
+            return Ok(());
+        }
+        file.seek_relative(i64::from(len[0]))?;
+    }
+}
+
+/// Count the animation frames of a WebP; still images have one.
+fn webp_frames(file: &mut BufReader<File>) -> std::io::Result<usize> {
+    file.seek(SeekFrom::Start(12))?;
+    let mut frames = 0;
+    let mut header = [0; 8];
+    while file.read_exact(&mut header).is_ok() {
+        let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as i64;
+        if &header[..4] == b"ANMF" {
+            frames += 1;
+        }
+        // Chunks are padded to an even length.
+        file.seek_relative(len + (len & 1))?;
+    }
+    Ok(frames.max(1))
+}
+
+/// The XMP packet in the first [`XMP_SCAN_LEN`] bytes, wherever the
+/// container keeps it.
+fn find_xmp(file: &mut BufReader<File>) -> std::io::Result<Option<String>> {
+    const START: &[u8] = b"<x:xmpmeta";
+    const END: &[u8] = b"</x:xmpmeta>";
+    file.seek(SeekFrom::Start(0))?;
+    let mut data = Vec::new();
+    file.take(XMP_SCAN_LEN).read_to_end(&mut data)?;
+    let Some(start) = find(&data, START) else {
+        return Ok(None);
+    };
+    let end = find(&data[start..], END)
+        .map(|i| start + i + END.len())
+        .unwrap_or(data.len())
+        .min(start + MAX_TEXT_LEN);
+    Ok(Some(
+        String::from_utf8_lossy(&data[start..end]).into_owned(),
+    ))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
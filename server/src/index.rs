@@ -0,0 +1,407 @@
+use ignore::gitignore::Gitignore;
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use nucleo_matcher::pattern::{CaseMatching, Normalization, Pattern};
+use nucleo_matcher::{Config as MatcherConfig, Matcher, Utf32Str};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use walkdir::WalkDir;
+
+/// Bursts of filesystem events (checkouts, builds) are applied together.
+const UPDATE_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// In-memory list of the files below one allowed root, for the fuzzy finder.
+///
+/// Hidden entries and anything excluded by a `.gitignore` under the root
+/// are left out. The index is built in the background and then kept up to
+/// date from notify events; a changed `.gitignore` triggers a rescan.
+#[derive(Clone, Default)]
+pub struct PathIndex {
+    inner: Arc<RwLock<IndexState>>,
+    task: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+#[derive(Default)]
+struct IndexState {
+    root: PathBuf,
+    /// Root-relative, `/`-separated file paths.
+    paths: HashSet<Box<str>>,
+    ready: bool,
+    /// `max_files` was reached and some files are missing.
+    truncated: bool,
+    max_files: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FindMatch {
+    pub path: String,
+    pub score: u32,
+    /// Character offsets in `path` that matched the query.
+    pub positions: Vec<u32>,
+}
+
+pub struct FindResult {
+    pub ready: bool,
+    pub truncated: bool,
+    pub indexed: usize,
+    pub matches: Vec<FindMatch>,
+}
+
+impl PathIndex {
+    /// (Re)build the index for `root`, replacing any previous one.
+    pub fn start(&self, root: PathBuf, max_files: usize, watch: bool) {
+        {
+            let mut state = self.inner.write().unwrap();
+            *state = IndexState {
+                root: root.clone(),
+                max_files,
+                ..IndexState::default()
+            };
+        }
+        let index = self.clone();
+        let task = tokio::spawn(async move { index.run(root, watch).await });
+        if let Some(previous) = self.task.lock().unwrap().replace(task) {
+            // Drops the old watcher; a walk still in flight is discarded
+            // because the state's root no longer matches.
+            previous.abort();
+        }
+    }
+
+    async fn run(self, root: PathBuf, watch: bool) {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        // Watch before walking so nothing created during the walk is missed.
+        let _watcher = if watch {
+            let watch_root = root.clone();
+            tokio::task::spawn_blocking(move || watch_tree(&watch_root, tx))
+                .await
+                .ok()
+                .flatten()
+        } else {
+            None
+        };
+
+        let mut rescan = true;
+        loop {
+            if rescan {
+                let index = self.clone();
+                let walk_root = root.clone();
+                let started = std::time::Instant::now();
+                let Ok(Some(count)) =
+                    tokio::task::spawn_blocking(move || index.rebuild(&walk_root)).await
+                else {
+                    return;
+                };
+                tracing::info!(
+                    "indexed {count} files under {} in {:?}",
+                    root.display(),
+                    started.elapsed()
+                );
+            }
+
+            let Some(first) = rx.recv().await else {
+                return;
+            };
+            tokio::time::sleep(UPDATE_DEBOUNCE).await;
+            let mut events = vec![first];
+            while let Ok(event) = rx.try_recv() {
+                events.push(event);
+            }
+
+            let index = self.clone();
+            let root = root.clone();
+            match tokio::task::spawn_blocking(move || index.apply(&root, events)).await {
+                Ok(needs_rescan) => rescan = needs_rescan,
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// Walk the whole tree. Returns the file count, or `None` if the index
+    /// was restarted for another root meanwhile.
+    fn rebuild(&self, root: &Path) -> Option<usize> {
+        let max_files = self.inner.read().unwrap().max_files;
+        let mut ignores = Ignores::new(root);
+        let mut paths = HashSet::new();
+        let truncated = collect(root, root, &mut ignores, &mut paths, max_files);
+
+        let mut state = self.inner.write().unwrap();
+        if state.root != root {
+            return None;
+        }
+        state.paths = paths;
+        state.truncated = truncated;
+        state.ready = true;
+        Some(state.paths.len())
+    }
+
+    /// Apply a batch of notify events. Returns true if a full rescan is
+    /// needed instead.
+    fn apply(&self, root: &Path, events: Vec<notify::Result<Event>>) -> bool {
+        let mut changed = HashSet::new();
+        for event in events {
+            let event = match event {
+                Ok(event) => event,
+                Err(err) => {
+                    tracing::warn!("index watcher error: {err:?}");
+                    return true;
+                }
+            };
+            if event.need_rescan() {
+                return true;
+            }
+            if matches!(event.kind, EventKind::Access(_)) {
+                continue;
+            }
+            if event
+                .paths
+                .iter()
+                .any(|path| path.file_name().is_some_and(|name| name == ".gitignore"))
+            {
+                return true;
+            }
+            if matches!(
+                event.kind,
+                EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Metadata(_))
+            ) {
+                continue;
+            }
+            for path in event.paths {
+                changed.insert(path);
+            }
+        }
+
+        // Look at the disk before taking the lock, so finds are not held up
+        // by a large directory being moved in.
+        let mut ignores = Ignores::new(root);
+        let mut added = HashSet::new();
+        let mut removed = HashSet::new();
+        let mut truncated = false;
+        let max_files = self.inner.read().unwrap().max_files;
+        for path in changed {
+            let Some(rel) = relative(root, &path) else {
+                continue;
+            };
+            match std::fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.is_dir() => {
+                    if !ignores.is_ignored(&path, true) {
+                        truncated |= collect(root, &path, &mut ignores, &mut added, max_files);
+                    }
+                }
+                Ok(_) if !ignores.is_ignored(&path, false) => {
+                    added.insert(rel.into_boxed_str());
+                }
+                // Gone (a file or a whole directory) or now ignored.
+                _ => {
+                    removed.insert(rel);
+                }
+            }
+        }
+
+        let mut state = self.inner.write().unwrap();
+        if state.root != root || !state.ready {
+            return false;
+        }
+        if !removed.is_empty() {
+            // One pass for the whole batch; `rm -rf` reports every file.
+            state.paths.retain(|path| {
+                let mut prefix = &**path;
+                loop {
+                    if removed.contains(prefix) {
+                        return false;
+                    }
+                    match prefix.rfind('/') {
+                        Some(i) => prefix = &prefix[..i],
+                        None => return true,
+                    }
+                }
+            });
+        }
+        for path in added {
+            if state.paths.len() >= max_files {
+                truncated = true;
+                break;
+            }
+            state.paths.insert(path);
+        }
+        state.truncated |= truncated;
+        false
+    }
+
+    /// Rank indexed paths below `within` (a root-relative directory, empty
+    /// for all) against `query`, best first. Matches are relative to
+    /// `within`; those `visible` rejects are skipped.
+    pub fn find(
+        &self,
+        query: &str,
+        within: &str,
+        limit: usize,
+        visible: impl Fn(&str) -> bool,
+    ) -> FindResult {
+        let state = self.inner.read().unwrap();
+        let prefix = if within.is_empty() {
+            String::new()
+        } else {
+            format!("{within}/")
+        };
+        let mut matcher = Matcher::new(MatcherConfig::DEFAULT.match_paths());
+        // fzf syntax: space-separated terms, `^prefix`, `suffix$`, `!not`.
+        let pattern = Pattern::parse(query, CaseMatching::Smart, Normalization::Smart);
+        let mut buf = Vec::new();
+
+        let mut scored: Vec<(u32, &str)> = state
+            .paths
+            .iter()
+            .filter_map(|path| {
+                let path = path.strip_prefix(prefix.as_str())?;
+                let score = pattern.score(Utf32Str::new(path, &mut buf), &mut matcher)?;
+                Some((score, path))
+            })
+            .collect();
+        scored.sort_unstable_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then_with(|| a.1.len().cmp(&b.1.len()))
+                .then_with(|| a.1.cmp(b.1))
+        });
+
+        let matches = scored
+            .into_iter()
+            .filter(|(_, path)| visible(path))
+            .take(limit)
+            .map(|(score, path)| {
+                let mut positions = Vec::new();
+                pattern.indices(Utf32Str::new(path, &mut buf), &mut matcher, &mut positions);
+                positions.sort_unstable();
+                positions.dedup();
+                FindMatch {
+                    path: path.to_string(),
+                    score,
+                    positions,
+                }
+            })
+            .collect();
+        FindResult {
+            ready: state.ready,
+            truncated: state.truncated,
+            indexed: state.paths.len(),
+            matches,
+        }
+    }
+}
+
+fn watch_tree(
+    root: &Path,
+    tx: mpsc::UnboundedSender<notify::Result<Event>>,
+) -> Option<RecommendedWatcher> {
+    let mut watcher = match RecommendedWatcher::new(
+        move |res| {
+            let _ = tx.send(res);
+        },
+        notify::Config::default(),
+    ) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            tracing::warn!("path index: failed to initialize watcher: {err:?}");
+            return None;
+        }
+    };
+    if let Err(err) = watcher.watch(root, RecursiveMode::Recursive) {
+        // Usually the inotify watch limit; the index still works, but only
+        // picks up changes on the next rescan.
+        tracing::warn!("path index: cannot watch {}: {err:?}", root.display());
+    }
+    Some(watcher)
+}
+
+/// Add the files below `dir` to `paths`. Returns true if `max_files` cut
+/// the walk short.
+fn collect(
+    root: &Path,
+    dir: &Path,
+    ignores: &mut Ignores,
+    paths: &mut HashSet<Box<str>>,
+    max_files: usize,
+) -> bool {
+    let mut walker = WalkDir::new(dir).follow_links(false).into_iter();
+    while let Some(entry) = walker.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let is_dir = entry.file_type().is_dir();
+        if entry.depth() > 0 && ignores.is_ignored(entry.path(), is_dir) {
+            if is_dir {
+                walker.skip_current_dir();
+            }
+            continue;
+        }
+        if is_dir {
+            continue;
+        }
+        if paths.len() >= max_files {
+            return true;
+        }
+        if let Some(rel) = relative(root, entry.path()) {
+            paths.insert(rel.into_boxed_str());
+        }
+    }
+    false
+}
+
+fn relative(root: &Path, path: &Path) -> Option<String> {
+    let rel = path.strip_prefix(root).ok()?;
+    if rel.as_os_str().is_empty() {
+        return None;
+    }
+    Some(rel.to_string_lossy().to_string())
+}
+
+/// `.gitignore` matchers of the directories under the root, loaded lazily.
+struct Ignores {
+    root: PathBuf,
+    cache: HashMap<PathBuf, Option<Gitignore>>,
+}
+
+impl Ignores {
+    fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            cache: HashMap::new(),
+        }
+    }
+
+    fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        let Ok(rel) = path.strip_prefix(&self.root) else {
+            return true;
+        };
+        if rel
+            .components()
+            .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+        {
+            return true;
+        }
+        // The closest .gitignore with an opinion wins.
+        for dir in path.ancestors().skip(1) {
+            if !dir.starts_with(&self.root) {
+                break;
+            }
+            let ignore = self.cache.entry(dir.to_path_buf()).or_insert_with(|| {
+                let file = dir.join(".gitignore");
+                file.is_file().then(|| Gitignore::new(file).0)
+            });
+            if let Some(ignore) = ignore {
+                let matched = ignore.matched_path_or_any_parents(path, is_dir);
+                if matched.is_ignore() {
+                    return true;
+                }
+                if matched.is_whitelist() {
+                    return false;
+                }
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,423 @@
+use crate::events::{EventBus, ServerEvent};
+use crate::upload::ProgressThrottle;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Lines between two recorded offsets. A window starts at the checkpoint
+/// before it and skips at most this many lines.
+const STRIDE: u64 = 1024;
+/// Files up to this size are indexed before answering, larger ones in the
+/// background.
+const SYNC_LIMIT: u64 = 16 * 1024 * 1024;
+/// How often a running build makes its checkpoints visible to readers.
+const PUBLISH_BYTES: u64 = 32 * 1024 * 1024;
+const CHUNK_LEN: usize = 1024 * 1024;
+/// Longer lines are cut off in a window.
+pub const MAX_LINE_LEN: usize = 64 * 1024;
+/// Indexes kept in memory; finished ones are reloaded from disk as needed.
+const MAX_ENTRIES: usize = 64;
+const CACHE_MAGIC: &[u8; 8] = b"LTLIDX2\0";
+/// Bytes before the end of the indexed part compared when a file has grown,
+/// to tell appended data from a file rewritten in place.
+const CHECK_LEN: usize = 64;
+
+/// What a file looked like when it was indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime_ns: i64,
+}
+
+impl Stamp {
+    fn of(metadata: &std::fs::Metadata) -> Self {
+        Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.len(),
+            mtime_ns: metadata.mtime() * 1_000_000_000 + metadata.mtime_nsec(),
+        }
+    }
+
+    /// Whether `self` can be the same file as `older` with data appended,
+    /// so indexing can continue where it stopped; see also
+    /// [`Progress::still_valid`].
+    fn extends(&self, older: &Stamp) -> bool {
+        self.dev == older.dev && self.ino == older.ino && self.size > older.size
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Progress {
+    /// Offset of line `k * STRIDE` (0-based) at index `k`.
+    checkpoints: Vec<u64>,
+    /// Complete (newline-terminated) lines seen.
+    lines: u64,
+    /// Offset just after the last newline seen.
+    bytes: u64,
+    /// Offset scanning has reached.
+    scanned: u64,
+    done: bool,
+    /// The last (up to [`CHECK_LEN`]) bytes before `bytes`.
+    check: Vec<u8>,
+}
+
+impl Progress {
+    /// Whether the indexed part of `path` still holds the same bytes, so
+    /// the file was appended to rather than truncated and rewritten.
+    /// Blocking.
+    fn still_valid(&self, path: &Path) -> bool {
+        check_bytes(path, self.bytes).is_ok_and(|now| now == self.check)
+    }
+}
+
+struct Entry {
+    stamp: Stamp,
+    progress: RwLock<Progress>,
+    /// Replaced by the index of a newer version of the file; its build
+    /// stops and leaves the cache alone. Only set under the entries lock.
+    superseded: AtomicBool,
+}
+
+impl Entry {
+    fn new(stamp: Stamp, progress: Progress) -> Arc<Self> {
+        Arc::new(Self {
+            stamp,
+            progress: RwLock::new(progress),
+            superseded: AtomicBool::new(false),
+        })
+    }
+}
+
+/// A window of lines read through the index.
+pub struct Window {
+    /// `(line, truncated)`; `truncated` lines were cut at [`MAX_LINE_LEN`].
+    pub lines: Vec<(String, bool)>,
+    /// Exact once indexing is done, extrapolated from the part indexed so
+    /// far before that.
+    pub total_lines: u64,
+    pub exact: bool,
+    /// The window lies beyond what has been indexed; ask again later.
+    pub pending: bool,
+    pub indexed: u64,
+    pub size: u64,
+}
+
+/// Line-offset indexes of large text files, built on first use and kept in
+/// `<cache_dir>/lines`, so any window of lines can be read without scanning
+/// the file from the start. An index is reused while the file's inode, size
+/// and mtime are unchanged, and extended when the file only grew.
+#[derive(Clone)]
+pub struct LineIndexes {
+    dir: PathBuf,
+    events: EventBus,
+    entries: Arc<Mutex<HashMap<PathBuf, Arc<Entry>>>>,
+}
+
+impl LineIndexes {
+    pub fn new(cache_dir: &Path, events: EventBus) -> Self {
+        Self {
+            dir: cache_dir.join("lines"),
+            events,
+            entries: Arc::default(),
+        }
+    }
+
+    /// `count` lines of `path` starting at line `from` (1-based), starting
+    /// to index the file if needed. Blocking.
+    pub fn window(&self, path: &Path, from: u64, count: usize) -> std::io::Result<Window> {
+        let mut file = File::open(path)?;
+        let stamp = Stamp::of(&file.metadata()?);
+        let entry = self.entry(path, stamp);
+        let progress = entry.progress.read().unwrap().clone();
+
+        let first = from.saturating_sub(1);
+        let has_partial_line = progress.done && stamp.size > progress.bytes;
+        let available = progress.lines + u64::from(has_partial_line);
+        let total_lines = if progress.done {
+            available
+        } else if progress.scanned > 0 {
+            (progress.lines as f64 * stamp.size as f64 / progress.scanned as f64) as u64
+        } else {
+            0
+        };
+        let mut window = Window {
+            lines: Vec::new(),
+            total_lines,
+            exact: progress.done,
+            pending: !progress.done && first >= progress.lines,
+            indexed: progress.scanned,
+            size: stamp.size,
+        };
+        if window.pending || first >= available {
+            return Ok(window);
+        }
+
+        let checkpoint = (first / STRIDE) as usize;
+        file.seek(SeekFrom::Start(progress.checkpoints[checkpoint]))?;
+        let mut reader = BufReader::new(file.take(stamp.size));
+        for _ in checkpoint as u64 * STRIDE..first {
+            read_line(&mut reader, 0)?;
+        }
+        let count = (count as u64).min(available - first);
+        for _ in 0..count {
+            let Some(line) = read_line(&mut reader, MAX_LINE_LEN)? else {
+                break;
+            };
+            window.lines.push(line);
+        }
+        Ok(window)
+    }
+
+    /// The index for `path` as it is now: from memory, from disk, or a new
+    /// build.
+    fn entry(&self, path: &Path, stamp: Stamp) -> Arc<Entry> {
+        let mut entries = self.entries.lock().unwrap();
+        let previous = match entries.get(path) {
+            Some(entry) if entry.stamp == stamp => return entry.clone(),
+            Some(entry) => {
+                entry.superseded.store(true, Ordering::Relaxed);
+                // A build still running continues from what it published.
+                let progress = entry.progress.read().unwrap().clone();
+                if stamp.extends(&entry.stamp) {
+                    Some((entry.stamp, progress))
+                } else {
+                    self.load(path)
+                }
+            }
+            None => self.load(path),
+        };
+        let base = match previous {
+            Some((old, progress)) if old == stamp => {
+                let entry = Entry::new(stamp, progress);
+                entries.insert(path.to_path_buf(), entry.clone());
+                return entry;
+            }
+            Some((old, progress)) if stamp.extends(&old) && progress.still_valid(path) => {
+                Progress {
+                    done: false,
+                    scanned: progress.bytes,
+                    ..progress
+                }
+            }
+            _ => Progress {
+                checkpoints: vec![0],
+                ..Default::default()
+            },
+        };
+
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, entry| !entry.progress.read().unwrap().done);
+        }
+        let entry = Entry::new(stamp, base);
+        entries.insert(path.to_path_buf(), entry.clone());
+        drop(entries);
+
+        let indexes = self.clone();
+        let path = path.to_path_buf();
+        let build_entry = entry.clone();
+        let build = move || {
+            if let Err(err) = indexes.build(&path, &build_entry) {
+                tracing::warn!("line index of {path:?} failed: {err}");
+                // Unless a newer index took its place meanwhile
+                let mut entries = indexes.entries.lock().unwrap();
+                if entries
+                    .get(&path)
+                    .is_some_and(|current| Arc::ptr_eq(current, &build_entry))
+                {
+                    entries.remove(&path);
+                }
+            }
+        };
+        if stamp.size - entry.progress.read().unwrap().scanned <= SYNC_LIMIT {
+            build();
+        } else {
+            tokio::task::spawn_blocking(build);
+        }
+        entry
+    }
+
+    /// Scan the file from where `entry` left off up to the size it was
+    /// stamped with, unless it is superseded first. Blocking.
+    fn build(&self, path: &Path, entry: &Entry) -> std::io::Result<()> {
+        let size = entry.stamp.size;
+        let mut progress = entry.progress.read().unwrap().clone();
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(progress.scanned))?;
+        let mut file = file.take(size - progress.scanned);
+        let mut buf = vec![0; CHUNK_LEN];
+        let mut throttle = ProgressThrottle::new();
+        let mut published = progress.scanned;
+        loop {
+            if entry.superseded.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            for (i, _) in buf[..n].iter().enumerate().filter(|(_, &b)| b == b'\n') {
+                progress.lines += 1;
+                progress.bytes = progress.scanned + i as u64 + 1;
+                if progress.lines.is_multiple_of(STRIDE) {
+                    progress.checkpoints.push(progress.bytes);
+                }
+            }
+            progress.scanned += n as u64;
+            if progress.scanned - published >= PUBLISH_BYTES {
+                published = progress.scanned;
+                progress.check = check_bytes(path, progress.bytes)?;
+                *entry.progress.write().unwrap() = progress.clone();
+                if throttle.due() {
+                    self.report(path, &progress, size);
+                }
+            }
+        }
+        progress.done = true;
+        progress.check = check_bytes(path, progress.bytes)?;
+        *entry.progress.write().unwrap() = progress.clone();
+        if size > SYNC_LIMIT {
+            self.report(path, &progress, size);
+        }
+        // Under the lock, so a newer index can't be saved in between.
+        let _entries = self.entries.lock().unwrap();
+        if entry.superseded.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        if let Err(err) = self.save(path, entry.stamp, &progress) {
+            tracing::warn!("failed to cache line index of {path:?}: {err}");
+        }
+        Ok(())
+    }
+
+    fn report(&self, path: &Path, progress: &Progress, size: u64) {
+        self.events.publish(ServerEvent::LineIndexProgress {
+            path: path.to_path_buf(),
+            indexed: progress.scanned,
+            size,
+            done: progress.done,
+        });
+    }
+
+    fn cache_file(&self, path: &Path) -> PathBuf {
+        let digest = Sha256::digest(path.as_os_str().as_encoded_bytes());
+        let name: String = digest.iter().map(|b| format!("{b:02x}")).collect();
+        self.dir.join(name)
+    }
+
+    fn save(&self, path: &Path, stamp: Stamp, progress: &Progress) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let mut data = Vec::with_capacity(128 + progress.checkpoints.len() * 8);
+        data.extend_from_slice(CACHE_MAGIC);
+        for value in [
+            stamp.dev,
+            stamp.ino,
+            stamp.size,
+            stamp.mtime_ns as u64,
+            progress.lines,
+            progress.bytes,
+            progress.check.len() as u64,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        let mut check = [0; CHECK_LEN];
+        check[..progress.check.len()].copy_from_slice(&progress.check);
+        data.extend_from_slice(&check);
+        for offset in &progress.checkpoints {
+            data.extend_from_slice(&offset.to_le_bytes());
+        }
+        let target = self.cache_file(path);
+        let tmp = target.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&data)?;
+        std::fs::rename(tmp, target)
+    }
+
+    /// A finished index saved for `path`, whatever version of it that was.
+    fn load(&self, path: &Path) -> Option<(Stamp, Progress)> {
+        let data = std::fs::read(self.cache_file(path)).ok()?;
+        let body = data.strip_prefix(CACHE_MAGIC)?;
+        if body.len() < 56 + CHECK_LEN || !body.len().is_multiple_of(8) {
+            return None;
+        }
+        let (header, rest) = body.split_at(56);
+        let (check, checkpoints) = rest.split_at(CHECK_LEN);
+        let mut values = header
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()));
+        let mut next = || values.next().unwrap_or_default();
+        let stamp = Stamp {
+            dev: next(),
+            ino: next(),
+            size: next(),
+            mtime_ns: next() as i64,
+        };
+        let (lines, bytes) = (next(), next());
+        let check_len = (next() as usize).min(CHECK_LEN);
+        let progress = Progress {
+            checkpoints: checkpoints
+                .chunks_exact(8)
+                .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+                .collect(),
+            lines,
+            bytes,
+            scanned: stamp.size,
+            done: true,
+            check: check[..check_len].to_vec(),
+        };
+        Some((stamp, progress))
+    }
+}
+
+/// The up to [`CHECK_LEN`] bytes of `path` before offset `end`. Blocking.
+fn check_bytes(path: &Path, end: u64) -> std::io::Result<Vec<u8>> {
+    let start = end.saturating_sub(CHECK_LEN as u64);
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut bytes = Vec::with_capacity(CHECK_LEN);
+    file.take(end - start).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Read one line without its line break, keeping at most `max` bytes of it.
+/// `None` at the end of the file.
+fn read_line(reader: &mut impl BufRead, max: usize) -> std::io::Result<Option<(String, bool)>> {
+    let mut line = Vec::new();
+    let mut truncated = false;
+    let mut any = false;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        any = true;
+        let (len, found) = match buf.iter().position(|&b| b == b'\n') {
+            Some(i) => (i + 1, true),
+            None => (buf.len(), false),
+        };
+        let keep = len.min(max.saturating_sub(line.len()));
+        line.extend_from_slice(&buf[..keep]);
+        truncated |= keep < len && !(found && keep + 1 == len);
+        reader.consume(len);
+        if found {
+            break;
+        }
+    }
+    if !any {
+        return Ok(None);
+    }
+    while line.last().is_some_and(|&b| b == b'\n' || b == b'\r') {
+        line.pop();
+    }
+    Ok(Some((
+        String::from_utf8_lossy(&line).into_owned(),
+        truncated,
+    )))
+}
@@ -0,0 +1,246 @@
+use crate::{error::AppError, fs::FsRoot, globs::PathGlobs};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortBy {
+    #[default]
+    Name,
+    /// Case-insensitive, with digit runs compared by value (`run2` < `run10`).
+    Natural,
+    Size,
+    Mtime,
+    /// By extension, then by name.
+    Type,
+}
+
+pub struct ListOptions {
+    pub sort: SortBy,
+    pub descending: bool,
+    pub dirs_first: bool,
+    pub show_hidden: bool,
+    pub globs: PathGlobs,
+}
+
+/// A directory entry with just enough metadata to sort it.
+pub struct Candidate {
+    pub name: String,
+    pub path: PathBuf,
+    is_dir: bool,
+    key: Key,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Key {
+    Name,
+    Size(u64),
+    Mtime(u128),
+    Type(String),
+}
+
+/// Position after the last entry of a page. Holds the sort key rather than
+/// an offset, so pages stay consistent while entries come and go.
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    dir: bool,
+    key: Key,
+    name: String,
+}
+
+/// Read `dir` and sort what passes the filters. Only the metadata the sort
+/// needs is read. Blocking.
+pub fn scan(root: &FsRoot, dir: &Path, options: &ListOptions) -> std::io::Result<Vec<Candidate>> {
+    let mut candidates = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !options.show_hidden && name.starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        if root.is_hidden(&path) {
+            continue;
+        }
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let needs_metadata =
+            file_type.is_symlink() || matches!(options.sort, SortBy::Size | SortBy::Mtime);
+        // Symlinks are sorted as what they point to, like listings show them.
+        let metadata = if needs_metadata {
+            std::fs::metadata(&path)
+                .or_else(|_| std::fs::symlink_metadata(&path))
+                .ok()
+        } else {
+            None
+        };
+        let is_dir = metadata
+            .as_ref()
+            .map(|m| m.is_dir())
+            .unwrap_or(file_type.is_dir());
+        if !is_dir && !options.globs.included(Path::new(&name)) {
+            continue;
+        }
+        if options.globs.excluded(Path::new(&name)) {
+            continue;
+        }
+
+        let key = match options.sort {
+            SortBy::Name | SortBy::Natural => Key::Name,
+            SortBy::Size => Key::Size(metadata.as_ref().map(|m| m.len()).unwrap_or_default()),
+            SortBy::Mtime => Key::Mtime(
+                metadata
+                    .as_ref()
+                    .and_then(|m| m.modified().ok())
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_nanos())
+                    .unwrap_or_default(),
+            ),
+            SortBy::Type => Key::Type(extension(&name, is_dir)),
+        };
+        candidates.push(Candidate {
+            name,
+            path,
+            is_dir,
+            key,
+        });
+    }
+
+    candidates.sort_unstable_by(|a, b| {
+        compare(
+            options,
+            (a.is_dir, &a.key, &a.name),
+            (b.is_dir, &b.key, &b.name),
+        )
+    });
+    Ok(candidates)
+}
+
+/// The entries after `cursor`, at most `limit` of them, and the cursor for
+/// the page after that if there is one.
+pub fn page<'a>(
+    candidates: &'a [Candidate],
+    options: &ListOptions,
+    cursor: Option<&str>,
+    limit: Option<usize>,
+) -> Result<(&'a [Candidate], Option<String>), AppError> {
+    let start = match cursor {
+        Some(cursor) => {
+            let cursor = decode_cursor(cursor)?;
+            candidates.partition_point(|c| {
+                compare(
+                    options,
+                    (c.is_dir, &c.key, &c.name),
+                    (cursor.dir, &cursor.key, &cursor.name),
+                ) != Ordering::Greater
+            })
+        }
+        None => 0,
+    };
+    let rest = &candidates[start..];
+    let Some(limit) = limit.filter(|&limit| limit < rest.len()) else {
+        return Ok((rest, None));
+    };
+    let page = &rest[..limit];
+    let last = &page[limit - 1];
+    let next = encode_cursor(&Cursor {
+        dir: last.is_dir,
+        key: last.key.clone(),
+        name: last.name.clone(),
+    });
+    Ok((page, Some(next)))
+}
+
+fn compare(options: &ListOptions, a: (bool, &Key, &str), b: (bool, &Key, &str)) -> Ordering {
+    if options.dirs_first && a.0 != b.0 {
+        return b.0.cmp(&a.0);
+    }
+    let by_key = match (a.1, b.1) {
+        (Key::Size(x), Key::Size(y)) => x.cmp(y),
+        (Key::Mtime(x), Key::Mtime(y)) => x.cmp(y),
+        (Key::Type(x), Key::Type(y)) => x.cmp(y),
+        _ => Ordering::Equal,
+    };
+    let by_name = match options.sort {
+        SortBy::Natural => natural_cmp(a.2, b.2),
+        _ => Ordering::Equal,
+    };
+    let ordering = by_key.then(by_name).then_with(|| a.2.cmp(b.2));
+    if options.descending {
+        ordering.reverse()
+    } else {
+        ordering
+    }
+}
+
+/// Compare names as people read them: case-insensitive, with runs of
+/// digits compared as numbers.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (x_digits, x_rest) = split_digits(a);
+                let (y_digits, y_rest) = split_digits(b);
+                let x_value = x_digits.trim_start_matches('0');
+                let y_value = y_digits.trim_start_matches('0');
+                let ordering = x_value
+                    .len()
+                    .cmp(&y_value.len())
+                    .then_with(|| x_value.cmp(y_value));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                (a, b) = (x_rest, y_rest);
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+            }
+        }
+    }
+}
+
+fn split_digits(s: &str) -> (&str, &str) {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+fn extension(name: &str, is_dir: bool) -> String {
+    if is_dir {
+        return String::new();
+    }
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => ext.to_lowercase(),
+        _ => String::new(),
+    }
+}
+
+/// Cursors are hex-encoded JSON: opaque to clients and safe in a URL.
+fn encode_cursor(cursor: &Cursor) -> String {
+    let json = serde_json::to_vec(cursor).unwrap_or_default();
+    json.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_cursor(cursor: &str) -> Result<Cursor, AppError> {
+    let invalid = || AppError::BadRequest("invalid cursor".into());
+    if !cursor.len().is_multiple_of(2) || !cursor.is_ascii() {
+        return Err(invalid());
+    }
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| invalid())?;
+    serde_json::from_slice(&bytes).map_err(|_| invalid())
+}
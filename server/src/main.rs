@@ -1,10 +1,31 @@
+mod access;
+mod archive;
 mod config;
+mod convert;
 mod error;
+mod events;
 mod fs;
+mod globs;
 mod http;
+mod image_info;
+mod index;
+mod lines;
+mod listing;
+mod proxy;
 mod pty;
+mod search;
 mod session;
+mod site;
+mod sniff;
+mod ssh;
 mod state;
+mod tail;
+mod terminal;
+mod text;
+mod thumbnail;
+mod tmux;
+mod transfer;
+mod upload;
 mod ws;
 
 use crate::config::Config;
@@ -28,6 +49,8 @@ async fn main() -> anyhow::Result<()> {
         std::env::var("LITETERM_CONFIG").unwrap_or_else(|_| "config/config.toml".to_string());
     let config = Config::from_file(config_path)?;
     let app_state = AppState::new(config)?;
+    app_state.start_index();
+    app_state.uploads.start_pruning(&app_state.config.upload);
 
     let app = Router::new()
         .merge(http::router())
@@ -0,0 +1,272 @@
+use crate::config::Config;
+use anyhow::Context;
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Request, Response, Version};
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use hyper_util::rt::TokioExecutor;
+use serde::Serialize;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// TCP socket state for LISTEN in `/proc/net/tcp*`.
+const TCP_LISTEN: &str = "0A";
+
+/// Headers that describe a single connection and must not be forwarded.
+const HOP_BY_HOP: &[HeaderName] = &[
+    header::CONNECTION,
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+/// HTTP client for dev servers listening on this host.
+#[derive(Clone)]
+pub struct ProxyClient {
+    client: Client<HttpConnector, Body>,
+}
+
+impl ProxyClient {
+    pub fn new() -> Self {
+        Self {
+            client: Client::builder(TokioExecutor::new()).build_http(),
+        }
+    }
+
+    /// Forward `req` to `127.0.0.1:{port}{path}`, where `path` includes the
+    /// query string and has the `/proxy/{port}` prefix already removed.
+    pub async fn forward(
+        &self,
+        port: u16,
+        path: &str,
+        req: Request<Body>,
+    ) -> anyhow::Result<Response<Body>> {
+        let (mut parts, body) = req.into_parts();
+        parts.uri = format!("http://127.0.0.1:{port}{path}")
+            .parse()
+            .context("invalid upstream uri")?;
+        parts.version = Version::HTTP_11;
+        prepare_request_headers(&mut parts.headers, port);
+
+        let response = self
+            .client
+            .request(Request::from_parts(parts, body))
+            .await
+            .with_context(|| format!("nothing answered on port {port}"))?;
+
+        let (mut parts, body) = response.into_parts();
+        strip_hop_by_hop(&mut parts.headers);
+        rewrite_response_headers(&mut parts.headers, port);
+        Ok(Response::from_parts(parts, Body::new(body)))
+    }
+}
+
+/// Path prefix the proxied app is served under.
+pub fn prefix(port: u16) -> String {
+    format!("/proxy/{port}")
+}
+
+/// Make a browser request look like it was sent to the dev server directly.
+///
+/// The original host and prefix are passed along in `X-Forwarded-*` headers
+/// for apps that can generate URLs under a base path.
+pub fn prepare_request_headers(headers: &mut HeaderMap, port: u16) {
+    strip_hop_by_hop(headers);
+
+    let upstream = format!("localhost:{port}");
+    if let Some(host) = headers.remove(header::HOST) {
+        headers.insert("x-forwarded-host", host);
+    }
+    if !headers.contains_key("x-forwarded-proto") {
+        headers.insert("x-forwarded-proto", HeaderValue::from_static("http"));
+    }
+    if let Ok(value) = HeaderValue::from_str(&prefix(port)) {
+        headers.insert("x-forwarded-prefix", value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&upstream) {
+        headers.insert(header::HOST, value);
+    }
+    // Dev servers commonly reject cross-origin websockets and form posts.
+    if headers.contains_key(header::ORIGIN) {
+        if let Ok(value) = HeaderValue::from_str(&format!("http://{upstream}")) {
+            headers.insert(header::ORIGIN, value);
+        }
+    }
+
+    // Keep the literm session to ourselves.
+    let cookies: Vec<String> = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .map(str::trim)
+        .filter(|c| !c.is_empty() && c.split('=').next() != Some("session"))
+        .map(str::to_string)
+        .collect();
+    headers.remove(header::COOKIE);
+    if !cookies.is_empty() {
+        if let Ok(value) = HeaderValue::from_str(&cookies.join("; ")) {
+            headers.insert(header::COOKIE, value);
+        }
+    }
+}
+
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let listed: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    for name in HOP_BY_HOP.iter().chain(&listed) {
+        headers.remove(name);
+    }
+    headers.remove("keep-alive");
+}
+
+/// Point redirects and cookies set by the dev server back under the prefix.
+fn rewrite_response_headers(headers: &mut HeaderMap, port: u16) {
+    if let Some(location) = headers
+        .get(header::LOCATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| rewrite_location(v, port))
+        .and_then(|v| HeaderValue::from_str(&v).ok())
+    {
+        headers.insert(header::LOCATION, location);
+    }
+
+    let cookies: Vec<HeaderValue> = headers
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .map(|v| rewrite_cookie_path(v, port))
+                .and_then(|v| HeaderValue::from_str(&v).ok())
+                .unwrap_or_else(|| value.clone())
+        })
+        .collect();
+    if !cookies.is_empty() {
+        headers.remove(header::SET_COOKIE);
+        for cookie in cookies {
+            headers.append(header::SET_COOKIE, cookie);
+        }
+    }
+}
+
+fn rewrite_location(location: &str, port: u16) -> Option<String> {
+    let prefix = prefix(port);
+    let path = [
+        format!("http://127.0.0.1:{port}"),
+        format!("http://localhost:{port}"),
+    ]
+    .iter()
+    .find_map(|origin| location.strip_prefix(origin.as_str()))
+    .map(|rest| if rest.is_empty() { "/" } else { rest })
+    .or_else(|| (location.starts_with('/') && !location.starts_with("//")).then_some(location))?;
+
+    // Apps configured with the prefix as their base path already include it.
+    if path == prefix || path.starts_with(&format!("{prefix}/")) {
+        return Some(path.to_string());
+    }
+    Some(format!("{prefix}{path}"))
+}
+
+fn rewrite_cookie_path(cookie: &str, port: u16) -> String {
+    let prefix = prefix(port);
+    cookie
+        .split(';')
+        .map(|attr| {
+            let trimmed = attr.trim();
+            match trimmed.split_once('=') {
+                Some((name, path))
+                    if name.eq_ignore_ascii_case("path")
+                        && path.starts_with('/')
+                        && !path.starts_with(&prefix) =>
+                {
+                    format!(" Path={prefix}{path}")
+                }
+                _ => attr.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// A TCP port with a listener the proxy can reach through `127.0.0.1`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct ListeningPort {
+    pub port: u16,
+    pub address: String,
+}
+
+/// Listening TCP ports on loopback or wildcard addresses, sorted by port.
+///
+/// Reads `/proc/net/tcp` and `/proc/net/tcp6`; returns an empty list where
+/// those are unavailable.
+pub fn listening_ports() -> Vec<ListeningPort> {
+    let mut ports = Vec::new();
+    for table in ["/proc/net/tcp", "/proc/net/tcp6"] {
+        let Ok(contents) = std::fs::read_to_string(table) else {
+            continue;
+        };
+        ports.extend(contents.lines().skip(1).filter_map(parse_tcp_line));
+    }
+    ports.sort();
+    ports.dedup_by_key(|p| p.port);
+    ports
+}
+
+fn parse_tcp_line(line: &str) -> Option<ListeningPort> {
+    let mut fields = line.split_whitespace();
+    let local = fields.nth(1)?;
+    let state = fields.nth(1)?;
+    if state != TCP_LISTEN {
+        return None;
+    }
+
+    let (addr, port) = local.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let address = match addr.len() {
+        8 => {
+            let ip = Ipv4Addr::from(u32::from_be(u32::from_str_radix(addr, 16).ok()?));
+            if !(ip.is_loopback() || ip.is_unspecified()) {
+                return None;
+            }
+            ip.to_string()
+        }
+        32 => {
+            // Four 32-bit words, each printed in host byte order.
+            let mut octets = [0u8; 16];
+            for (i, chunk) in octets.chunks_mut(4).enumerate() {
+                let word = u32::from_str_radix(&addr[i * 8..i * 8 + 8], 16).ok()?;
+                chunk.copy_from_slice(&u32::from_be(word).to_be_bytes());
+            }
+            let ip = Ipv6Addr::from(octets);
+            match ip.to_ipv4_mapped() {
+                Some(v4) if v4.is_loopback() || v4.is_unspecified() => v4.to_string(),
+                // `::1` only listeners are not reachable through 127.0.0.1.
+                None if ip.is_unspecified() => ip.to_string(),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    Some(ListeningPort { port, address })
+}
+
+/// Listening ports that `/proxy/{port}` would accept under `config`.
+pub fn proxyable_ports(config: &Config) -> Vec<ListeningPort> {
+    listening_ports()
+        .into_iter()
+        .filter(|p| is_proxyable(config, p.port))
+        .collect()
+}
+
+pub fn is_proxyable(config: &Config, port: u16) -> bool {
+    config.proxy.allows(port) && port != config.server.port
+}
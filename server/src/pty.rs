@@ -27,18 +27,29 @@ impl PtyManager {
         rows: u16,
         cols: u16,
     ) -> anyhow::Result<(PtySession, Box<dyn Read + Send>)> {
-        let mut size = PtySize::default();
-        size.rows = rows.max(1);
-        size.cols = cols.max(1);
-        let system = self.system.lock().expect("pty system mutex poisoned");
-        let pair = system.openpty(size)?;
-        let portable_pty::PtyPair { master, slave } = pair;
-
         let shell = std::env::var("SHELL")
             .ok()
             .filter(|s| !s.trim().is_empty())
             .unwrap_or_else(|| "/bin/bash".into());
-        let mut cmd = CommandBuilder::new(shell);
+        self.spawn(rows, cols, CommandBuilder::new(shell))
+    }
+
+    /// Run an arbitrary command on a fresh PTY.
+    pub fn spawn(
+        &self,
+        rows: u16,
+        cols: u16,
+        mut cmd: CommandBuilder,
+    ) -> anyhow::Result<(PtySession, Box<dyn Read + Send>)> {
+        let size = PtySize {
+            rows: rows.max(1),
+            cols: cols.max(1),
+            ..Default::default()
+        };
+        let system = self.system.lock().expect("pty system mutex poisoned");
+        let pair = system.openpty(size)?;
+        let portable_pty::PtyPair { master, slave } = pair;
+
         cmd.env("TERM", "xterm-256color");
         let child = slave.spawn_command(cmd)?;
         let reader = master.try_clone_reader()?;
@@ -85,6 +96,33 @@ impl PtySession {
         Ok(())
     }
 
+    /// Deliver a signal to the foreground process group of the terminal.
+    #[cfg(unix)]
+    pub async fn signal(&self, signal: libc::c_int) -> anyhow::Result<()> {
+        let leader = self.master.lock().await.process_group_leader();
+        let res = match leader {
+            Some(pgid) => unsafe { libc::killpg(pgid, signal) },
+            None => {
+                let pid = self.child.lock().await.process_id();
+                let pid = pid.ok_or_else(|| anyhow::anyhow!("pty child has no pid"))?;
+                unsafe { libc::kill(pid as libc::pid_t, signal) }
+            }
+        };
+        if res != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+
+    /// Exit code of the shell, once it has exited.
+    pub async fn exit_code(&self) -> Option<u32> {
+        let mut child = self.child.lock().await;
+        tokio::task::block_in_place(|| child.try_wait())
+            .ok()
+            .flatten()
+            .map(|status| status.exit_code())
+    }
+
     pub async fn shutdown(&self) {
         let mut child = self.child.lock().await;
         let _ = tokio::task::block_in_place(|| child.kill());
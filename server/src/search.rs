@@ -0,0 +1,188 @@
+use crate::{fs::FsRoot, globs::PathGlobs};
+use ignore::WalkBuilder;
+use regex::Regex;
+use serde::Serialize;
+use std::io::Read;
+use std::path::Path;
+
+/// Files larger than this are not searched.
+const MAX_FILE_SIZE: u64 = 32 * 1024 * 1024;
+/// A NUL byte this early in a file marks it as binary.
+const BINARY_PROBE: usize = 8 * 1024;
+/// Matched and context lines are cut to this many characters, so a match in
+/// minified code does not ship the whole file.
+const MAX_LINE_CHARS: usize = 500;
+
+pub struct SearchOptions {
+    pub pattern: Regex,
+    pub globs: PathGlobs,
+    pub show_hidden: bool,
+    /// Lines of context before and after each matching line.
+    pub context: usize,
+    pub max_results: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SearchEvent {
+    /// One matching line. `line` and `column` are 1-based, `ranges` are
+    /// `[start, end)` character offsets of every match within `text`.
+    Match {
+        path: String,
+        line: usize,
+        column: usize,
+        text: String,
+        ranges: Vec<(usize, usize)>,
+        before: Vec<String>,
+        after: Vec<String>,
+    },
+    Done {
+        matches: usize,
+        files: usize,
+        /// The result cap was hit before the walk finished.
+        truncated: bool,
+    },
+}
+
+/// Search the files below `dir`, reporting paths as `root` shows them.
+/// Blocking; stops as soon as `emit` returns false or `cancelled` returns
+/// true, which is checked for every walked entry so a search that finds
+/// nothing still ends once nobody is waiting for it.
+pub fn search(
+    root: &FsRoot,
+    dir: &Path,
+    options: &SearchOptions,
+    cancelled: impl Fn() -> bool,
+    mut emit: impl FnMut(SearchEvent) -> bool,
+) {
+    let globs = options.globs.clone();
+    let fs = root.clone();
+    let base = dir.to_path_buf();
+    let walker = WalkBuilder::new(dir)
+        .hidden(!options.show_hidden)
+        .follow_links(false)
+        // Honour .gitignore in plain directories too, not only in checkouts.
+        .require_git(false)
+        .git_global(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .filter_entry(move |entry| {
+            let rel = entry.path().strip_prefix(&base).unwrap_or(entry.path());
+            entry.depth() == 0
+                || (entry.file_name() != ".git"
+                    && !globs.excluded(rel)
+                    && !fs.is_hidden(entry.path()))
+        })
+        .build();
+
+    let mut matches = 0;
+    let mut files = 0;
+    for entry in walker {
+        if cancelled() {
+            return;
+        }
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                tracing::debug!("search: {err}");
+                continue;
+            }
+        };
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        let rel_to_dir = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+        if !options.globs.included(rel_to_dir) {
+            continue;
+        }
+        let Some(text) = read_text(entry.path()) else {
+            continue;
+        };
+        if !options.pattern.is_match(&text) {
+            continue;
+        }
+
+        let path = root
+            .to_relative(entry.path())
+            .unwrap_or_else(|| entry.path().to_string_lossy().to_string());
+        let lines: Vec<&str> = text.lines().collect();
+        let mut matched_file = false;
+        for (index, line) in lines.iter().enumerate() {
+            let ranges = match_ranges(&options.pattern, line);
+            if ranges.is_empty() {
+                continue;
+            }
+            if !matched_file {
+                matched_file = true;
+                files += 1;
+            }
+            matches += 1;
+            let before = lines[index.saturating_sub(options.context)..index]
+                .iter()
+                .map(|l| clip(l))
+                .collect();
+            let after = lines[index + 1..(index + 1 + options.context).min(lines.len())]
+                .iter()
+                .map(|l| clip(l))
+                .collect();
+            let event = SearchEvent::Match {
+                path: path.clone(),
+                line: index + 1,
+                column: ranges[0].0 + 1,
+                text: clip(line),
+                ranges,
+                before,
+                after,
+            };
+            if !emit(event) {
+                return;
+            }
+            if matches >= options.max_results {
+                emit(SearchEvent::Done {
+                    matches,
+                    files,
+                    truncated: true,
+                });
+                return;
+            }
+        }
+    }
+
+    emit(SearchEvent::Done {
+        matches,
+        files,
+        truncated: false,
+    });
+}
+
+/// Contents of a searchable file: not too large and not binary.
+fn read_text(path: &Path) -> Option<String> {
+    let file = std::fs::File::open(path).ok()?;
+    if file.metadata().ok()?.len() > MAX_FILE_SIZE {
+        return None;
+    }
+    let mut data = Vec::new();
+    file.take(MAX_FILE_SIZE).read_to_end(&mut data).ok()?;
+    if data[..data.len().min(BINARY_PROBE)].contains(&0) {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// Character ranges of the non-empty matches in `line` that fall within
+/// the clipped text.
+fn match_ranges(pattern: &Regex, line: &str) -> Vec<(usize, usize)> {
+    pattern
+        .find_iter(line)
+        .filter(|m| !m.is_empty())
+        .map(|m| {
+            let start = line[..m.start()].chars().count();
+            (start, start + m.as_str().chars().count())
+        })
+        .take_while(|&(start, _)| start < MAX_LINE_CHARS)
+        .map(|(start, end)| (start, end.min(MAX_LINE_CHARS)))
+        .collect()
+}
+
+fn clip(line: &str) -> String {
+    line.chars().take(MAX_LINE_CHARS).collect()
+}
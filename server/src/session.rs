@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
@@ -6,10 +7,17 @@ use uuid::Uuid;
 
 #[derive(Clone)]
 pub struct SessionStore {
-    inner: Arc<RwLock<HashMap<String, Instant>>>,
+    inner: Arc<RwLock<HashMap<String, Session>>>,
     ttl: Duration,
 }
 
+struct Session {
+    expires_at: Instant,
+    /// Browsing root chosen with `POST /api/fs/root`; `None` means the
+    /// configured `root_dir`.
+    root: Option<PathBuf>,
+}
+
 impl SessionStore {
     pub fn new(ttl_minutes: u64) -> Self {
         Self {
@@ -20,9 +28,12 @@ impl SessionStore {
 
     pub async fn create_session(&self) -> String {
         let id = Uuid::new_v4().to_string();
-        let expires_at = Instant::now() + self.ttl;
+        let session = Session {
+            expires_at: Instant::now() + self.ttl,
+            root: None,
+        };
         let mut guard = self.inner.write().await;
-        guard.insert(id.clone(), expires_at);
+        guard.insert(id.clone(), session);
         id
     }
 
@@ -37,9 +48,30 @@ impl SessionStore {
         guard.remove(id);
     }
 
+    /// The session's browsing root, if it has chosen one.
+    pub async fn root(&self, id: &str) -> Option<PathBuf> {
+        let guard = self.inner.read().await;
+        guard
+            .get(id)
+            .filter(|session| session.expires_at > Instant::now())
+            .and_then(|session| session.root.clone())
+    }
+
+    /// Returns false if the session does not exist (anymore).
+    pub async fn set_root(&self, id: &str, root: PathBuf) -> bool {
+        let mut guard = self.inner.write().await;
+        match guard.get_mut(id) {
+            Some(session) if session.expires_at > Instant::now() => {
+                session.root = Some(root);
+                true
+            }
+            _ => false,
+        }
+    }
+
     async fn prune_expired(&self) {
         let mut guard = self.inner.write().await;
         let now = Instant::now();
-        guard.retain(|_, &mut expires_at| expires_at > now);
+        guard.retain(|_, session| session.expires_at > now);
     }
 }
@@ -0,0 +1,144 @@
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// A rebuild touches many files at once; wait for it to settle before reloading.
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Directories published as static sites under `/site/{token}/`.
+#[derive(Clone, Default)]
+pub struct SiteMounts {
+    inner: Arc<RwLock<HashMap<String, Site>>>,
+}
+
+struct Site {
+    dir: PathBuf,
+    /// Started by the first live-reload subscriber, stopped on unmount.
+    watch: Option<SiteWatch>,
+}
+
+/// One watcher per site, its debounced changes fanned out to every subscriber.
+struct SiteWatch {
+    reload: broadcast::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for SiteWatch {
+    fn drop(&mut self) {
+        // Drops the watcher and closes the subscribers' streams
+        self.task.abort();
+    }
+}
+
+impl SiteMounts {
+    /// Mount a canonical directory, reusing the token if it is already mounted.
+    pub async fn mount(&self, dir: PathBuf) -> String {
+        let mut guard = self.inner.write().await;
+        if let Some((token, _)) = guard.iter().find(|(_, site)| site.dir == dir) {
+            return token.clone();
+        }
+        let token = Uuid::new_v4().simple().to_string();
+        guard.insert(token.clone(), Site { dir, watch: None });
+        token
+    }
+
+    pub async fn get(&self, token: &str) -> Option<PathBuf> {
+        self.inner
+            .read()
+            .await
+            .get(token)
+            .map(|site| site.dir.clone())
+    }
+
+    pub async fn unmount(&self, token: &str) -> bool {
+        self.inner.write().await.remove(token).is_some()
+    }
+
+    /// Receive a message whenever something under the site changes, or `None`
+    /// for an unknown token.
+    pub async fn subscribe(&self, token: &str) -> notify::Result<Option<broadcast::Receiver<()>>> {
+        let mut guard = self.inner.write().await;
+        let Some(site) = guard.get_mut(token) else {
+            return Ok(None);
+        };
+        let watch = match &mut site.watch {
+            Some(watch) => watch,
+            watch @ None => watch.insert(SiteWatch::start(&site.dir)?),
+        };
+        Ok(Some(watch.reload.subscribe()))
+    }
+}
+
+impl SiteWatch {
+    fn start(dir: &Path) -> notify::Result<Self> {
+        let (fs_tx, mut fs_rx) = mpsc::unbounded_channel();
+        let mut watcher = RecommendedWatcher::new(
+            move |res| {
+                let _ = fs_tx.send(res);
+            },
+            notify::Config::default(),
+        )?;
+        watcher.watch(dir, RecursiveMode::Recursive)?;
+
+        let (reload, _) = broadcast::channel(1);
+        let tx = reload.clone();
+        let task = tokio::spawn(async move {
+            let _watcher = watcher;
+            while let Some(event) = fs_rx.recv().await {
+                match event {
+                    Ok(ev) if matches!(ev.kind, EventKind::Access(_)) => continue,
+                    Ok(_) => {}
+                    Err(err) => {
+                        tracing::warn!("site watcher error: {err:?}");
+                        continue;
+                    }
+                }
+                tokio::time::sleep(RELOAD_DEBOUNCE).await;
+                while fs_rx.try_recv().is_ok() {}
+                // Nobody listening right now is fine
+                let _ = tx.send(());
+            }
+        });
+        Ok(Self { reload, task })
+    }
+}
+
+/// Resolve a request path inside a mounted directory, refusing anything that
+/// escapes it (including through symlinks).
+pub fn resolve(mount: &Path, rest: &str) -> std::io::Result<PathBuf> {
+    let mut candidate = mount.to_path_buf();
+    for segment in rest.split('/').filter(|s| !s.is_empty() && *s != ".") {
+        if segment == ".." {
+            return Err(std::io::ErrorKind::PermissionDenied.into());
+        }
+        candidate.push(segment);
+    }
+    let canonical = candidate.canonicalize()?;
+    if !canonical.starts_with(mount) {
+        return Err(std::io::ErrorKind::PermissionDenied.into());
+    }
+    Ok(canonical)
+}
+
+/// Insert the live-reload client before `</body>`, or at the end of documents
+/// without one. Works on bytes so pages in other encodings stay intact.
+pub fn inject_live_reload(html: &[u8], token: &str) -> Vec<u8> {
+    let script = format!(
+        "<script>(function(){{var es=new EventSource(\"/api/site/{token}/events\");\
+         es.addEventListener(\"reload\",function(){{es.close();location.reload();}});}})();</script>"
+    );
+    let pos = html
+        .windows(b"</body>".len())
+        .rposition(|window| window.eq_ignore_ascii_case(b"</body>"))
+        .unwrap_or(html.len());
+    let mut out = Vec::with_capacity(html.len() + script.len());
+    out.extend_from_slice(&html[..pos]);
+    out.extend_from_slice(script.as_bytes());
+    out.extend_from_slice(&html[pos..]);
+    out
+}
@@ -0,0 +1,221 @@
+use mime_guess::MimeGuess;
+use serde::Serialize;
+use std::io::Read;
+use std::path::Path;
+
+/// Bytes read from the start of a file to recognise its type; enough for
+/// the `ustar` marker of tar archives at offset 257.
+pub const SNIFF_LEN: usize = 512;
+
+/// A file format recognised by its magic number.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Magic {
+    pub mime: &'static str,
+    pub description: &'static str,
+}
+
+/// `(offset, magic, mime, description)` of formats recognisable from their
+/// first bytes. More specific entries come first.
+#[rustfmt::skip]
+const SIGNATURES: &[(usize, &[u8], &str, &str)] = &[
+    (0,   b"\x89PNG\r\n\x1a\n",                "image/png",                                     "PNG image"),
+    (0,   b"\xff\xd8\xff",                     "image/jpeg",                                    "JPEG image"),
+    (0,   b"GIF87a",                           "image/gif",                                     "GIF image"),
+    (0,   b"GIF89a",                           "image/gif",                                     "GIF image"),
+    (0,   b"II*\0",                            "image/tiff",                                    "TIFF image (little-endian)"),
+    (0,   b"MM\0*",                            "image/tiff",                                    "TIFF image (big-endian)"),
+    (0,   b"v/1\x01",                          "image/x-exr",                                   "OpenEXR image"),
+    (0,   b"8BPS",                             "image/vnd.adobe.photoshop",                     "Photoshop document"),
+    (0,   b"%PDF-",                            "application/pdf",                               "PDF document"),
+    (0,   b"PK\x03\x04",                       "application/zip",                               "ZIP archive"),
+    (0,   b"PK\x05\x06",                       "application/zip",                               "ZIP archive (empty)"),
+    (0,   b"\x1f\x8b",                         "application/gzip",                              "gzip compressed data"),
+    (0,   b"BZh",                              "application/x-bzip2",                           "bzip2 compressed data"),
+    (0,   b"\xfd7zXZ\0",                       "application/x-xz",                              "xz compressed data"),
+    (0,   b"\x28\xb5\x2f\xfd",                 "application/zstd",                              "Zstandard compressed data"),
+    (0,   b"7z\xbc\xaf\x27\x1c",               "application/x-7z-compressed",                   "7-Zip archive"),
+    (0,   b"Rar!\x1a\x07",                     "application/vnd.rar",                           "RAR archive"),
+    (257, b"ustar",                            "application/x-tar",                             "tar archive"),
+    (0,   b"\x89HDF\r\n\x1a\n",                "application/x-hdf5",                            "HDF5 data"),
+    (0,   b"CDF\x01",                          "application/x-netcdf",                          "NetCDF classic data"),
+    (0,   b"CDF\x02",                          "application/x-netcdf",                          "NetCDF 64-bit offset data"),
+    (0,   b"SIMPLE  =",                        "application/fits",                              "FITS data"),
+    (0,   b"\x93NUMPY",                        "application/x-npy",                             "NumPy array"),
+    (0,   b"PAR1",                             "application/vnd.apache.parquet",                "Parquet data"),
+    (0,   b"SQLite format 3\0",                "application/vnd.sqlite3",                       "SQLite database"),
+    (0,   b"\x7fELF",                          "application/x-executable",                      "ELF executable"),
+    (0,   b"\xcf\xfa\xed\xfe",                 "application/x-mach-binary",                     "Mach-O executable (64-bit)"),
+    (0,   b"\xce\xfa\xed\xfe",                 "application/x-mach-binary",                     "Mach-O executable (32-bit)"),
+    (0,   b"MZ",                               "application/vnd.microsoft.portable-executable", "Windows executable"),
+    (0,   b"\0asm",                            "application/wasm",                              "WebAssembly module"),
+    (0,   b"\xca\xfe\xba\xbe",                 "application/java-vm",                           "Java class or Mach-O universal binary"),
+    (0,   b"fLaC",                             "audio/flac",                                    "FLAC audio"),
+    (0,   b"OggS",                             "audio/ogg",                                     "Ogg media"),
+    (0,   b"ID3",                              "audio/mpeg",                                    "MP3 audio"),
+    (4,   b"ftyp",                             "video/mp4",                                     "MPEG-4 media"),
+    (0,   b"\x1a\x45\xdf\xa3",                 "video/webm",                                    "Matroska / WebM media"),
+    (0,   b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1", "application/x-ole-storage",                     "OLE compound document"),
+];
+
+/// RIFF containers share one magic number; the form type tells them apart.
+const RIFF_FORMS: &[(&[u8], &str, &str)] = &[
+    (b"WEBP", "image/webp", "WebP image"),
+    (b"WAVE", "audio/wav", "WAV audio"),
+    (b"AVI ", "video/x-msvideo", "AVI video"),
+];
+
+/// How the client should preview a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreviewKind {
+    /// Displayable by a browser as is, or after `/api/fs/convert`.
+    Image,
+    Pdf,
+    Markdown,
+    Hdf5,
+    Text,
+    Video,
+    Audio,
+    /// Nothing better than a hex dump.
+    Binary,
+}
+
+/// Images browsers display natively.
+const BROWSER_IMAGES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/svg+xml",
+    "image/bmp",
+    "image/avif",
+    "image/x-icon",
+];
+
+/// Images browsers can't display, which `/api/fs/convert` turns into PNG.
+const CONVERTED_IMAGES: &[&str] = &[
+    "image/tiff",
+    "image/x-exr",
+    "image/x-portable-bitmap",
+    "image/x-portable-graymap",
+    "image/x-portable-pixmap",
+    "image/x-portable-anymap",
+];
+
+/// Non-`text/*` types that are text all the same.
+const TEXT_TYPES: &[&str] = &[
+    "application/json",
+    "application/xml",
+    "application/javascript",
+    "application/toml",
+    "application/x-yaml",
+    "application/x-sh",
+    "application/x-python",
+    "application/sql",
+    "application/x-tex",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileType {
+    pub mime: String,
+    pub preview: PreviewKind,
+}
+
+/// The type of the file at `path`, judged by content first: a known magic
+/// number wins over the extension, unless the extension says text and the
+/// content is text. Without either, a text heuristic decides. Blocking.
+pub fn detect(path: &Path) -> FileType {
+    let head = read_head(path);
+    let by_extension = MimeGuess::from_path(path).first();
+    let text = looks_like_text(&head);
+    let mime = match (magic(&head), by_extension) {
+        (Some(_), Some(ext)) if ext.type_() == mime_guess::mime::TEXT && text => {
+            ext.essence_str().to_string()
+        }
+        (Some(magic), _) => magic.mime.to_string(),
+        (None, Some(ext)) => ext.essence_str().to_string(),
+        (None, None) if text => "text/plain".to_string(),
+        (None, None) => "application/octet-stream".to_string(),
+    };
+    let preview = preview_kind(&mime, text);
+    FileType { mime, preview }
+}
+
+/// The type of the file at `path` judged by its extension alone, without
+/// reading it; `None` when the extension says nothing.
+pub fn guess(path: &Path) -> Option<FileType> {
+    let mime = MimeGuess::from_path(path)
+        .first()?
+        .essence_str()
+        .to_string();
+    let preview = preview_kind(&mime, false);
+    Some(FileType { mime, preview })
+}
+
+fn preview_kind(mime: &str, text: bool) -> PreviewKind {
+    match mime {
+        "application/pdf" => PreviewKind::Pdf,
+        "application/x-hdf5" => PreviewKind::Hdf5,
+        "text/markdown" | "text/x-markdown" => PreviewKind::Markdown,
+        _ if BROWSER_IMAGES.contains(&mime) || CONVERTED_IMAGES.contains(&mime) => {
+            PreviewKind::Image
+        }
+        _ if mime.starts_with("video/") => PreviewKind::Video,
+        _ if mime.starts_with("audio/") => PreviewKind::Audio,
+        _ if mime.starts_with("text/") || TEXT_TYPES.contains(&mime) => PreviewKind::Text,
+        // Unknown types like `.dat` or `.out` are often plain text.
+        "application/octet-stream" if text => PreviewKind::Text,
+        _ => PreviewKind::Binary,
+    }
+}
+
+/// The first [`SNIFF_LEN`] bytes of the file at `path`, or fewer if it is
+/// shorter or unreadable. Blocking.
+pub fn read_head(path: &Path) -> Vec<u8> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    if let Ok(file) = std::fs::File::open(path) {
+        let _ = file.take(SNIFF_LEN as u64).read_to_end(&mut head);
+    }
+    head
+}
+
+/// The format whose magic number `head` starts with, if any.
+pub fn magic(head: &[u8]) -> Option<Magic> {
+    if head.starts_with(b"RIFF") && head.len() >= 12 {
+        return RIFF_FORMS
+            .iter()
+            .find(|(form, _, _)| &head[8..12] == *form)
+            .map(|&(_, mime, description)| Magic { mime, description });
+    }
+    SIGNATURES
+        .iter()
+        .find(|(offset, magic, _, _)| {
+            head.get(*offset..)
+                .is_some_and(|rest| rest.starts_with(magic))
+        })
+        .map(|&(_, _, mime, description)| Magic { mime, description })
+}
+
+/// Text has no NULs and is either UTF-8 or, in a legacy encoding, mostly
+/// free of control characters. Empty files count as text.
+fn looks_like_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    if is_utf8_prefix(head) {
+        return true;
+    }
+    let control = head
+        .iter()
+        .filter(|&&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b))
+        .count();
+    control * 20 < head.len()
+}
+
+/// UTF-8, allowing a character cut off at the end of the sample.
+fn is_utf8_prefix(head: &[u8]) -> bool {
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(err) => err.error_len().is_none() && head.len() - err.valid_up_to() < 4,
+    }
+}
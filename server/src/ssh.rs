@@ -0,0 +1,271 @@
+use crate::config::{KnownHostsPolicy, SshProfile};
+use anyhow::{anyhow, bail, Context};
+use async_trait::async_trait;
+use russh::client::{self, Handle, Msg};
+use russh::{Channel, ChannelMsg, Disconnect, Sig};
+use russh_keys::key::PublicKey;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::mpsc as std_mpsc;
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+enum SshCommand {
+    Data(Vec<u8>),
+    Resize { rows: u16, cols: u16 },
+    Signal(Sig),
+    Close,
+}
+
+/// An interactive shell on a remote host, driven by a background task that
+/// owns the SSH channel.
+pub struct SshSession {
+    id: Uuid,
+    commands: mpsc::UnboundedSender<SshCommand>,
+    exit_status: Arc<StdMutex<Option<u32>>>,
+}
+
+impl SshSession {
+    pub async fn connect(
+        profile: &SshProfile,
+        rows: u16,
+        cols: u16,
+    ) -> anyhow::Result<(Self, Box<dyn Read + Send>)> {
+        let handler = HostKeyCheck {
+            host: profile.host.clone(),
+            port: profile.port,
+            policy: profile.known_hosts,
+            known_hosts_path: match &profile.known_hosts_path {
+                Some(path) => PathBuf::from(path),
+                None => default_known_hosts_path()?,
+            },
+        };
+
+        let config = Arc::new(client::Config::default());
+        let mut handle = client::connect(config, (profile.host.as_str(), profile.port), handler)
+            .await
+            .with_context(|| {
+                format!("ssh connection to {}:{} failed", profile.host, profile.port)
+            })?;
+
+        let key = russh_keys::load_secret_key(&profile.key_path, profile.key_passphrase.as_deref())
+            .with_context(|| format!("failed to load ssh key {}", profile.key_path))?;
+        if !handle
+            .authenticate_publickey(profile.user.clone(), Arc::new(key))
+            .await?
+        {
+            bail!(
+                "public key authentication rejected for {}@{}",
+                profile.user,
+                profile.host
+            );
+        }
+
+        let channel = handle.channel_open_session().await?;
+        channel
+            .request_pty(
+                false,
+                "xterm-256color",
+                u32::from(cols.max(1)),
+                u32::from(rows.max(1)),
+                0,
+                0,
+                &[],
+            )
+            .await?;
+        channel.request_shell(false).await?;
+
+        let (output_tx, output_rx) = std_mpsc::channel();
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let exit_status = Arc::new(StdMutex::new(None));
+        tokio::spawn(pump(
+            handle,
+            channel,
+            command_rx,
+            output_tx,
+            exit_status.clone(),
+        ));
+
+        Ok((
+            Self {
+                id: Uuid::new_v4(),
+                commands: command_tx,
+                exit_status,
+            },
+            Box::new(ChannelReader {
+                rx: output_rx,
+                pending: Vec::new(),
+                pos: 0,
+            }),
+        ))
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub async fn write(&self, data: &[u8]) -> anyhow::Result<()> {
+        self.send(SshCommand::Data(data.to_vec()))
+    }
+
+    pub async fn resize(&self, rows: u16, cols: u16) -> anyhow::Result<()> {
+        self.send(SshCommand::Resize { rows, cols })
+    }
+
+    pub async fn signal(&self, signal: Sig) -> anyhow::Result<()> {
+        self.send(SshCommand::Signal(signal))
+    }
+
+    pub fn exit_status(&self) -> Option<u32> {
+        *self
+            .exit_status
+            .lock()
+            .expect("ssh exit status mutex poisoned")
+    }
+
+    pub async fn shutdown(&self) {
+        let _ = self.send(SshCommand::Close);
+    }
+
+    fn send(&self, command: SshCommand) -> anyhow::Result<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| anyhow!("ssh session closed"))
+    }
+}
+
+async fn pump(
+    handle: Handle<HostKeyCheck>,
+    mut channel: Channel<Msg>,
+    mut commands: mpsc::UnboundedReceiver<SshCommand>,
+    output: std_mpsc::Sender<Vec<u8>>,
+    exit_status: Arc<StdMutex<Option<u32>>>,
+) {
+    loop {
+        tokio::select! {
+            msg = channel.wait() => match msg {
+                Some(ChannelMsg::Data { data }) | Some(ChannelMsg::ExtendedData { data, .. }) => {
+                    if output.send(data.to_vec()).is_err() {
+                        break;
+                    }
+                }
+                Some(ChannelMsg::ExitStatus { exit_status: code }) => {
+                    *exit_status.lock().expect("ssh exit status mutex poisoned") = Some(code);
+                }
+                Some(ChannelMsg::Close) | None => break,
+                Some(_) => {}
+            },
+            command = commands.recv() => {
+                let res = match command {
+                    Some(SshCommand::Data(bytes)) => channel.data(&bytes[..]).await,
+                    Some(SshCommand::Resize { rows, cols }) => {
+                        channel
+                            .window_change(u32::from(cols.max(1)), u32::from(rows.max(1)), 0, 0)
+                            .await
+                    }
+                    Some(SshCommand::Signal(signal)) => channel.signal(signal).await,
+                    Some(SshCommand::Close) | None => {
+                        let _ = channel.close().await;
+                        break;
+                    }
+                };
+                if let Err(err) = res {
+                    tracing::warn!("ssh channel request failed: {err:?}");
+                }
+            }
+        }
+    }
+
+    let _ = handle.disconnect(Disconnect::ByApplication, "", "en").await;
+    // Dropping `output` here ends the reader with EOF.
+}
+
+struct HostKeyCheck {
+    host: String,
+    port: u16,
+    policy: KnownHostsPolicy,
+    known_hosts_path: PathBuf,
+}
+
+#[async_trait]
+impl client::Handler for HostKeyCheck {
+    type Error = anyhow::Error;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &PublicKey,
+    ) -> Result<bool, Self::Error> {
+        if self.policy == KnownHostsPolicy::AcceptAny {
+            return Ok(true);
+        }
+
+        let known = russh_keys::check_known_hosts_path(
+            &self.host,
+            self.port,
+            server_public_key,
+            &self.known_hosts_path,
+        );
+        match known {
+            Ok(true) => Ok(true),
+            Ok(false) if self.policy == KnownHostsPolicy::AcceptNew => {
+                tracing::info!(
+                    "learning new host key for {}:{} ({})",
+                    self.host,
+                    self.port,
+                    server_public_key.fingerprint()
+                );
+                russh_keys::learn_known_hosts_path(
+                    &self.host,
+                    self.port,
+                    server_public_key,
+                    &self.known_hosts_path,
+                )?;
+                Ok(true)
+            }
+            Ok(false) => {
+                tracing::warn!(
+                    "rejecting unknown host key for {}:{} ({})",
+                    self.host,
+                    self.port,
+                    server_public_key.fingerprint()
+                );
+                Ok(false)
+            }
+            Err(err) => {
+                tracing::warn!("host key check failed for {}: {err}", self.host);
+                Ok(false)
+            }
+        }
+    }
+}
+
+fn default_known_hosts_path() -> anyhow::Result<PathBuf> {
+    let home = std::env::var_os("HOME").ok_or_else(|| anyhow!("HOME is not set"))?;
+    Ok(PathBuf::from(home).join(".ssh").join("known_hosts"))
+}
+
+/// Blocking reader over the channel output, matching the PTY reader interface.
+struct ChannelReader {
+    rx: std_mpsc::Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.pending.len() {
+            match self.rx.recv() {
+                Ok(data) => {
+                    self.pending = data;
+                    self.pos = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.pending.len() - self.pos);
+        buf[..n].copy_from_slice(&self.pending[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
@@ -1,5 +1,18 @@
-use crate::{config::Config, fs::FsService, pty::PtyManager, session::SessionStore};
+use crate::{
+    config::Config,
+    events::EventBus,
+    fs::{FsRoot, FsService},
+    index::PathIndex,
+    lines::LineIndexes,
+    proxy::ProxyClient,
+    pty::PtyManager,
+    session::SessionStore,
+    site::SiteMounts,
+    thumbnail::Thumbnails,
+    upload::UploadStore,
+};
 use anyhow::Context;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 #[derive(Clone)]
@@ -8,20 +21,82 @@ pub struct AppState {
     pub fs: Arc<FsService>,
     pub pty: Arc<PtyManager>,
     pub sessions: SessionStore,
+    pub proxy: ProxyClient,
+    pub sites: SiteMounts,
+    pub events: EventBus,
+    pub uploads: UploadStore,
+    /// One path index per allowed root that is not inside another one.
+    pub indexes: Arc<Vec<(PathBuf, PathIndex)>>,
+    pub lines: LineIndexes,
+    pub thumbnails: Thumbnails,
 }
 
 impl AppState {
     pub fn new(config: Config) -> anyhow::Result<Self> {
-        let fs =
-            FsService::new(&config.server.root_dir).context("failed to init filesystem service")?;
+        let fs = FsService::new(&config.server, &config.fs)
+            .context("failed to init filesystem service")?;
         let pty = PtyManager::new().context("failed to initialize PTY manager")?;
         let sessions = SessionStore::new(config.server.session_timeout_minutes);
+        let roots = fs.allowed_roots();
+        let indexes = roots
+            .iter()
+            .filter(|root| {
+                !roots
+                    .iter()
+                    .any(|other| other != *root && root.starts_with(other))
+            })
+            .map(|root| (root.clone(), PathIndex::default()))
+            .collect();
+
+        let events = EventBus::new();
+        let lines = LineIndexes::new(Path::new(&config.server.cache_dir), events.clone());
+        let thumbnails = Thumbnails::new(Path::new(&config.server.cache_dir), &config.thumbnails);
 
         Ok(Self {
             config: Arc::new(config),
             fs: Arc::new(fs),
             pty: Arc::new(pty),
             sessions,
+            proxy: ProxyClient::new(),
+            sites: SiteMounts::default(),
+            events,
+            uploads: UploadStore::default(),
+            indexes: Arc::new(indexes),
+            lines,
+            thumbnails,
+        })
+    }
+
+    /// Start indexing the allowed roots for `/api/fs/find`, if enabled.
+    pub fn start_index(&self) {
+        if self.config.index.enabled {
+            for (root, index) in self.indexes.iter() {
+                index.start(
+                    root.clone(),
+                    self.config.index.max_files,
+                    self.config.features.enable_watch,
+                );
+            }
+        }
+    }
+
+    /// The index covering `root`, and `root` relative to the indexed root.
+    pub fn index_for(&self, root: &Path) -> Option<(PathIndex, String)> {
+        self.indexes.iter().find_map(|(indexed, index)| {
+            let rel = root.strip_prefix(indexed).ok()?;
+            Some((index.clone(), rel.to_string_lossy().to_string()))
         })
     }
+
+    /// The filesystem view of the session `session_id`: the root it chose,
+    /// or the configured `root_dir`.
+    pub async fn fs_root(&self, session_id: Option<&str>) -> FsRoot {
+        match session_id {
+            Some(id) => match self.sessions.root(id).await {
+                Some(root) => self.fs.view(root),
+                None => self.fs.default_root(),
+            },
+            None => self.fs.default_root(),
+        }
+    }
 }
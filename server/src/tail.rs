@@ -0,0 +1,266 @@
+use crate::access::Op;
+use crate::fs::FsRoot;
+use crate::lines::MAX_LINE_LEN;
+use regex::Regex;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Most bytes read from a followed file at once; the rest is picked up by the
+/// next poll.
+const MAX_READ: u64 = 4 * 1024 * 1024;
+/// How far back from the end the initial backfill looks for lines.
+const BACKFILL_SCAN_LIMIT: u64 = MAX_READ;
+const CHUNK_LEN: u64 = 64 * 1024;
+/// Bytes before the read position compared on every poll, to notice a file
+/// that was truncated and rewritten past that position in the meantime.
+const CHECK_LEN: usize = 64;
+
+/// A file followed like `tail -F`: appended lines are read as they arrive,
+/// and truncation or replacement of the file (log rotation) is noticed by
+/// comparing its size and inode with what was read so far.
+pub struct Tail {
+    /// The path as given, possibly a symlink; only used to notice rotation.
+    path: PathBuf,
+    /// Every file opened must lie inside it and be readable under its rules.
+    root: FsRoot,
+    file: File,
+    id: (u64, u64),
+    /// Bytes of the current file consumed, including `partial`.
+    pos: u64,
+    /// The unfinished last line, held back until its newline arrives.
+    partial: Vec<u8>,
+    /// The current line grew past [`MAX_LINE_LEN`] and was sent cut off;
+    /// the rest of it is dropped up to its newline.
+    skipping: bool,
+    /// The last bytes read, ending at `pos`.
+    check: Vec<u8>,
+    filter: Option<Regex>,
+}
+
+#[derive(Default)]
+pub struct TailUpdate {
+    pub lines: Vec<String>,
+    /// The file shrank and is read again from the start.
+    pub truncated: bool,
+    /// The path now names a different file, which is read from the start.
+    pub rotated: bool,
+    /// More data is waiting than was read this time.
+    pub more: bool,
+}
+
+impl Tail {
+    /// Start following `path`: from byte `offset` if given, otherwise from
+    /// the end after reading back `backfill` lines. Blocking.
+    pub fn open(
+        root: &FsRoot,
+        path: &Path,
+        offset: Option<u64>,
+        backfill: usize,
+        filter: Option<Regex>,
+    ) -> std::io::Result<(Self, TailUpdate)> {
+        let mut file = open_checked(root, path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "not a regular file",
+            ));
+        }
+        let size = metadata.len();
+        let start = match offset {
+            Some(offset) => offset.min(size),
+            None => backfill_start(&mut file, size, backfill, filter.as_ref())?,
+        };
+        let mut tail = Self {
+            path: path.to_path_buf(),
+            root: root.clone(),
+            file,
+            id: (metadata.dev(), metadata.ino()),
+            pos: start,
+            partial: Vec::new(),
+            skipping: false,
+            check: Vec::new(),
+            filter,
+        };
+        let mut update = TailUpdate::default();
+        tail.read_new(&mut update)?;
+        if offset.is_none() && update.lines.len() > backfill {
+            update.lines.drain(..update.lines.len() - backfill);
+        }
+        Ok((tail, update))
+    }
+
+    /// Byte offset after the last complete line read, to resume from.
+    pub fn offset(&self) -> u64 {
+        self.pos - self.partial.len() as u64
+    }
+
+    /// Read whatever was appended since the last poll. Blocking.
+    pub fn poll(&mut self) -> std::io::Result<TailUpdate> {
+        let mut update = TailUpdate::default();
+        match std::fs::metadata(&self.path) {
+            Ok(current) if (current.dev(), current.ino()) != self.id => {
+                // Finish the old file before switching to its replacement.
+                self.read_new(&mut update)?;
+                self.flush_partial(&mut update);
+                self.file = open_checked(&self.root, &self.path)?;
+                self.id = (current.dev(), current.ino());
+                self.pos = 0;
+                self.skipping = false;
+                self.check.clear();
+                update.rotated = true;
+            }
+            Ok(current) if current.len() < self.pos || !self.unchanged()? => {
+                self.partial.clear();
+                self.skipping = false;
+                self.check.clear();
+                self.pos = 0;
+                update.truncated = true;
+            }
+            // Renamed or deleted without a replacement yet: keep reading
+            // the old file until one appears.
+            _ => {}
+        }
+        self.read_new(&mut update)?;
+        Ok(update)
+    }
+
+    fn read_new(&mut self, update: &mut TailUpdate) -> std::io::Result<()> {
+        let size = self.file.metadata()?.len();
+        if size <= self.pos {
+            return Ok(());
+        }
+        let len = (size - self.pos).min(MAX_READ);
+        update.more = size - self.pos > len;
+        self.file.seek(SeekFrom::Start(self.pos))?;
+        let mut data = std::mem::take(&mut self.partial);
+        let read = (&mut self.file).take(len).read_to_end(&mut data)?;
+        self.pos += read as u64;
+        self.check = data[data.len().saturating_sub(CHECK_LEN)..].to_vec();
+
+        let complete = data.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        self.partial = data.split_off(complete);
+        let skip = !data.is_empty() && std::mem::take(&mut self.skipping);
+        for line in data
+            .split_inclusive(|&b| b == b'\n')
+            .skip(usize::from(skip))
+        {
+            self.push_line(update, line);
+        }
+        if self.skipping {
+            self.partial.clear();
+        } else if self.partial.len() > MAX_LINE_LEN {
+            // Don't hold an endless line in memory waiting for its newline.
+            let partial = std::mem::take(&mut self.partial);
+            self.push_line(update, &partial);
+            self.skipping = true;
+        }
+        Ok(())
+    }
+
+    /// Whether the bytes before `pos` are still the ones read there.
+    fn unchanged(&mut self) -> std::io::Result<bool> {
+        if self.check.is_empty() {
+            return Ok(true);
+        }
+        let mut current = vec![0; self.check.len()];
+        self.file
+            .seek(SeekFrom::Start(self.pos - self.check.len() as u64))?;
+        self.file.read_exact(&mut current)?;
+        Ok(current == self.check)
+    }
+
+    fn flush_partial(&mut self, update: &mut TailUpdate) {
+        let partial = std::mem::take(&mut self.partial);
+        if !partial.is_empty() {
+            self.push_line(update, &partial);
+        }
+    }
+
+    /// Send `line`, cut at [`MAX_LINE_LEN`], if it passes the filter.
+    fn push_line(&self, update: &mut TailUpdate, line: &[u8]) {
+        let line = String::from_utf8_lossy(&line[..line.len().min(MAX_LINE_LEN)]);
+        let line = line.trim_end_matches(['\n', '\r']);
+        if self.filter.as_ref().is_none_or(|f| f.is_match(line)) {
+            update.lines.push(line.to_string());
+        }
+    }
+}
+
+/// Open the file `path` names now, refusing symlinks that lead out of
+/// `root` or to files its rules don't let be read.
+fn open_checked(root: &FsRoot, path: &Path) -> std::io::Result<File> {
+    let target = root.check_target(path, Op::Read).map_err(|err| {
+        std::io::Error::new(std::io::ErrorKind::PermissionDenied, err.to_string())
+    })?;
+    File::open(target)
+}
+
+/// Where to start reading so that the last `lines` (matching) lines before
+/// `size` are included, looking back at most [`BACKFILL_SCAN_LIMIT`] bytes.
+fn backfill_start(
+    file: &mut File,
+    size: u64,
+    lines: usize,
+    filter: Option<&Regex>,
+) -> std::io::Result<u64> {
+    if lines == 0 {
+        return Ok(size);
+    }
+    let floor = size.saturating_sub(BACKFILL_SCAN_LIMIT);
+    if filter.is_some() {
+        // Matching lines can be anywhere; read the whole window and keep
+        // the last ones.
+        return next_line_start(file, floor, size);
+    }
+    let mut end = size;
+    // The newline ending the last line doesn't start a new one.
+    let mut newlines = 0;
+    let mut skip_final = true;
+    while end > floor {
+        let start = end.saturating_sub(CHUNK_LEN).max(floor);
+        let mut chunk = vec![0; (end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        for (i, &b) in chunk.iter().enumerate().rev() {
+            if b != b'\n' {
+                skip_final = false;
+                continue;
+            }
+            if std::mem::take(&mut skip_final) {
+                continue;
+            }
+            newlines += 1;
+            if newlines == lines {
+                return Ok(start + i as u64 + 1);
+            }
+        }
+        end = start;
+    }
+    next_line_start(file, floor, size)
+}
+
+/// The first line start at or after `pos`, so a backfill cut off by the
+/// scan limit doesn't begin mid-line; `size` if no line starts there.
+fn next_line_start(file: &mut File, pos: u64, size: u64) -> std::io::Result<u64> {
+    if pos == 0 {
+        return Ok(0);
+    }
+    // Look from the byte before `pos`, in case a line starts right at it.
+    let mut at = pos - 1;
+    file.seek(SeekFrom::Start(at))?;
+    let mut chunk = vec![0; CHUNK_LEN as usize];
+    while at < size {
+        let n = file.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        if let Some(i) = chunk[..n].iter().position(|&b| b == b'\n') {
+            return Ok(at + i as u64 + 1);
+        }
+        at += n as u64;
+    }
+    Ok(size)
+}
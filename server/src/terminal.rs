@@ -0,0 +1,136 @@
+use crate::config::{TerminalBackend, TerminalProfile};
+use crate::pty::{PtyManager, PtySession};
+use crate::ssh::SshSession;
+use portable_pty::CommandBuilder;
+use russh::Sig;
+use std::io::Read;
+use uuid::Uuid;
+
+/// Signals a client may send to the terminal's foreground job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminalSignal {
+    Int,
+    Term,
+    Hup,
+    Kill,
+    Quit,
+    Usr1,
+    Usr2,
+}
+
+impl TerminalSignal {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().trim_start_matches("SIG").to_ascii_uppercase();
+        Some(match name.as_str() {
+            "INT" => Self::Int,
+            "TERM" => Self::Term,
+            "HUP" => Self::Hup,
+            "KILL" => Self::Kill,
+            "QUIT" => Self::Quit,
+            "USR1" => Self::Usr1,
+            "USR2" => Self::Usr2,
+            _ => return None,
+        })
+    }
+
+    #[cfg(unix)]
+    fn as_libc(self) -> libc::c_int {
+        match self {
+            Self::Int => libc::SIGINT,
+            Self::Term => libc::SIGTERM,
+            Self::Hup => libc::SIGHUP,
+            Self::Kill => libc::SIGKILL,
+            Self::Quit => libc::SIGQUIT,
+            Self::Usr1 => libc::SIGUSR1,
+            Self::Usr2 => libc::SIGUSR2,
+        }
+    }
+
+    fn as_ssh(self) -> Sig {
+        match self {
+            Self::Int => Sig::INT,
+            Self::Term => Sig::TERM,
+            Self::Hup => Sig::HUP,
+            Self::Kill => Sig::KILL,
+            Self::Quit => Sig::QUIT,
+            Self::Usr1 => Sig::USR1,
+            Self::Usr2 => Sig::Custom("USR2".into()),
+        }
+    }
+}
+
+/// A terminal stream served over `/ws/term`, backed by a local PTY or a remote SSH shell.
+pub enum TerminalSession {
+    Local(PtySession),
+    Ssh(SshSession),
+}
+
+impl TerminalSession {
+    /// Open a session for `profile`, or the default local shell when none is given.
+    pub async fn open(
+        pty: &PtyManager,
+        profile: Option<&TerminalProfile>,
+        rows: u16,
+        cols: u16,
+    ) -> anyhow::Result<(Self, Box<dyn Read + Send>)> {
+        match profile.map(|p| &p.backend) {
+            None | Some(TerminalBackend::Local { shell: None }) => {
+                let (session, reader) = pty.create_session(rows, cols)?;
+                Ok((Self::Local(session), reader))
+            }
+            Some(TerminalBackend::Local { shell: Some(shell) }) => {
+                let (session, reader) = pty.spawn(rows, cols, CommandBuilder::new(shell))?;
+                Ok((Self::Local(session), reader))
+            }
+            Some(TerminalBackend::Ssh(ssh)) => {
+                let (session, reader) = SshSession::connect(ssh, rows, cols).await?;
+                Ok((Self::Ssh(session), reader))
+            }
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        match self {
+            Self::Local(session) => session.id(),
+            Self::Ssh(session) => session.id(),
+        }
+    }
+
+    pub async fn write(&self, data: &[u8]) -> anyhow::Result<()> {
+        match self {
+            Self::Local(session) => session.write(data).await,
+            Self::Ssh(session) => session.write(data).await,
+        }
+    }
+
+    pub async fn resize(&self, rows: u16, cols: u16) -> anyhow::Result<()> {
+        match self {
+            Self::Local(session) => session.resize(rows, cols).await,
+            Self::Ssh(session) => session.resize(rows, cols).await,
+        }
+    }
+
+    pub async fn signal(&self, signal: TerminalSignal) -> anyhow::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Self::Local(session) => session.signal(signal.as_libc()).await,
+            #[cfg(not(unix))]
+            Self::Local(_) => anyhow::bail!("signals are not supported on this platform"),
+            Self::Ssh(session) => session.signal(signal.as_ssh()).await,
+        }
+    }
+
+    pub async fn exit_code(&self) -> Option<u32> {
+        match self {
+            Self::Local(session) => session.exit_code().await,
+            Self::Ssh(session) => session.exit_status(),
+        }
+    }
+
+    pub async fn shutdown(&self) {
+        match self {
+            Self::Local(session) => session.shutdown().await,
+            Self::Ssh(session) => session.shutdown().await,
+        }
+    }
+}
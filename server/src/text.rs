@@ -0,0 +1,458 @@
+use crate::error::AppError;
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Bytes from the start of a file used to detect its encoding, line endings
+/// and whether it is text at all.
+const SAMPLE_LEN: usize = 64 * 1024;
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// The part of a file to read.
+#[derive(Debug, Clone, Copy)]
+pub enum TextRange {
+    Whole,
+    /// The first `n` lines.
+    Head(u64),
+    /// The last `n` lines.
+    Tail(u64),
+    /// Lines `start..=end`, 1-based; to the end of the file without `end`.
+    Lines {
+        start: u64,
+        end: Option<u64>,
+    },
+    /// Raw bytes, decoded as far as they go.
+    Bytes {
+        offset: u64,
+        length: Option<u64>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    Cr,
+    Mixed,
+    /// No line break in the sample.
+    None,
+}
+
+pub struct Text {
+    pub content: String,
+    /// WHATWG name of the encoding the file was decoded from.
+    pub encoding: &'static str,
+    pub bom: bool,
+    /// The file looks binary; `content` is empty.
+    pub binary: bool,
+    /// Some bytes were not valid in `encoding` and were replaced.
+    pub lossy: bool,
+    pub line_ending: LineEnding,
+    /// Left out where it would take reading the rest of the file.
+    pub total_lines: Option<u64>,
+    /// 1-based first and last line in `content`, for line ranges whose
+    /// position is known.
+    pub lines: Option<(u64, u64)>,
+    /// Whether `content` covers the whole file.
+    pub complete: bool,
+}
+
+/// Read `range` of the text file at `path`, decoded to UTF-8.
+///
+/// The encoding comes from a byte-order mark, then `encoding` if given, then
+/// detection. Files are scanned in chunks, so only the selected part (at
+/// most `max_size` bytes of it) is held in memory, and only as far as
+/// needed: a tail is found from the end, a head stops after its last line.
+/// Blocking.
+pub fn read(
+    path: &Path,
+    range: TextRange,
+    encoding: Option<&'static Encoding>,
+    max_size: u64,
+) -> Result<Text, AppError> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut sample = Vec::with_capacity(SAMPLE_LEN.min(size as usize));
+    (&mut file)
+        .take(SAMPLE_LEN as u64)
+        .read_to_end(&mut sample)?;
+
+    let bom = Encoding::for_bom(&sample);
+    let detected = bom
+        .map(|(encoding, _)| encoding)
+        .or(encoding)
+        .or_else(|| detect(&sample, sample.len() as u64 == size));
+    let Some(encoding) = detected else {
+        return Ok(Text {
+            content: String::new(),
+            encoding: "binary",
+            bom: false,
+            binary: true,
+            lossy: false,
+            line_ending: LineEnding::None,
+            total_lines: None,
+            lines: None,
+            complete: false,
+        });
+    };
+    let bom_len = bom.map(|(_, len)| len as u64).unwrap_or(0);
+    let line_ending = line_ending(&encoding.decode_without_bom_handling(&sample).0);
+
+    let too_large = |len: u64| {
+        AppError::TooLarge(format!(
+            "{len} bytes requested, the limit is {max_size}; ask for a line or byte range"
+        ))
+    };
+    let mut text = Text {
+        content: String::new(),
+        encoding: encoding.name(),
+        bom: bom.is_some(),
+        binary: false,
+        lossy: false,
+        line_ending,
+        total_lines: None,
+        lines: None,
+        complete: false,
+    };
+
+    // Byte ranges need no line structure. Everything else in UTF-16 is
+    // decoded whole, since a line break is not a single byte there.
+    let wide = encoding == UTF_16LE || encoding == UTF_16BE;
+    let selected = match range {
+        TextRange::Bytes { offset, length } => {
+            let offset = if wide { offset & !1 } else { offset }.max(bom_len);
+            let length = length.unwrap_or(u64::MAX).min(size.saturating_sub(offset));
+            if length > max_size {
+                return Err(too_large(length));
+            }
+            file.seek(SeekFrom::Start(offset))?;
+            let mut bytes = Vec::with_capacity(length as usize);
+            file.take(length).read_to_end(&mut bytes)?;
+            text.complete = offset <= bom_len && offset + length >= size;
+            bytes
+        }
+        _ if wide || matches!(range, TextRange::Whole) => {
+            if size - bom_len > max_size {
+                return Err(too_large(size - bom_len));
+            }
+            file.seek(SeekFrom::Start(bom_len))?;
+            let mut bytes = Vec::with_capacity((size - bom_len) as usize);
+            (&mut file).take(size - bom_len).read_to_end(&mut bytes)?;
+            let (decoded, lossy) = encoding.decode_without_bom_handling(&bytes);
+            let newlines = decoded.matches('\n').count() as u64;
+            let total = count_lines(newlines, decoded.is_empty() || decoded.ends_with('\n'));
+            text.lossy = lossy;
+            text.total_lines = Some(total);
+            if matches!(range, TextRange::Whole) {
+                text.content = decoded.into_owned();
+                text.complete = true;
+            } else {
+                let (first, last) = line_span(range, total);
+                text.content = slice_lines(&decoded, first, last);
+                text.lines = Some((first, last.min(total)));
+                text.complete = first <= 1 && last >= total;
+            }
+            return Ok(text);
+        }
+        TextRange::Tail(n) => {
+            let offset = tail_start(&mut file, bom_len, size, n, max_size)?
+                .ok_or_else(|| too_many_lines(max_size))?;
+            file.seek(SeekFrom::Start(offset))?;
+            let mut bytes = Vec::with_capacity((size - offset) as usize);
+            (&mut file).take(size - offset).read_to_end(&mut bytes)?;
+            // Line numbers are only known when the tail is the whole file.
+            if offset <= bom_len {
+                let newlines = bytes.iter().filter(|&&b| b == b'\n').count() as u64;
+                let total = count_lines(newlines, bytes.last().is_none_or(|&b| b == b'\n'));
+                text.total_lines = Some(total);
+                text.lines = Some((1, total));
+                text.complete = true;
+            }
+            bytes
+        }
+        _ => {
+            let (first, last) = line_span(range, u64::MAX);
+            let scanned = scan_lines(&mut file, bom_len, first, last, max_size)?
+                .ok_or_else(|| too_many_lines(max_size))?;
+            match scanned.total {
+                Some(total) => {
+                    let last = last.min(total);
+                    text.total_lines = Some(total);
+                    text.lines = Some((first, last));
+                    text.complete = first <= 1 && last >= total;
+                }
+                // Stopped after `last`, with more of the file left.
+                None => text.lines = Some((first, last)),
+            }
+            scanned.bytes
+        }
+    };
+
+    let (content, lossy) = encoding.decode_without_bom_handling(&selected);
+    text.content = content.into_owned();
+    text.lossy = lossy;
+    Ok(text)
+}
+
+fn too_many_lines(max_size: u64) -> AppError {
+    AppError::TooLarge(format!(
+        "the selected lines exceed the limit of {max_size} bytes"
+    ))
+}
+
+/// The encoding of the text file at `path` and whether it starts with a
+/// byte-order mark, detected as [`read`] does; `None` if it looks binary.
+/// Blocking.
+pub fn detect_file(path: &Path) -> std::io::Result<Option<(&'static Encoding, bool)>> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut sample = Vec::with_capacity(SAMPLE_LEN.min(size as usize));
+    file.take(SAMPLE_LEN as u64).read_to_end(&mut sample)?;
+    if let Some((encoding, _)) = Encoding::for_bom(&sample) {
+        return Ok(Some((encoding, true)));
+    }
+    Ok(detect(&sample, sample.len() as u64 == size).map(|encoding| (encoding, false)))
+}
+
+/// Encode `text` in `encoding`, after a byte-order mark if `bom` is set and
+/// the encoding has one. Characters the encoding can't represent are
+/// refused rather than replaced.
+pub fn encode(text: &str, encoding: &'static Encoding, bom: bool) -> Result<Vec<u8>, AppError> {
+    let mut out = Vec::with_capacity(text.len() + 3);
+    // encoding_rs only decodes UTF-16; its encoder writes UTF-8 instead.
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let little = encoding == UTF_16LE;
+        if bom {
+            out.extend_from_slice(if little { b"\xFF\xFE" } else { b"\xFE\xFF" });
+        }
+        for unit in text.encode_utf16() {
+            let bytes = if little {
+                unit.to_le_bytes()
+            } else {
+                unit.to_be_bytes()
+            };
+            out.extend_from_slice(&bytes);
+        }
+        return Ok(out);
+    }
+    if bom && encoding == UTF_8 {
+        out.extend_from_slice(b"\xEF\xBB\xBF");
+    }
+    let (bytes, _, unmappable) = encoding.encode(text);
+    if unmappable {
+        return Err(AppError::BadRequest(format!(
+            "the content has characters {} can't represent",
+            encoding.name()
+        )));
+    }
+    out.extend_from_slice(&bytes);
+    Ok(out)
+}
+
+/// Look up an encoding by its WHATWG label (`latin1`, `shift_jis`, ...).
+pub fn encoding_for_label(label: &str) -> Result<&'static Encoding, AppError> {
+    Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| AppError::BadRequest(format!("unknown encoding {label:?}")))
+}
+
+/// The encoding of a file starting with `sample`, or `None` if it looks
+/// binary.
+fn detect(sample: &[u8], complete: bool) -> Option<&'static Encoding> {
+    if let Some(encoding) = detect_utf16(sample) {
+        return Some(encoding);
+    }
+    if sample.contains(&0) {
+        return None;
+    }
+    match std::str::from_utf8(sample) {
+        Ok(_) => return Some(UTF_8),
+        Err(err) if err.error_len().is_none() && !complete => return Some(UTF_8),
+        Err(_) => {}
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(sample, complete);
+    Some(detector.guess(None, true))
+}
+
+/// UTF-16 without a BOM: mostly-ASCII text has a NUL in every other byte.
+fn detect_utf16(sample: &[u8]) -> Option<&'static Encoding> {
+    let pairs = sample.len() / 2;
+    if pairs < 4 {
+        return None;
+    }
+    let zeros_at = |parity: usize| {
+        sample
+            .iter()
+            .skip(parity)
+            .step_by(2)
+            .filter(|&&b| b == 0)
+            .count()
+    };
+    let (even, odd) = (zeros_at(0), zeros_at(1));
+    if odd * 10 >= pairs * 4 && even * 20 < pairs {
+        Some(UTF_16LE)
+    } else if even * 10 >= pairs * 4 && odd * 20 < pairs {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+fn line_ending(text: &str) -> LineEnding {
+    let (mut lf, mut crlf, mut cr) = (0, 0, 0);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' if chars.peek() == Some(&'\n') => {
+                chars.next();
+                crlf += 1;
+            }
+            '\r' => cr += 1,
+            '\n' => lf += 1,
+            _ => {}
+        }
+    }
+    match (lf > 0, crlf > 0, cr > 0) {
+        (false, false, false) => LineEnding::None,
+        (true, false, false) => LineEnding::Lf,
+        (false, true, false) => LineEnding::Crlf,
+        (false, false, true) => LineEnding::Cr,
+        _ => LineEnding::Mixed,
+    }
+}
+
+/// Lines in a file with `newlines` line breaks: a last line without a
+/// break counts too.
+fn count_lines(newlines: u64, ends_with_newline: bool) -> u64 {
+    if ends_with_newline {
+        newlines
+    } else {
+        newlines + 1
+    }
+}
+
+/// First and last line (1-based, inclusive) selected by `range` in a file
+/// of `total` lines.
+fn line_span(range: TextRange, total: u64) -> (u64, u64) {
+    match range {
+        TextRange::Whole | TextRange::Bytes { .. } => (1, total),
+        TextRange::Head(n) => (1, n),
+        TextRange::Tail(n) => (total.saturating_sub(n) + 1, total),
+        TextRange::Lines { start, end } => (start.max(1), end.unwrap_or(total)),
+    }
+}
+
+fn slice_lines(text: &str, first: u64, last: u64) -> String {
+    text.split_inclusive('\n')
+        .skip(first.saturating_sub(1) as usize)
+        .take(last.saturating_sub(first.saturating_sub(1)) as usize)
+        .collect()
+}
+
+/// Where the last `n` lines after `start` begin, walking back from the end
+/// of the file. `None` if they take more than `max_size` bytes. Blocking.
+fn tail_start(
+    file: &mut File,
+    start: u64,
+    size: u64,
+    n: u64,
+    max_size: u64,
+) -> std::io::Result<Option<u64>> {
+    if n == 0 {
+        return Ok(Some(size));
+    }
+    let mut buf = vec![0; CHUNK_LEN];
+    let mut end = size;
+    // The newline ending the last line doesn't start a new one.
+    let mut newlines = 0;
+    let mut skip_final = true;
+    while end > start {
+        let from = end.saturating_sub(CHUNK_LEN as u64).max(start);
+        let chunk = &mut buf[..(end - from) as usize];
+        file.seek(SeekFrom::Start(from))?;
+        file.read_exact(chunk)?;
+        for (i, &b) in chunk.iter().enumerate().rev() {
+            if b != b'\n' {
+                skip_final = false;
+                continue;
+            }
+            if std::mem::take(&mut skip_final) {
+                continue;
+            }
+            newlines += 1;
+            if newlines == n {
+                let offset = from + i as u64 + 1;
+                return Ok((size - offset <= max_size).then_some(offset));
+            }
+        }
+        end = from;
+        if size - end > max_size {
+            return Ok(None);
+        }
+    }
+    Ok(Some(start))
+}
+
+/// Lines collected by [`scan_lines`].
+struct Scanned {
+    bytes: Vec<u8>,
+    /// Lines after `start`, if the scan got to the end of the file.
+    total: Option<u64>,
+}
+
+/// Collect lines `first..=last` after `start`, reading no further than the
+/// first byte past them. `None` if the selection outgrew `max_size`.
+/// Blocking.
+fn scan_lines(
+    file: &mut File,
+    start: u64,
+    first: u64,
+    last: u64,
+    max_size: u64,
+) -> std::io::Result<Option<Scanned>> {
+    file.seek(SeekFrom::Start(start))?;
+    let mut buf = vec![0; CHUNK_LEN];
+    let mut selected = Vec::new();
+    // 1-based number of the line the next byte belongs to.
+    let mut line = 1u64;
+    let mut at_line_start = true;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let mut chunk = &buf[..n];
+        while !chunk.is_empty() {
+            if line > last {
+                return Ok(Some(Scanned {
+                    bytes: selected,
+                    total: None,
+                }));
+            }
+            let end = chunk
+                .iter()
+                .position(|&b| b == b'\n')
+                .map(|i| i + 1)
+                .unwrap_or(chunk.len());
+            if line >= first {
+                if (selected.len() + end) as u64 > max_size {
+                    return Ok(None);
+                }
+                selected.extend_from_slice(&chunk[..end]);
+            }
+            at_line_start = chunk[end - 1] == b'\n';
+            if at_line_start {
+                line += 1;
+            }
+            chunk = &chunk[end..];
+        }
+    }
+    Ok(Some(Scanned {
+        bytes: selected,
+        total: Some(if at_line_start { line - 1 } else { line }),
+    }))
+}
@@ -0,0 +1,272 @@
+use crate::config::ThumbnailConfig;
+use crate::error::AppError;
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fs::{File, FileTimes};
+use std::io::BufWriter;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Edge lengths thumbnails are rendered at; requested sizes are rounded up
+/// to one of these so that the cache is shared between similar requests.
+pub const SIZES: &[u32] = &[64, 128, 256, 512, 1024];
+pub const DEFAULT_SIZE: u32 = 256;
+/// Decoded pixels allowed in memory for one source image.
+const MAX_DECODED_BYTES: u64 = 1024 * 1024 * 1024;
+/// Eviction trims the cache to this share of its limit, so that it doesn't
+/// run again after every new thumbnail.
+const EVICT_TO_PERCENT: u64 = 90;
+/// Files used this recently are kept, since they may be about to be sent.
+const EVICT_GRACE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailFormat {
+    #[default]
+    Webp,
+    Png,
+}
+
+impl ThumbnailFormat {
+    pub fn mime(self) -> &'static str {
+        match self {
+            Self::Webp => "image/webp",
+            Self::Png => "image/png",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Webp => "webp",
+            Self::Png => "png",
+        }
+    }
+
+    fn image_format(self) -> image::ImageFormat {
+        match self {
+            Self::Webp => image::ImageFormat::WebP,
+            Self::Png => image::ImageFormat::Png,
+        }
+    }
+}
+
+pub enum Outcome {
+    /// The rendered thumbnail in the cache.
+    Ready(PathBuf),
+    /// The source can't be thumbnailed: not an image, a format without a
+    /// decoder, corrupt or too large.
+    Unsupported(String),
+}
+
+/// Thumbnails of images, rendered on first request and kept in
+/// `<cache_dir>/thumbnails` under a key of the source path, size and mtime,
+/// so an edited image gets a new one. Failures are remembered the same way,
+/// so broken files are not decoded over and over. The cache is trimmed by
+/// last use once it outgrows its limit, and rendering is limited to a few
+/// images at once.
+#[derive(Clone)]
+pub struct Thumbnails {
+    dir: PathBuf,
+    max_cache_size: u64,
+    max_source_size: u64,
+    permits: Arc<Semaphore>,
+}
+
+impl Thumbnails {
+    pub fn new(cache_dir: &Path, config: &ThumbnailConfig) -> Self {
+        Self {
+            dir: cache_dir.join("thumbnails"),
+            max_cache_size: config.max_cache_size,
+            max_source_size: config.max_source_size,
+            permits: Arc::new(Semaphore::new(config.max_concurrent.max(1))),
+        }
+    }
+
+    /// A slot for decoding an image, shared with conversions in
+    /// `/api/fs/convert`; waits while all are taken.
+    pub async fn permit(&self) -> Result<SemaphorePermit<'_>, AppError> {
+        self.permits
+            .acquire()
+            .await
+            .map_err(|e| AppError::Internal(format!("image decoding queue closed: {e}")))
+    }
+
+    /// The smallest rendered size at least `size`, or the largest one.
+    pub fn bucket(size: u32) -> u32 {
+        SIZES
+            .iter()
+            .copied()
+            .find(|&s| s >= size)
+            .unwrap_or(SIZES[SIZES.len() - 1])
+    }
+
+    /// The thumbnail of the image at `path` fitting in `size`×`size`,
+    /// rendering it unless it is cached.
+    pub async fn get(
+        &self,
+        path: &Path,
+        size: u32,
+        format: ThumbnailFormat,
+    ) -> Result<Outcome, AppError> {
+        let metadata = tokio::fs::metadata(path).await?;
+        if !metadata.is_file() {
+            return Err(AppError::BadRequest("not a regular file".into()));
+        }
+        let key = cache_key(path, &metadata, size, format);
+        let file = self.dir.join(format!("{key}.{}", format.extension()));
+        let failure = self.dir.join(format!("{key}.fail"));
+
+        if let Some(outcome) = self.cached(&file, &failure).await {
+            return Ok(outcome);
+        }
+        if metadata.len() > self.max_source_size {
+            return Ok(Outcome::Unsupported(format!(
+                "{} bytes is over the limit of {} for thumbnails",
+                metadata.len(),
+                self.max_source_size
+            )));
+        }
+
+        let _permit = self.permit().await?;
+        // Another request may have rendered it while this one waited.
+        if let Some(outcome) = self.cached(&file, &failure).await {
+            return Ok(outcome);
+        }
+
+        let thumbnails = self.clone();
+        let source = path.to_path_buf();
+        let target = file.clone();
+        tokio::task::spawn_blocking(move || {
+            match render(&source, size) {
+                Ok(image) => thumbnails.store(&image, &target, format)?,
+                // Unreadable now, maybe not later: don't remember it.
+                Err(image::ImageError::IoError(err))
+                    if err.kind() != std::io::ErrorKind::UnexpectedEof =>
+                {
+                    return Err(err.into());
+                }
+                Err(err) => {
+                    std::fs::create_dir_all(&thumbnails.dir)?;
+                    std::fs::write(&failure, err.to_string())?;
+                    return Ok(Outcome::Unsupported(err.to_string()));
+                }
+            }
+            thumbnails.evict();
+            Ok(Outcome::Ready(target))
+        })
+        .await
+        .map_err(|e| AppError::Internal(format!("thumbnail task failed: {e}")))?
+    }
+
+    async fn cached(&self, file: &Path, failure: &Path) -> Option<Outcome> {
+        if let Ok(cached) = tokio::fs::File::open(file).await {
+            // Access times are often not kept up by the filesystem; set
+            // them for eviction. The mtime stays, and with it the ETag.
+            let cached = cached.into_std().await;
+            let _ = cached.set_times(FileTimes::new().set_accessed(SystemTime::now()));
+            return Some(Outcome::Ready(file.to_path_buf()));
+        }
+        let reason = tokio::fs::read_to_string(failure).await.ok()?;
+        Some(Outcome::Unsupported(reason))
+    }
+
+    /// Encode `image` to `target` through a temporary file. Blocking.
+    fn store(
+        &self,
+        image: &DynamicImage,
+        target: &Path,
+        format: ThumbnailFormat,
+    ) -> Result<(), AppError> {
+        std::fs::create_dir_all(&self.dir)?;
+        // Both encoders take 8-bit pixels; PNG would keep 16 bits, which
+        // only makes the thumbnail larger.
+        let image = if image.color().has_alpha() {
+            DynamicImage::ImageRgba8(image.to_rgba8())
+        } else {
+            DynamicImage::ImageRgb8(image.to_rgb8())
+        };
+        // Unique, in case the same thumbnail is being rendered twice.
+        let tmp = target.with_extension(format!("{}.tmp", uuid::Uuid::new_v4().simple()));
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        image
+            .write_to(&mut writer, format.image_format())
+            .map_err(|e| AppError::Internal(format!("failed to encode thumbnail: {e}")))?;
+        writer.into_inner().map_err(|e| e.into_error())?;
+        std::fs::rename(tmp, target)?;
+        Ok(())
+    }
+
+    /// Remove the least recently used files until the cache is back under
+    /// its limit. Blocking.
+    fn evict(&self) {
+        let Ok(dir) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut files: Vec<_> = dir
+            .flatten()
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                let used = metadata.accessed().or_else(|_| metadata.modified()).ok()?;
+                Some((used, metadata.len(), entry.path()))
+            })
+            .collect();
+        let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+        if total <= self.max_cache_size {
+            return;
+        }
+        let target = self.max_cache_size / 100 * EVICT_TO_PERCENT;
+        let recent = SystemTime::now() - EVICT_GRACE;
+        files.sort_by_key(|(used, _, _)| *used);
+        for (used, len, path) in files {
+            if total <= target || used > recent {
+                break;
+            }
+            if std::fs::remove_file(&path).is_ok() {
+                total -= len;
+            }
+        }
+    }
+}
+
+fn cache_key(
+    path: &Path,
+    metadata: &std::fs::Metadata,
+    size: u32,
+    format: ThumbnailFormat,
+) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(path.as_os_str().as_encoded_bytes());
+    hasher.update(metadata.len().to_le_bytes());
+    hasher.update((metadata.mtime() * 1_000_000_000 + metadata.mtime_nsec()).to_le_bytes());
+    hasher.update(size.to_le_bytes());
+    hasher.update(format.extension());
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Decode the image at `path`, upright, and scale it down to fit in
+/// `size`×`size`. Blocking.
+fn render(path: &Path, size: u32) -> image::ImageResult<DynamicImage> {
+    let mut decoder = ImageReader::open(path)?
+        .with_guessed_format()?
+        .into_decoder()?;
+    if decoder.total_bytes() > MAX_DECODED_BYTES {
+        return Err(image::ImageError::Limits(
+            image::error::LimitError::from_kind(image::error::LimitErrorKind::InsufficientMemory),
+        ));
+    }
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    if image.width() > size || image.height() > size {
+        image = image.thumbnail(size, size);
+    }
+    Ok(image)
+}
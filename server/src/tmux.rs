@@ -0,0 +1,461 @@
+use crate::pty::PtySession;
+use portable_pty::CommandBuilder;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex as StdMutex;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+/// DCS sequence tmux prints before the first line in `-CC` mode.
+const CONTROL_MODE_PREFIX: &[u8] = b"\x1bP1000p";
+/// Keep `send-keys` command lines short enough for tmux's input buffer.
+const SEND_KEYS_CHUNK: usize = 256;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TmuxSessionInfo {
+    pub id: String,
+    pub name: String,
+    pub windows: u32,
+    pub attached: u32,
+    pub created: u64,
+}
+
+/// List sessions on the default tmux server. A missing server means no sessions.
+pub async fn list_sessions() -> anyhow::Result<Vec<TmuxSessionInfo>> {
+    let output = Command::new("tmux")
+        .args([
+            "list-sessions",
+            "-F",
+            "#{session_id} #{session_windows} #{session_attached} #{session_created} #{session_name}",
+        ])
+        .env_remove("TMUX")
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Ok(Vec::new());
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let sessions = stdout
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(5, ' ');
+            Some(TmuxSessionInfo {
+                id: parts.next()?.to_string(),
+                windows: parts.next()?.parse().ok()?,
+                attached: parts.next()?.parse().ok()?,
+                created: parts.next()?.parse().ok()?,
+                name: parts.next()?.to_string(),
+            })
+        })
+        .collect();
+    Ok(sessions)
+}
+
+/// Command that attaches a control-mode client to `session`.
+pub fn attach_command(session: &str) -> CommandBuilder {
+    let mut cmd = CommandBuilder::new("tmux");
+    cmd.args(["-CC", "attach-session", "-t", session]);
+    // Attaching from inside another tmux would otherwise be refused.
+    cmd.env_remove("TMUX");
+    cmd
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayoutSplit {
+    /// Children arranged left to right (`{...}` in tmux layout strings).
+    Horizontal,
+    /// Children arranged top to bottom (`[...]`).
+    Vertical,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TmuxLayout {
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pane: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub split: Option<LayoutSplit>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TmuxLayout>,
+}
+
+impl TmuxLayout {
+    /// Parse a tmux layout string such as `b25f,80x24,0,0{40x24,0,0,1,39x24,41,0,2}`.
+    pub fn parse(layout: &str) -> Option<Self> {
+        // Skip the leading checksum.
+        let (_, body) = layout.split_once(',')?;
+        let mut parser = LayoutParser {
+            input: body.as_bytes(),
+            pos: 0,
+        };
+        let node = parser.node()?;
+        (parser.pos == parser.input.len()).then_some(node)
+    }
+
+    pub fn panes(&self) -> Vec<u32> {
+        let mut panes = Vec::new();
+        self.collect_panes(&mut panes);
+        panes
+    }
+
+    fn collect_panes(&self, out: &mut Vec<u32>) {
+        out.extend(self.pane);
+        for child in &self.children {
+            child.collect_panes(out);
+        }
+    }
+}
+
+struct LayoutParser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl LayoutParser<'_> {
+    fn node(&mut self) -> Option<TmuxLayout> {
+        let width = self.number()?;
+        self.expect(b'x')?;
+        let height = self.number()?;
+        self.expect(b',')?;
+        let x = self.number()?;
+        self.expect(b',')?;
+        let y = self.number()?;
+
+        let mut node = TmuxLayout {
+            width,
+            height,
+            x,
+            y,
+            pane: None,
+            split: None,
+            children: Vec::new(),
+        };
+
+        match self.input.get(self.pos) {
+            Some(b',') => {
+                self.pos += 1;
+                node.pane = Some(self.number()?);
+            }
+            Some(&open @ (b'{' | b'[')) => {
+                self.pos += 1;
+                let (split, close) = if open == b'{' {
+                    (LayoutSplit::Horizontal, b'}')
+                } else {
+                    (LayoutSplit::Vertical, b']')
+                };
+                node.split = Some(split);
+                loop {
+                    node.children.push(self.node()?);
+                    match self.input.get(self.pos)? {
+                        b',' => self.pos += 1,
+                        c if *c == close => {
+                            self.pos += 1;
+                            break;
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+            _ => {}
+        }
+        Some(node)
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.input.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.input[start..self.pos])
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        (self.input.get(self.pos) == Some(&byte)).then(|| self.pos += 1)
+    }
+}
+
+/// Notifications and command replies from a control-mode client.
+#[derive(Debug)]
+pub enum ControlEvent {
+    Output {
+        pane: u32,
+        data: Vec<u8>,
+    },
+    /// Reply to a command; `own` is set when this client issued the command.
+    Reply {
+        own: bool,
+        error: bool,
+        lines: Vec<String>,
+    },
+    LayoutChange {
+        window: u32,
+        layout: String,
+    },
+    WindowAdd {
+        window: u32,
+    },
+    WindowClose {
+        window: u32,
+    },
+    WindowRenamed {
+        window: u32,
+        name: String,
+    },
+    SessionChanged {
+        name: String,
+    },
+    Exit {
+        reason: Option<String>,
+    },
+}
+
+struct PendingReply {
+    own: bool,
+    lines: Vec<String>,
+}
+
+/// Line-oriented parser for the tmux control-mode protocol.
+#[derive(Default)]
+pub struct ControlParser {
+    buffer: Vec<u8>,
+    reply: Option<PendingReply>,
+}
+
+impl ControlParser {
+    pub fn feed(&mut self, data: &[u8]) -> Vec<ControlEvent> {
+        self.buffer.extend_from_slice(data);
+        let mut events = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|b| *b == b'\n') {
+            let mut line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if let Some(rest) = line.strip_prefix(CONTROL_MODE_PREFIX) {
+                line = rest.to_vec();
+            }
+            if let Some(event) = self.parse_line(&line) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    fn parse_line(&mut self, line: &[u8]) -> Option<ControlEvent> {
+        if let Some(reply) = self.reply.as_mut() {
+            if line.starts_with(b"%end ") || line.starts_with(b"%error ") {
+                let reply = self.reply.take()?;
+                return Some(ControlEvent::Reply {
+                    own: reply.own,
+                    error: line.starts_with(b"%error "),
+                    lines: reply.lines,
+                });
+            }
+            reply.lines.push(String::from_utf8_lossy(line).into_owned());
+            return None;
+        }
+
+        if let Some(rest) = line.strip_prefix(b"%output ") {
+            let (pane, data) = split_word(rest);
+            return Some(ControlEvent::Output {
+                pane: parse_id(pane, b'%')?,
+                data: unescape_output(data),
+            });
+        }
+        if let Some(rest) = line.strip_prefix(b"%extended-output ") {
+            let (pane, rest) = split_word(rest);
+            let start = rest.windows(3).position(|w| w == b" : ")?;
+            return Some(ControlEvent::Output {
+                pane: parse_id(pane, b'%')?,
+                data: unescape_output(&rest[start + 3..]),
+            });
+        }
+
+        let text = String::from_utf8_lossy(line);
+        let mut words = text.split(' ');
+        match words.next()? {
+            "%begin" => {
+                let flags: u32 = words.nth(2).and_then(|f| f.parse().ok()).unwrap_or(0);
+                self.reply = Some(PendingReply {
+                    own: flags & 1 == 1,
+                    lines: Vec::new(),
+                });
+                None
+            }
+            "%layout-change" => Some(ControlEvent::LayoutChange {
+                window: parse_id(words.next()?.as_bytes(), b'@')?,
+                layout: words.next()?.to_string(),
+            }),
+            "%window-add" => Some(ControlEvent::WindowAdd {
+                window: parse_id(words.next()?.as_bytes(), b'@')?,
+            }),
+            "%window-close" | "%unlinked-window-close" => Some(ControlEvent::WindowClose {
+                window: parse_id(words.next()?.as_bytes(), b'@')?,
+            }),
+            "%window-renamed" => Some(ControlEvent::WindowRenamed {
+                window: parse_id(words.next()?.as_bytes(), b'@')?,
+                name: words.collect::<Vec<_>>().join(" "),
+            }),
+            "%session-changed" => Some(ControlEvent::SessionChanged {
+                name: words.skip(1).collect::<Vec<_>>().join(" "),
+            }),
+            "%exit" => {
+                let reason = words.collect::<Vec<_>>().join(" ");
+                Some(ControlEvent::Exit {
+                    reason: (!reason.is_empty()).then_some(reason),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+fn split_word(line: &[u8]) -> (&[u8], &[u8]) {
+    match line.iter().position(|b| *b == b' ') {
+        Some(pos) => (&line[..pos], &line[pos + 1..]),
+        None => (line, &[]),
+    }
+}
+
+fn parse_id(word: &[u8], sigil: u8) -> Option<u32> {
+    std::str::from_utf8(word.strip_prefix(&[sigil])?)
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Undo the octal escaping tmux applies to `%output` payloads.
+fn unescape_output(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] == b'\\' {
+            if let Some(digits) = data.get(i + 1..i + 4) {
+                if digits.iter().all(|d| (b'0'..=b'7').contains(d)) {
+                    let value = digits
+                        .iter()
+                        .fold(0u32, |acc, d| acc * 8 + u32::from(d - b'0'));
+                    out.push(value as u8);
+                    i += 4;
+                    continue;
+                }
+            }
+        }
+        out.push(data[i]);
+        i += 1;
+    }
+    out
+}
+
+/// What a command reply should be used for, in the order commands were sent.
+#[derive(Debug, Clone, Copy)]
+pub enum PendingCommand {
+    ListWindows,
+    CapturePane(u32),
+    Ignore,
+}
+
+/// A control-mode client running on a PTY.
+pub struct TmuxClient {
+    session: PtySession,
+    pending: StdMutex<VecDeque<PendingCommand>>,
+    order: Mutex<()>,
+}
+
+impl TmuxClient {
+    pub fn new(session: PtySession) -> Self {
+        Self {
+            session,
+            pending: StdMutex::new(VecDeque::new()),
+            order: Mutex::new(()),
+        }
+    }
+
+    pub fn session(&self) -> &PtySession {
+        &self.session
+    }
+
+    /// Send a command; its reply is matched back through `take_pending`.
+    pub async fn command(&self, command: &str, kind: PendingCommand) -> anyhow::Result<()> {
+        let _order = self.order.lock().await;
+        self.pending
+            .lock()
+            .expect("tmux pending mutex poisoned")
+            .push_back(kind);
+        self.session.write(format!("{command}\n").as_bytes()).await
+    }
+
+    pub fn take_pending(&self) -> Option<PendingCommand> {
+        self.pending
+            .lock()
+            .expect("tmux pending mutex poisoned")
+            .pop_front()
+    }
+
+    pub async fn list_windows(&self) -> anyhow::Result<()> {
+        self.command(
+            "list-windows -F \"#{window_id} #{window_active} #{window_layout} #{window_name}\"",
+            PendingCommand::ListWindows,
+        )
+        .await
+    }
+
+    pub async fn capture_pane(&self, pane: u32) -> anyhow::Result<()> {
+        self.command(
+            &format!("capture-pane -p -e -t %{pane}"),
+            PendingCommand::CapturePane(pane),
+        )
+        .await
+    }
+
+    pub async fn send_keys(&self, pane: u32, data: &[u8]) -> anyhow::Result<()> {
+        for chunk in data.chunks(SEND_KEYS_CHUNK) {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+            let command = format!("send-keys -t %{pane} -H {}", hex.join(" "));
+            self.command(&command, PendingCommand::Ignore).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn resize(&self, rows: u16, cols: u16) -> anyhow::Result<()> {
+        let command = format!("refresh-client -C {}x{}", cols.max(1), rows.max(1));
+        self.command(&command, PendingCommand::Ignore).await
+    }
+
+    /// Detach without touching the tmux session itself.
+    pub async fn detach(&self) -> anyhow::Result<()> {
+        self.command("detach-client", PendingCommand::Ignore).await
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TmuxWindow {
+    pub id: u32,
+    pub name: String,
+    pub active: bool,
+    pub layout: TmuxLayout,
+}
+
+/// Parse the reply of `TmuxClient::list_windows`.
+pub fn parse_windows(lines: &[String]) -> Vec<TmuxWindow> {
+    lines
+        .iter()
+        .filter_map(|line| {
+            let mut parts = line.splitn(4, ' ');
+            Some(TmuxWindow {
+                id: parse_id(parts.next()?.as_bytes(), b'@')?,
+                active: parts.next()? == "1",
+                layout: TmuxLayout::parse(parts.next()?)?,
+                name: parts.next().unwrap_or_default().to_string(),
+            })
+        })
+        .collect()
+}
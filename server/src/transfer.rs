@@ -0,0 +1,266 @@
+use serde::Serialize;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+/// ZRQINIT hex header, emitted by a remote `sz` that wants to send files.
+const ZMODEM_SEND_INIT: &[u8] = b"**\x18B00";
+/// ZRINIT hex header, emitted by a remote `rz` waiting for files.
+const ZMODEM_RECV_INIT: &[u8] = b"**\x18B01";
+/// Prefix of the trzsz handshake line, followed by `S` (tsz), `R` (trz) or `D` (trz -d).
+const TRZSZ_MAGIC: &[u8] = b"::TRZSZ:TRANSFER:";
+/// Standard ZMODEM abort: eight CANs followed by eight backspaces.
+const ZMODEM_ABORT: &[u8] = b"\x18\x18\x18\x18\x18\x18\x18\x18\x08\x08\x08\x08\x08\x08\x08\x08";
+/// A remote end gives up on a ZMODEM session by sending at least five CANs in a row.
+const ZMODEM_REMOTE_ABORT: &[u8] = b"\x18\x18\x18\x18\x18";
+/// ZFIN hex header; both sides send one at the end of a session.
+const ZMODEM_FIN: &[u8] = b"**\x18B08";
+/// "Over and out", sent by the sending side after the ZFIN exchange.
+const ZMODEM_OVER_AND_OUT: &[u8] = b"OO";
+/// The browser tells the remote trz/tsz that it is done, with a message to print.
+const TRZSZ_EXIT: &[u8] = b"#EXIT:";
+/// Either side gives up on a trzsz transfer; the browser sends it lowercase.
+const TRZSZ_FAIL: &[&[u8]] = &[b"#FAIL:", b"#fail:"];
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+/// A transfer with no data in either direction for this long is over: the
+/// start sequence was only part of some output, or one side went away.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_PATTERN_LEN: usize = TRZSZ_MAGIC.len() + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferProtocol {
+    Zmodem,
+    Trzsz,
+}
+
+/// Direction as seen from the browser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferDirection {
+    /// Browser to remote (`rz`, `trz`).
+    Upload,
+    /// Remote to browser (`sz`, `tsz`).
+    Download,
+}
+
+#[derive(Debug, Clone)]
+pub struct TransferStart {
+    pub protocol: TransferProtocol,
+    pub direction: TransferDirection,
+    /// Index in the scanned chunk where transfer data begins.
+    pub offset: usize,
+    /// The beginning of the start sequence when it came with earlier reads,
+    /// which were already passed on as terminal output. Transfer data starts
+    /// with these bytes, then the chunk from `offset`.
+    pub carried: Vec<u8>,
+}
+
+/// Scans PTY output for transfer start sequences, including ones split across reads.
+#[derive(Default)]
+pub struct TransferDetector {
+    carry: Vec<u8>,
+}
+
+impl TransferDetector {
+    pub fn scan(&mut self, chunk: &[u8]) -> Option<TransferStart> {
+        let mut window = std::mem::take(&mut self.carry);
+        let carried = window.len();
+        window.extend_from_slice(chunk);
+
+        let zmodem = [
+            (ZMODEM_SEND_INIT, TransferDirection::Download),
+            (ZMODEM_RECV_INIT, TransferDirection::Upload),
+        ]
+        .into_iter()
+        .filter_map(|(pattern, direction)| {
+            find(&window, pattern).map(|pos| (pos, TransferProtocol::Zmodem, direction))
+        });
+        let trzsz = find(&window, TRZSZ_MAGIC).and_then(|pos| {
+            let direction = match window.get(pos + TRZSZ_MAGIC.len())? {
+                b'S' => TransferDirection::Download,
+                b'R' | b'D' => TransferDirection::Upload,
+                _ => return None,
+            };
+            Some((pos, TransferProtocol::Trzsz, direction))
+        });
+
+        let found = zmodem.chain(trzsz).min_by_key(|(pos, _, _)| *pos);
+        match found {
+            Some((pos, protocol, direction)) => Some(TransferStart {
+                protocol,
+                direction,
+                offset: pos.saturating_sub(carried),
+                carried: window[pos.min(carried)..carried].to_vec(),
+            }),
+            None => {
+                let keep = window.len().min(MAX_PATTERN_LEN - 1);
+                self.carry = window.split_off(window.len() - keep);
+                None
+            }
+        }
+    }
+}
+
+/// Keeps the end of a stream so that markers split across reads are found.
+#[derive(Default)]
+struct StreamTail {
+    tail: Vec<u8>,
+}
+
+impl StreamTail {
+    /// `chunk` preceded by the end of the previous one.
+    fn window(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut window = std::mem::take(&mut self.tail);
+        window.extend_from_slice(chunk);
+        let keep = window.len().min(MAX_PATTERN_LEN - 1);
+        self.tail = window[window.len() - keep..].to_vec();
+        window
+    }
+}
+
+/// Byte accounting and end detection for an in-progress transfer.
+pub struct TransferSession {
+    pub protocol: TransferProtocol,
+    pub direction: TransferDirection,
+    sent: u64,
+    received: u64,
+    last_report: Instant,
+    last_activity: Instant,
+    output: StreamTail,
+    input: StreamTail,
+    /// Whether a ZFIN header went by, after which "OO" ends the session.
+    zmodem_fin: bool,
+}
+
+impl TransferSession {
+    pub fn new(start: &TransferStart) -> Self {
+        Self {
+            protocol: start.protocol,
+            direction: start.direction,
+            sent: 0,
+            received: 0,
+            last_report: Instant::now(),
+            last_activity: Instant::now(),
+            output: StreamTail::default(),
+            input: StreamTail::default(),
+            zmodem_fin: false,
+        }
+    }
+
+    /// Account for data from the browser. Returns why the transfer ended
+    /// if this was the last of it.
+    pub fn record_sent(&mut self, data: &[u8]) -> Option<&'static str> {
+        self.sent += data.len() as u64;
+        self.last_activity = Instant::now();
+        let window = self.input.window(data);
+        match self.protocol {
+            TransferProtocol::Zmodem => self.zmodem_end(&window),
+            TransferProtocol::Trzsz if find(&window, TRZSZ_EXIT).is_some() => Some("completed"),
+            TransferProtocol::Trzsz => trzsz_failed(&window),
+        }
+    }
+
+    /// Account for output of the remote side. Returns why the transfer
+    /// ended if this was the last of it.
+    pub fn record_received(&mut self, data: &[u8]) -> Option<&'static str> {
+        self.received += data.len() as u64;
+        self.last_activity = Instant::now();
+        let window = self.output.window(data);
+        match self.protocol {
+            TransferProtocol::Zmodem if find(&window, ZMODEM_REMOTE_ABORT).is_some() => {
+                Some("aborted")
+            }
+            TransferProtocol::Zmodem => self.zmodem_end(&window),
+            TransferProtocol::Trzsz => trzsz_failed(&window),
+        }
+    }
+
+    /// Keep the transfer alive without data, e.g. while the browser waits
+    /// for the user to pick files.
+    pub fn touch(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    pub fn idle(&self) -> bool {
+        self.last_activity.elapsed() >= IDLE_TIMEOUT
+    }
+
+    fn zmodem_end(&mut self, window: &[u8]) -> Option<&'static str> {
+        let searched = match find(window, ZMODEM_FIN) {
+            Some(pos) => {
+                self.zmodem_fin = true;
+                &window[pos + ZMODEM_FIN.len()..]
+            }
+            None => window,
+        };
+        (self.zmodem_fin && find(searched, ZMODEM_OVER_AND_OUT).is_some()).then_some("completed")
+    }
+
+    /// Returns true at most once per `PROGRESS_INTERVAL`.
+    pub fn progress_due(&mut self) -> bool {
+        if self.last_report.elapsed() < PROGRESS_INTERVAL {
+            return false;
+        }
+        self.last_report = Instant::now();
+        true
+    }
+
+    /// Bytes to write to the PTY so the remote side stops the transfer.
+    ///
+    /// trzsz has no fixed abort sequence; the browser-side library sends its own
+    /// stop message as the payload of the cancel frame.
+    pub fn abort_sequence(&self) -> &'static [u8] {
+        match self.protocol {
+            TransferProtocol::Zmodem => ZMODEM_ABORT,
+            TransferProtocol::Trzsz => b"",
+        }
+    }
+
+    pub fn start_event(&self) -> serde_json::Value {
+        json!({
+            "event": "transfer_start",
+            "protocol": self.protocol,
+            "direction": self.direction,
+        })
+    }
+
+    pub fn progress_event(&self) -> serde_json::Value {
+        json!({
+            "event": "transfer_progress",
+            "protocol": self.protocol,
+            "sent": self.sent,
+            "received": self.received,
+        })
+    }
+
+    pub fn end_event(&self, reason: &str) -> serde_json::Value {
+        json!({
+            "event": "transfer_end",
+            "protocol": self.protocol,
+            "reason": reason,
+            "sent": self.sent,
+            "received": self.received,
+        })
+    }
+}
+
+/// Detector and active session for one terminal connection.
+#[derive(Default)]
+pub struct TransferState {
+    pub detector: TransferDetector,
+    pub active: Option<TransferSession>,
+}
+
+fn trzsz_failed(window: &[u8]) -> Option<&'static str> {
+    TRZSZ_FAIL
+        .iter()
+        .any(|marker| find(window, marker).is_some())
+        .then_some("failed")
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
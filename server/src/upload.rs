@@ -0,0 +1,287 @@
+use crate::config::UploadConfig;
+use crate::error::AppError;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+/// How often idle chunked uploads are looked for.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What to do when the uploaded file's name is already taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    #[default]
+    Fail,
+    Overwrite,
+    /// Store as `name (1).ext`, `name (2).ext`, ...
+    Rename,
+}
+
+/// A chunked upload in progress. Data is appended to a hidden `.part` file
+/// next to the target so the final rename never crosses filesystems.
+pub struct PendingUpload {
+    pub id: String,
+    pub dir: PathBuf,
+    pub name: String,
+    pub size: u64,
+    pub sha256: Option<String>,
+    pub conflict: ConflictPolicy,
+    pub part: PathBuf,
+    last_activity: Instant,
+}
+
+impl PendingUpload {
+    pub fn new(
+        dir: PathBuf,
+        name: String,
+        size: u64,
+        sha256: Option<String>,
+        conflict: ConflictPolicy,
+    ) -> Self {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        Self {
+            part: part_path(&dir, &name, &id),
+            id,
+            dir,
+            name,
+            size,
+            sha256,
+            conflict,
+            last_activity: Instant::now(),
+        }
+    }
+
+    pub fn touch(&mut self) {
+        self.last_activity = Instant::now();
+    }
+}
+
+struct UploadEntry {
+    /// Copy of the declared size, readable while a chunk holds the upload lock.
+    size: u64,
+    upload: Arc<Mutex<PendingUpload>>,
+}
+
+#[derive(Clone, Default)]
+pub struct UploadStore {
+    inner: Arc<Mutex<HashMap<String, UploadEntry>>>,
+    /// Bytes received so far by multipart uploads that are still streaming.
+    streaming: Arc<AtomicU64>,
+}
+
+/// A multipart upload's share of `max_pending_size`, returned on drop.
+pub struct StreamingUpload {
+    store: UploadStore,
+    bytes: u64,
+}
+
+impl StreamingUpload {
+    /// Account for `n` more received bytes, unless that exceeds the quota.
+    pub async fn grow(&mut self, config: &UploadConfig, n: u64) -> Result<(), AppError> {
+        let guard = self.store.inner.lock().await;
+        if UploadStore::pending(&guard, &self.store.streaming).saturating_add(n)
+            > config.max_pending_size
+        {
+            return Err(AppError::TooLarge("upload quota exceeded".into()));
+        }
+        self.store.streaming.fetch_add(n, Ordering::Relaxed);
+        self.bytes += n;
+        Ok(())
+    }
+}
+
+impl Drop for StreamingUpload {
+    fn drop(&mut self) {
+        self.store
+            .streaming
+            .fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+impl UploadStore {
+    /// Register an upload after checking it against the configured quotas.
+    pub async fn create(
+        &self,
+        config: &UploadConfig,
+        upload: PendingUpload,
+    ) -> Result<Arc<Mutex<PendingUpload>>, AppError> {
+        if upload.size > config.max_file_size {
+            return Err(AppError::TooLarge(format!(
+                "file exceeds the {} byte upload limit",
+                config.max_file_size
+            )));
+        }
+
+        self.prune_expired(Duration::from_secs(
+            config.expire_minutes.saturating_mul(60),
+        ))
+        .await;
+        let mut guard = self.inner.lock().await;
+        let pending = Self::pending(&guard, &self.streaming);
+        if pending.saturating_add(upload.size) > config.max_pending_size {
+            return Err(AppError::TooLarge("upload quota exceeded".into()));
+        }
+
+        let id = upload.id.clone();
+        let size = upload.size;
+        let upload = Arc::new(Mutex::new(upload));
+        guard.insert(
+            id,
+            UploadEntry {
+                size,
+                upload: upload.clone(),
+            },
+        );
+        Ok(upload)
+    }
+
+    /// Start counting a multipart upload against the quota.
+    pub fn stream(&self) -> StreamingUpload {
+        StreamingUpload {
+            store: self.clone(),
+            bytes: 0,
+        }
+    }
+
+    /// Discard idle chunked uploads periodically, not only when a new one
+    /// is created.
+    pub fn start_pruning(&self, config: &UploadConfig) {
+        let store = self.clone();
+        let ttl = Duration::from_secs(config.expire_minutes.saturating_mul(60));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+            loop {
+                interval.tick().await;
+                store.prune_expired(ttl).await;
+            }
+        });
+    }
+
+    /// Declared sizes of chunked uploads plus what multipart uploads hold.
+    fn pending(entries: &HashMap<String, UploadEntry>, streaming: &AtomicU64) -> u64 {
+        let declared: u64 = entries.values().map(|entry| entry.size).sum();
+        declared.saturating_add(streaming.load(Ordering::Relaxed))
+    }
+
+    pub async fn get(&self, id: &str) -> Option<Arc<Mutex<PendingUpload>>> {
+        self.inner
+            .lock()
+            .await
+            .get(id)
+            .map(|entry| entry.upload.clone())
+    }
+
+    pub async fn remove(&self, id: &str) {
+        self.inner.lock().await.remove(id);
+    }
+
+    async fn prune_expired(&self, ttl: Duration) {
+        let mut guard = self.inner.lock().await;
+        let mut expired = Vec::new();
+        guard.retain(|_, entry| match entry.upload.try_lock() {
+            Ok(upload) if upload.last_activity.elapsed() > ttl => {
+                expired.push(upload.part.clone());
+                false
+            }
+            _ => true,
+        });
+        drop(guard);
+        for part in expired {
+            tracing::info!("discarding idle upload {}", part.display());
+            let _ = tokio::fs::remove_file(part).await;
+        }
+    }
+}
+
+/// Reject names that would place the file somewhere other than the target
+/// directory.
+pub fn validate_name(name: &str) -> Result<(), AppError> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+        return Err(AppError::BadRequest(format!("invalid file name: {name:?}")));
+    }
+    Ok(())
+}
+
+pub fn part_path(dir: &Path, name: &str, id: &str) -> PathBuf {
+    dir.join(format!(".{name}.{id}.part"))
+}
+
+/// Where a finished upload should be stored under `policy`.
+pub fn resolve_target(dir: &Path, name: &str, policy: ConflictPolicy) -> Result<PathBuf, AppError> {
+    let target = dir.join(name);
+    let Ok(existing) = std::fs::symlink_metadata(&target) else {
+        return Ok(target);
+    };
+    match policy {
+        ConflictPolicy::Fail => Err(AppError::Conflict(format!("{name} already exists"))),
+        ConflictPolicy::Overwrite if existing.is_dir() => Err(AppError::Conflict(format!(
+            "{name} is an existing directory"
+        ))),
+        ConflictPolicy::Overwrite => Ok(target),
+        ConflictPolicy::Rename => {
+            let path = Path::new(name);
+            let stem = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| name.to_string());
+            let ext = path
+                .extension()
+                .map(|e| format!(".{}", e.to_string_lossy()))
+                .unwrap_or_default();
+            (1..)
+                .map(|n| dir.join(format!("{stem} ({n}){ext}")))
+                .find(|candidate| std::fs::symlink_metadata(candidate).is_err())
+                .ok_or_else(|| AppError::Internal("no free file name".into()))
+        }
+    }
+}
+
+/// Compare a client-supplied hex digest with the computed one.
+pub fn checksum_matches(expected: &str, actual: &str) -> bool {
+    expected.trim().eq_ignore_ascii_case(actual)
+}
+
+/// Hex SHA-256 of a file. Blocking.
+pub fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Rate limit for progress events while a body streams in.
+pub struct ProgressThrottle {
+    last_report: Instant,
+}
+
+impl ProgressThrottle {
+    pub fn new() -> Self {
+        Self {
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Returns true at most once per `PROGRESS_INTERVAL`.
+    pub fn due(&mut self) -> bool {
+        if self.last_report.elapsed() < PROGRESS_INTERVAL {
+            return false;
+        }
+        self.last_report = Instant::now();
+        true
+    }
+}
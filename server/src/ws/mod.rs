@@ -1,5 +1,6 @@
 pub mod system;
 pub mod terminal;
+pub mod tmux;
 
 use crate::state::AppState;
 use axum::Router;
@@ -8,4 +9,5 @@ pub fn router() -> Router<AppState> {
     Router::new()
         .merge(terminal::router())
         .merge(system::router())
+        .merge(tmux::router())
 }
@@ -1,7 +1,13 @@
+use crate::events::ServerEvent;
+use crate::fs::FsRoot;
+use crate::http::login::extract_session_id_from_headers;
+use crate::proxy::{self, ListeningPort};
 use crate::state::AppState;
+use crate::tail::{Tail, TailUpdate};
 use axum::extract::ws::{Message, WebSocket};
 use axum::{
     extract::{State, WebSocketUpgrade},
+    http::HeaderMap,
     response::IntoResponse,
     routing::get,
     Router,
@@ -11,14 +17,29 @@ use futures::{
     SinkExt,
 };
 use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
+use regex::Regex;
 use serde::Deserialize;
 use serde_json::json;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::select;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::time::MissedTickBehavior;
+
+const PORT_SCAN_INTERVAL: Duration = Duration::from_secs(2);
+/// Followed files are also checked on this interval, to catch rotation and
+/// changes the watcher misses (or all of them when watching is disabled).
+const TAIL_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Most reads of one followed file per poll, so a file growing faster than
+/// it is sent can't keep the connection from handling anything else; the
+/// next poll carries on.
+const MAX_TAIL_READS: usize = 4;
+/// Files one connection may follow at a time.
+const MAX_TAILS: usize = 16;
+/// Most lines a `tail` request may ask to be backfilled.
+const MAX_BACKFILL: usize = 10_000;
 
 pub fn router() -> Router<AppState> {
     Router::new().route("/ws/system", get(ws_handler))
@@ -31,20 +52,57 @@ enum SystemClientMessage {
     Watch { path: String },
     #[serde(rename = "unwatch")]
     Unwatch { path: String },
+    #[serde(rename = "watch_ports")]
+    WatchPorts,
+    #[serde(rename = "unwatch_ports")]
+    UnwatchPorts,
+    /// Follow a file, sending lines as they are appended.
+    #[serde(rename = "tail")]
+    Tail {
+        path: String,
+        /// Byte offset to start from, such as the `offset` of an earlier
+        /// `tail` event; otherwise the end of the file.
+        offset: Option<u64>,
+        /// Lines before the end to send first, when there is no `offset`.
+        #[serde(default)]
+        lines: usize,
+        /// Regex; only matching lines are sent.
+        filter: Option<String>,
+    },
+    #[serde(rename = "untail")]
+    Untail { path: String },
+}
+
+/// What one connection is watching.
+#[derive(Default)]
+struct ClientState {
+    watcher: Option<RecommendedWatcher>,
+    tracked: HashMap<PathBuf, String>,
+    /// Last reported listening ports; `None` while the client isn't watching them.
+    known_ports: Option<Vec<ListeningPort>>,
+    /// Followed files by resolved path, with the path the client used.
+    tails: HashMap<PathBuf, (String, Tail)>,
 }
 
-pub async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let session_id = extract_session_id_from_headers(&headers);
+    ws.on_upgrade(move |socket| handle_socket(socket, state, session_id))
 }
 
-async fn handle_socket(socket: WebSocket, state: AppState) {
+/// Paths in messages are relative to the session's browsing root, looked up
+/// anew for every message since it can change while the socket is open.
+async fn handle_socket(socket: WebSocket, state: AppState, session_id: Option<String>) {
     tracing::info!("new system ws connection");
 
     let (sender, mut receiver) = socket.split();
     let sender = Arc::new(Mutex::new(sender));
     let watch_enabled = state.config.features.enable_watch;
     let (event_tx, mut event_rx) = mpsc::unbounded_channel();
-    let mut watcher = if watch_enabled {
+    let watcher = if watch_enabled {
         match RecommendedWatcher::new(
             move |res| {
                 let _ = event_tx.send(res);
@@ -62,14 +120,23 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
         None
     };
 
-    let mut tracked: HashMap<PathBuf, String> = HashMap::new();
+    let mut client = ClientState {
+        watcher,
+        ..Default::default()
+    };
+    let mut server_events = state.events.subscribe();
+    let mut port_scan = tokio::time::interval(PORT_SCAN_INTERVAL);
+    port_scan.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut tail_poll = tokio::time::interval(TAIL_POLL_INTERVAL);
+    tail_poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
     loop {
         select! {
             ws_msg = receiver.next() => {
                 match ws_msg {
                     Some(Ok(Message::Text(text))) => {
-                        handle_client_message(&state, &sender, &mut watcher, &mut tracked, text, watch_enabled).await;
+                        let root = state.fs_root(session_id.as_deref()).await;
+                        handle_client_message(&state, &root, &sender, &mut client, text).await;
                     }
                     Some(Ok(Message::Ping(payload))) => {
                         let _ = sender.lock().await.send(Message::Pong(payload)).await;
@@ -86,10 +153,30 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                     None => break,
                 }
             }
-            event = event_rx.recv(), if watch_enabled && watcher.is_some() => {
+            event = event_rx.recv(), if watch_enabled && client.watcher.is_some() => {
                 match event {
-                    Some(Ok(ev)) => {
-                        forward_event(&state, &sender, &tracked, ev).await;
+                    Some(Ok(mut ev)) => {
+                        if !client.tails.is_empty() {
+                            let tailed: Vec<PathBuf> = ev
+                                .paths
+                                .iter()
+                                .filter(|p| client.tails.contains_key(*p))
+                                .cloned()
+                                .collect();
+                            for path in tailed {
+                                poll_tail(&sender, &mut client.tails, path).await;
+                            }
+                            // Appends to a followed file are reported as lines;
+                            // only send `change` if the client watches it too.
+                            let tracked = &client.tracked;
+                            ev.paths.retain(|p| {
+                                !client.tails.contains_key(p)
+                                    || tracked.contains_key(p)
+                                    || p.parent().is_some_and(|d| tracked.contains_key(d))
+                            });
+                        }
+                        let root = state.fs_root(session_id.as_deref()).await;
+                        forward_event(&root, &sender, &client.tracked, ev).await;
                     }
                     Some(Err(err)) => {
                         tracing::warn!("watcher error: {err:?}");
@@ -98,6 +185,31 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                     None => break,
                 }
             }
+            server_event = server_events.recv() => {
+                match server_event {
+                    Ok(ev) => {
+                        let root = state.fs_root(session_id.as_deref()).await;
+                        forward_server_event(&root, &sender, &client.tracked, ev).await;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("system ws dropped {skipped} server events");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+            _ = port_scan.tick(), if client.known_ports.is_some() => {
+                let ports = scan_ports(&state).await;
+                if client.known_ports.as_ref() != Some(&ports) {
+                    send_json(&sender, json!({"event":"ports","ports":ports})).await;
+                    client.known_ports = Some(ports);
+                }
+            }
+            _ = tail_poll.tick(), if !client.tails.is_empty() => {
+                let paths: Vec<PathBuf> = client.tails.keys().cloned().collect();
+                for path in paths {
+                    poll_tail(&sender, &mut client.tails, path).await;
+                }
+            }
         }
     }
 
@@ -106,11 +218,10 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
 
 async fn handle_client_message(
     state: &AppState,
+    root: &FsRoot,
     sender: &Arc<Mutex<SplitSink<WebSocket, Message>>>,
-    watcher: &mut Option<RecommendedWatcher>,
-    tracked: &mut HashMap<PathBuf, String>,
+    client: &mut ClientState,
     payload: String,
-    watch_enabled: bool,
 ) {
     let msg = match serde_json::from_str::<SystemClientMessage>(&payload) {
         Ok(m) => m,
@@ -123,11 +234,11 @@ async fn handle_client_message(
 
     match msg {
         SystemClientMessage::Watch { path } => {
-            if !watch_enabled {
+            if !state.config.features.enable_watch {
                 send_error(sender, "file watching disabled").await;
                 return;
             }
-            let resolved = match state.fs.resolve_path(&path) {
+            let resolved = match root.resolve_path(&path) {
                 Ok(p) => p,
                 Err(err) => {
                     tracing::warn!("watch path rejected: {err}");
@@ -135,7 +246,7 @@ async fn handle_client_message(
                     return;
                 }
             };
-            let Some(watcher_ref) = watcher.as_mut() else {
+            let Some(watcher_ref) = client.watcher.as_mut() else {
                 send_error(sender, "watcher unavailable").await;
                 return;
             };
@@ -144,16 +255,18 @@ async fn handle_client_message(
                 send_error(sender, "watch failed").await;
                 return;
             }
-            tracked.insert(resolved, path.clone());
+            client.tracked.insert(resolved, path.clone());
             send_json(sender, json!({"event":"watching","path":path})).await;
         }
         SystemClientMessage::Unwatch { path } => {
-            let resolved = match state.fs.resolve_path(&path) {
+            let resolved = match root.resolve_path(&path) {
                 Ok(p) => p,
                 Err(_) => return,
             };
-            if let Some(watcher_ref) = watcher.as_mut() {
-                if tracked.remove(&resolved).is_some() {
+            if let Some(watcher_ref) = client.watcher.as_mut() {
+                if client.tracked.remove(&resolved).is_some()
+                    && !client.tails.contains_key(&resolved)
+                {
                     let _ = watcher_ref.unwatch(&resolved);
                 }
             } else {
@@ -162,11 +275,153 @@ async fn handle_client_message(
             }
             send_json(sender, json!({"event":"unwatched","path":path})).await;
         }
+        SystemClientMessage::WatchPorts => {
+            let ports = scan_ports(state).await;
+            send_json(sender, json!({"event":"ports","ports":ports})).await;
+            client.known_ports = Some(ports);
+        }
+        SystemClientMessage::UnwatchPorts => {
+            client.known_ports = None;
+        }
+        SystemClientMessage::Tail {
+            path,
+            offset,
+            lines,
+            filter,
+        } => {
+            // Not following the last component, so a symlink swapped to a new
+            // file counts as rotation.
+            let resolved = match root.resolve_entry(&path) {
+                Ok(p) => p,
+                Err(err) => {
+                    tracing::warn!("tail path rejected: {err}");
+                    send_error(sender, "invalid path").await;
+                    return;
+                }
+            };
+            if !client.tails.contains_key(&resolved) && client.tails.len() >= MAX_TAILS {
+                send_error(sender, "too many tailed files").await;
+                return;
+            }
+            let filter = match filter.as_deref().map(Regex::new).transpose() {
+                Ok(filter) => filter,
+                Err(_) => {
+                    send_error(sender, "invalid filter").await;
+                    return;
+                }
+            };
+            let lines = lines.min(MAX_BACKFILL);
+            let (root, file) = (root.clone(), resolved.clone());
+            let opened = tokio::task::spawn_blocking(move || {
+                Tail::open(&root, &file, offset, lines, filter)
+            })
+            .await;
+            let (tail, update) = match opened {
+                Ok(Ok(opened)) => opened,
+                Ok(Err(err)) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+                    tracing::warn!("tail of {resolved:?} refused: {err}");
+                    send_error(sender, "path not allowed").await;
+                    return;
+                }
+                Ok(Err(err)) => {
+                    tracing::warn!("failed to tail {resolved:?}: {err}");
+                    send_error(sender, "tail failed").await;
+                    return;
+                }
+                Err(_) => return,
+            };
+            if let Some(watcher_ref) = client.watcher.as_mut() {
+                if let Err(err) = watcher_ref.watch(&resolved, RecursiveMode::NonRecursive) {
+                    // Polling still picks up changes, just later.
+                    tracing::warn!("failed to watch tailed {resolved:?}: {err:?}");
+                }
+            }
+            send_json(sender, json!({"event":"tailing","path":path})).await;
+            send_tail_update(sender, &path, &tail, &update).await;
+            let more = update.more;
+            client.tails.insert(resolved.clone(), (path, tail));
+            if more {
+                poll_tail(sender, &mut client.tails, resolved).await;
+            }
+        }
+        SystemClientMessage::Untail { path } => {
+            let Ok(resolved) = root.resolve_entry(&path) else {
+                return;
+            };
+            if client.tails.remove(&resolved).is_some() && !client.tracked.contains_key(&resolved) {
+                if let Some(watcher_ref) = client.watcher.as_mut() {
+                    let _ = watcher_ref.unwatch(&resolved);
+                }
+            }
+            send_json(sender, json!({"event":"untailed","path":path})).await;
+        }
     }
 }
 
+/// Send what was appended to a followed file, reading until it is caught up
+/// or [`MAX_TAIL_READS`] reads were made.
+async fn poll_tail(
+    sender: &Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    tails: &mut HashMap<PathBuf, (String, Tail)>,
+    path: PathBuf,
+) {
+    for _ in 0..MAX_TAIL_READS {
+        let Some((rel, mut tail)) = tails.remove(&path) else {
+            return;
+        };
+        let polled = tokio::task::spawn_blocking(move || {
+            let update = tail.poll();
+            (tail, update)
+        })
+        .await;
+        let Ok((tail, update)) = polled else {
+            return;
+        };
+        let update = match update {
+            Ok(update) => update,
+            Err(err) => {
+                tracing::warn!("tail of {path:?} failed: {err}");
+                send_json(
+                    sender,
+                    json!({"event":"untailed","path":rel,"error":err.to_string()}),
+                )
+                .await;
+                return;
+            }
+        };
+        if !update.lines.is_empty() || update.truncated || update.rotated {
+            send_tail_update(sender, &rel, &tail, &update).await;
+        }
+        tails.insert(path.clone(), (rel, tail));
+        if !update.more {
+            return;
+        }
+    }
+}
+
+async fn send_tail_update(
+    sender: &Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    path: &str,
+    tail: &Tail,
+    update: &TailUpdate,
+) {
+    let mut payload = json!({
+        "event": "tail",
+        "path": path,
+        "lines": update.lines,
+        "offset": tail.offset(),
+    });
+    if update.truncated {
+        payload["truncated"] = json!(true);
+    }
+    if update.rotated {
+        payload["rotated"] = json!(true);
+    }
+    send_json(sender, payload).await;
+}
+
 async fn forward_event(
-    state: &AppState,
+    root: &FsRoot,
     sender: &Arc<Mutex<SplitSink<WebSocket, Message>>>,
     tracked: &HashMap<PathBuf, String>,
     event: Event,
@@ -176,13 +431,16 @@ async fn forward_event(
         .unwrap_or_default()
         .as_secs();
     for path in event.paths {
+        if root.is_hidden(&path) {
+            continue;
+        }
         let canonical = path.canonicalize().ok();
         let rel = canonical
             .as_ref()
             .and_then(|p| tracked.get(p).cloned())
-            .or_else(|| canonical.as_ref().and_then(|p| state.fs.to_relative(p)))
+            .or_else(|| canonical.as_ref().and_then(|p| root.to_relative(p)))
             .or_else(|| tracked.get(&path).cloned())
-            .or_else(|| state.fs.to_relative(&path));
+            .or_else(|| root.to_relative(&path));
         if let Some(path_str) = rel {
             send_json(
                 sender,
@@ -193,6 +451,86 @@ async fn forward_event(
     }
 }
 
+/// Report upload progress to every client, and API-made filesystem changes
+/// under watched paths with the same `change` event the watcher produces plus
+/// what kind of change it was.
+async fn forward_server_event(
+    root: &FsRoot,
+    sender: &Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    tracked: &HashMap<PathBuf, String>,
+    event: ServerEvent,
+) {
+    let (kind, path, from) = match event {
+        ServerEvent::FsChange { kind, path, from } => (kind, path, from),
+        ServerEvent::UploadProgress {
+            id,
+            path,
+            received,
+            size,
+        } => {
+            send_json(
+                sender,
+                json!({"event":"upload_progress","id":id,"path":path,"received":received,"size":size}),
+            )
+            .await;
+            return;
+        }
+        ServerEvent::UploadFinished { id, path, error } => {
+            send_json(
+                sender,
+                json!({"event":"upload_finished","id":id,"path":path,"ok":error.is_none(),"error":error}),
+            )
+            .await;
+            return;
+        }
+        ServerEvent::LineIndexProgress {
+            path,
+            indexed,
+            size,
+            done,
+        } => {
+            if root.is_hidden(&path) {
+                return;
+            }
+            if let Some(path) = root.to_relative(&path) {
+                send_json(
+                    sender,
+                    json!({"event":"line_index_progress","path":path,"indexed":indexed,"size":size,"done":done}),
+                )
+                .await;
+            }
+            return;
+        }
+    };
+    let watched =
+        |p: &Path| tracked.contains_key(p) || p.parent().is_some_and(|d| tracked.contains_key(d));
+    if !watched(&path) && !from.as_deref().is_some_and(watched) {
+        return;
+    }
+    if root.is_hidden(&path) {
+        return;
+    }
+    let Some(path_str) = root.to_relative(&path) else {
+        return;
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut payload = json!({"event":"change","path":path_str,"kind":kind,"timestamp":timestamp});
+    if let Some(from) = from.as_deref().and_then(|p| root.to_relative(p)) {
+        payload["from"] = json!(from);
+    }
+    send_json(sender, payload).await;
+}
+
+async fn scan_ports(state: &AppState) -> Vec<ListeningPort> {
+    let config = state.config.clone();
+    tokio::task::spawn_blocking(move || proxy::proxyable_ports(&config))
+        .await
+        .unwrap_or_default()
+}
+
 async fn send_error(sender: &Arc<Mutex<SplitSink<WebSocket, Message>>>, message: &str) {
     send_json(sender, json!({"event":"error","message":message})).await;
 }
@@ -1,8 +1,12 @@
+use crate::config::TerminalProfile;
+use crate::error::AppError;
 use crate::state::AppState;
+use crate::terminal::{TerminalSession, TerminalSignal};
+use crate::transfer::{TransferSession, TransferState};
 use axum::extract::ws::{Message, WebSocket};
 use axum::{
-    extract::{State, WebSocketUpgrade},
-    response::IntoResponse,
+    extract::{Query, State, WebSocketUpgrade},
+    response::{IntoResponse, Response},
     routing::get,
     Router,
 };
@@ -10,37 +14,85 @@ use futures::{
     stream::{SplitSink, StreamExt},
     SinkExt,
 };
+use serde::Deserialize;
+use serde_json::json;
 use std::io::Read;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
 
+/// How often an active transfer is checked for having gone idle.
+const TRANSFER_WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+/// Ctrl-C and CAN, passed through even during a transfer so it can always be aborted.
+const INTERRUPT: u8 = 0x03;
+const CANCEL: u8 = 0x18;
+
 pub fn router() -> Router<AppState> {
     Router::new().route("/ws/term", get(ws_handler))
 }
 
-pub async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+#[derive(Debug, Deserialize)]
+pub struct TermQuery {
+    pub profile: Option<String>,
+}
+
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Query(query): Query<TermQuery>,
+) -> Result<Response, AppError> {
+    let profile = match query.profile.as_deref() {
+        Some(name) => Some(
+            state
+                .config
+                .terminal_profile(name)
+                .cloned()
+                .ok_or_else(|| AppError::BadRequest(format!("unknown terminal profile: {name}")))?,
+        ),
+        None => None,
+    };
+    Ok(ws
+        .on_upgrade(move |socket| handle_socket(socket, state, profile))
+        .into_response())
 }
 
-async fn handle_socket(socket: WebSocket, state: AppState) {
-    tracing::info!("new terminal ws connection");
+async fn handle_socket(socket: WebSocket, state: AppState, profile: Option<TerminalProfile>) {
+    tracing::info!(
+        "new terminal ws connection (profile: {})",
+        profile.as_ref().map_or("default", |p| p.name.as_str())
+    );
 
     let (sender, mut receiver) = socket.split();
     let sender = Arc::new(Mutex::new(sender));
 
-    // Initialize PTY session immediately with default size (will be resized by client shortly)
-    let (active_session, reader) = match state.pty.create_session(24, 80) {
-        Ok(pair) => pair,
-        Err(err) => {
-            tracing::error!("failed to create pty session: {err:?}");
-            let _ = sender.lock().await.send(Message::Close(None)).await;
-            return;
-        }
-    };
-    
+    // Initialize session immediately with default size (will be resized by client shortly)
+    let (active_session, reader) =
+        match TerminalSession::open(&state.pty, profile.as_ref(), 24, 80).await {
+            Ok(pair) => pair,
+            Err(err) => {
+                tracing::error!("failed to create terminal session: {err:?}");
+                send_json(
+                    &sender,
+                    json!({"event":"error","message":format!("{err:#}")}),
+                )
+                .await;
+                let _ = sender.lock().await.send(Message::Close(None)).await;
+                return;
+            }
+        };
+
+    let active_session = Arc::new(active_session);
     let session_id = active_session.id();
-    let reader_task = spawn_reader_task(reader, sender.clone());
+    let transfer = Arc::new(Mutex::new(TransferState::default()));
+    let reader_task = spawn_reader_task(
+        reader,
+        sender.clone(),
+        active_session.clone(),
+        transfer.clone(),
+    );
+    let watchdog =
+        spawn_transfer_watchdog(sender.clone(), active_session.clone(), transfer.clone());
 
     while let Some(Ok(msg)) = receiver.next().await {
         match msg {
@@ -49,8 +101,41 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                     // 0x01: Input (Stdin)
                     0x01 => {
                         if bytes.len() > 1 {
-                            if let Err(err) = active_session.write(&bytes[1..]).await {
-                                tracing::error!("pty write failed: {err:?}");
+                            let transferring = transfer.lock().await.active.is_some();
+                            if !transferring {
+                                if let Err(err) = active_session.write(&bytes[1..]).await {
+                                    tracing::error!("pty write failed: {err:?}");
+                                }
+                            } else {
+                                // Other keystrokes would corrupt the transfer stream
+                                let aborts: Vec<u8> = bytes[1..]
+                                    .iter()
+                                    .copied()
+                                    .filter(|&b| b == INTERRUPT || b == CANCEL)
+                                    .collect();
+                                if aborts.contains(&INTERRUPT) {
+                                    let cancelled = cancel_transfer(
+                                        &active_session,
+                                        &sender,
+                                        &transfer,
+                                        &aborts,
+                                    )
+                                    .await;
+                                    // The transfer ended meanwhile; it's a plain Ctrl-C now
+                                    if !cancelled {
+                                        if let Err(err) = active_session.write(&aborts).await {
+                                            tracing::error!("pty write failed: {err:?}");
+                                        }
+                                    }
+                                } else if !aborts.is_empty() {
+                                    handle_transfer_data(
+                                        &active_session,
+                                        &sender,
+                                        &transfer,
+                                        &aborts,
+                                    )
+                                    .await;
+                                }
                             }
                         }
                     }
@@ -65,6 +150,34 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                             }
                         }
                     }
+                    // 0x03: Transfer data
+                    0x03 => {
+                        handle_transfer_data(&active_session, &sender, &transfer, &bytes[1..])
+                            .await;
+                    }
+                    // 0x04: Cancel transfer (optional payload: protocol-level stop message)
+                    0x04 => {
+                        cancel_transfer(&active_session, &sender, &transfer, &bytes[1..]).await;
+                    }
+                    // 0x05: Transfer finished
+                    0x05 => {
+                        let finished = transfer.lock().await.active.take();
+                        if let Some(session) = finished {
+                            send_json(&sender, session.end_event("completed")).await;
+                        }
+                    }
+                    // 0x06: Signal, followed by its name (e.g. "INT", "TERM")
+                    0x06 => {
+                        let name = String::from_utf8_lossy(&bytes[1..]);
+                        match TerminalSignal::from_name(&name) {
+                            Some(signal) => {
+                                if let Err(err) = active_session.signal(signal).await {
+                                    tracing::warn!("failed to deliver {signal:?}: {err:?}");
+                                }
+                            }
+                            None => tracing::warn!("unknown signal requested: {name}"),
+                        }
+                    }
                     _ => {}
                 }
             }
@@ -80,6 +193,7 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
 
     active_session.shutdown().await;
     reader_task.abort();
+    watchdog.abort();
 
     tracing::info!("terminal ws connection ended (session: {})", session_id);
 }
@@ -87,6 +201,8 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
 fn spawn_reader_task(
     mut reader: Box<dyn Read + Send>,
     sender: Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    session: Arc<TerminalSession>,
+    transfer: Arc<Mutex<TransferState>>,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
         let mut buffer = vec![0u8; 4096];
@@ -94,15 +210,18 @@ fn spawn_reader_task(
             let read_res = tokio::task::block_in_place(|| reader.read(&mut buffer));
             match read_res {
                 Ok(0) => {
-                    // EOF: Shell exited. Close the WebSocket to notify client.
+                    // EOF: Shell exited. Report the status and close the WebSocket.
+                    let code = session.exit_code().await;
+                    send_json(&sender, json!({"event":"exit","code":code})).await;
                     let mut guard = sender.lock().await;
                     let _ = guard.send(Message::Close(None)).await;
                     break;
                 }
                 Ok(n) => {
-                    // Send raw binary data directly
-                    let mut guard = sender.lock().await;
-                    if guard.send(Message::Binary(buffer[..n].to_vec())).await.is_err() {
+                    if forward_output(&sender, &transfer, &buffer[..n])
+                        .await
+                        .is_err()
+                    {
                         break;
                     }
                 }
@@ -115,3 +234,151 @@ fn spawn_reader_task(
     })
 }
 
+/// Send PTY output to the client, switching into transfer mode when a
+/// trzsz/ZMODEM start sequence shows up. While a transfer is active the
+/// binary frames carry protocol data instead of terminal output.
+async fn forward_output(
+    sender: &Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    transfer: &Arc<Mutex<TransferState>>,
+    chunk: &[u8],
+) -> Result<(), axum::Error> {
+    let mut transfer = transfer.lock().await;
+    let transfer = &mut *transfer;
+
+    if let Some(session) = transfer.active.as_mut() {
+        let ended = session.record_received(chunk);
+        send_binary(sender, chunk).await?;
+        if let Some(reason) = ended {
+            tracing::info!("file transfer ended ({reason})");
+            send_json(sender, session.end_event(reason)).await;
+            transfer.active = None;
+        } else if session.progress_due() {
+            send_json(sender, session.progress_event()).await;
+        }
+        return Ok(());
+    }
+
+    let Some(start) = transfer.detector.scan(chunk) else {
+        return send_binary(sender, chunk).await;
+    };
+
+    let (before, rest) = chunk.split_at(start.offset);
+    // The handler must see the whole start sequence, even the part that
+    // already went out as terminal output.
+    let data = [start.carried.as_slice(), rest].concat();
+    if !before.is_empty() {
+        send_binary(sender, before).await?;
+    }
+    let mut session = TransferSession::new(&start);
+    tracing::info!(
+        "file transfer started ({:?}, {:?})",
+        session.protocol,
+        session.direction
+    );
+    send_json(sender, session.start_event()).await;
+    session.record_received(&data);
+    if !data.is_empty() {
+        send_binary(sender, &data).await?;
+    }
+    transfer.active = Some(session);
+    Ok(())
+}
+
+async fn handle_transfer_data(
+    session: &TerminalSession,
+    sender: &Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    transfer: &Arc<Mutex<TransferState>>,
+    data: &[u8],
+) {
+    // Account under the lock but write without it: a blocked PTY write must not
+    // stall the reader task that drains the remote side's acknowledgements.
+    let event = {
+        let mut transfer = transfer.lock().await;
+        let Some(active) = transfer.active.as_mut() else {
+            tracing::warn!("transfer data received outside of a transfer");
+            return;
+        };
+        if data.is_empty() {
+            // Keep-alive while the browser waits for the user
+            active.touch();
+            return;
+        }
+        match active.record_sent(data) {
+            Some(reason) => {
+                let event = active.end_event(reason);
+                transfer.active = None;
+                tracing::info!("file transfer ended ({reason})");
+                Some(event)
+            }
+            None => active.progress_due().then(|| active.progress_event()),
+        }
+    };
+    if let Err(err) = session.write(data).await {
+        tracing::error!("pty write failed during transfer: {err:?}");
+        return;
+    }
+    if let Some(event) = event {
+        send_json(sender, event).await;
+    }
+}
+
+/// End a transfer that no data has moved for, so that a start sequence
+/// that was only part of some output doesn't leave the terminal without
+/// input. The remote side is told to stop too.
+fn spawn_transfer_watchdog(
+    sender: Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    session: Arc<TerminalSession>,
+    transfer: Arc<Mutex<TransferState>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TRANSFER_WATCHDOG_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some(idle) = transfer.lock().await.active.take_if(|active| active.idle()) else {
+                continue;
+            };
+            tracing::info!("file transfer timed out");
+            let abort = idle.abort_sequence();
+            if !abort.is_empty() {
+                if let Err(err) = session.write(abort).await {
+                    tracing::warn!("failed to send transfer abort: {err:?}");
+                }
+            }
+            send_json(&sender, idle.end_event("timeout")).await;
+        }
+    })
+}
+
+async fn cancel_transfer(
+    session: &TerminalSession,
+    sender: &Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    transfer: &Arc<Mutex<TransferState>>,
+    stop_message: &[u8],
+) -> bool {
+    let Some(active) = transfer.lock().await.active.take() else {
+        return false;
+    };
+    let mut payload = stop_message.to_vec();
+    payload.extend_from_slice(active.abort_sequence());
+    if !payload.is_empty() {
+        if let Err(err) = session.write(&payload).await {
+            tracing::warn!("failed to send transfer abort: {err:?}");
+        }
+    }
+    tracing::info!("file transfer cancelled by client");
+    send_json(sender, active.end_event("cancelled")).await;
+    true
+}
+
+async fn send_binary(
+    sender: &Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    data: &[u8],
+) -> Result<(), axum::Error> {
+    let mut guard = sender.lock().await;
+    guard.send(Message::Binary(data.to_vec())).await
+}
+
+async fn send_json(sender: &Arc<Mutex<SplitSink<WebSocket, Message>>>, value: serde_json::Value) {
+    let mut guard = sender.lock().await;
+    let _ = guard.send(Message::Text(value.to_string())).await;
+}
@@ -0,0 +1,281 @@
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::tmux::{self, ControlEvent, ControlParser, PendingCommand, TmuxClient, TmuxLayout};
+use axum::extract::ws::{Message, WebSocket};
+use axum::{
+    extract::{Query, State, WebSocketUpgrade},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use futures::{
+    stream::{SplitSink, StreamExt},
+    SinkExt,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashSet;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/ws/tmux", get(ws_handler))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TmuxAttachQuery {
+    pub session: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action")]
+enum TmuxClientMessage {
+    #[serde(rename = "select_window")]
+    SelectWindow { window: u32 },
+    #[serde(rename = "select_pane")]
+    SelectPane { pane: u32 },
+}
+
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Query(query): Query<TmuxAttachQuery>,
+) -> Result<Response, AppError> {
+    if !state.config.features.enable_tmux {
+        return Err(AppError::BadRequest("tmux support is disabled".into()));
+    }
+    let sessions = tmux::list_sessions()
+        .await
+        .map_err(|e| AppError::Internal(format!("failed to list tmux sessions: {e}")))?;
+    if !sessions
+        .iter()
+        .any(|s| s.name == query.session || s.id == query.session)
+    {
+        return Err(AppError::BadRequest(format!(
+            "unknown tmux session: {}",
+            query.session
+        )));
+    }
+
+    Ok(ws
+        .on_upgrade(move |socket| handle_socket(socket, state, query.session))
+        .into_response())
+}
+
+async fn handle_socket(socket: WebSocket, state: AppState, session_name: String) {
+    tracing::info!("new tmux ws connection (session: {session_name})");
+
+    let (sender, mut receiver) = socket.split();
+    let sender = Arc::new(Mutex::new(sender));
+
+    let (pty_session, reader) = match state.pty.spawn(24, 80, tmux::attach_command(&session_name)) {
+        Ok(pair) => pair,
+        Err(err) => {
+            tracing::error!("failed to start tmux control client: {err:?}");
+            let _ = sender.lock().await.send(Message::Close(None)).await;
+            return;
+        }
+    };
+    let client = Arc::new(TmuxClient::new(pty_session));
+    let mut reader_task = spawn_reader_task(reader, sender.clone(), client.clone());
+
+    if let Err(err) = client.list_windows().await {
+        tracing::warn!("failed to query tmux windows: {err:?}");
+    }
+
+    while let Some(Ok(msg)) = receiver.next().await {
+        match msg {
+            Message::Binary(bytes) if !bytes.is_empty() => match bytes[0] {
+                // 0x01: Input for a pane: pane id (u32 BE) followed by the keys
+                0x01 if bytes.len() > 5 => {
+                    let pane = u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]);
+                    if let Err(err) = client.send_keys(pane, &bytes[5..]).await {
+                        tracing::error!("tmux send-keys failed: {err:?}");
+                    }
+                }
+                // 0x02: Resize the whole client
+                0x02 if bytes.len() >= 5 => {
+                    let rows = u16::from_be_bytes([bytes[1], bytes[2]]);
+                    let cols = u16::from_be_bytes([bytes[3], bytes[4]]);
+                    if let Err(err) = client.resize(rows, cols).await {
+                        tracing::warn!("tmux resize failed: {err:?}");
+                    }
+                }
+                _ => {}
+            },
+            Message::Text(text) => match serde_json::from_str::<TmuxClientMessage>(&text) {
+                Ok(TmuxClientMessage::SelectWindow { window }) => {
+                    let command = format!("select-window -t @{window}");
+                    let _ = client.command(&command, PendingCommand::Ignore).await;
+                }
+                Ok(TmuxClientMessage::SelectPane { pane }) => {
+                    let command = format!("select-pane -t %{pane}");
+                    let _ = client.command(&command, PendingCommand::Ignore).await;
+                }
+                Err(err) => {
+                    tracing::warn!("invalid tmux payload: {err}");
+                    send_error(&sender, "invalid payload").await;
+                }
+            },
+            Message::Close(_) => {
+                break;
+            }
+            Message::Ping(payload) => {
+                let _ = sender.lock().await.send(Message::Pong(payload)).await;
+            }
+            _ => {}
+        }
+    }
+
+    // Detach so the tmux session keeps running, then make sure the client is gone.
+    if client.detach().await.is_ok() {
+        let _ = tokio::time::timeout(Duration::from_secs(1), &mut reader_task).await;
+    }
+    client.session().shutdown().await;
+    reader_task.abort();
+
+    tracing::info!("tmux ws connection ended (session: {session_name})");
+}
+
+fn spawn_reader_task(
+    mut reader: Box<dyn Read + Send>,
+    sender: Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    client: Arc<TmuxClient>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut parser = ControlParser::default();
+        let mut captured = HashSet::new();
+        let mut buffer = vec![0u8; 4096];
+        loop {
+            let read_res = tokio::task::block_in_place(|| reader.read(&mut buffer));
+            let n = match read_res {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) => {
+                    tracing::warn!("tmux read failed: {err:?}");
+                    break;
+                }
+            };
+            for event in parser.feed(&buffer[..n]) {
+                if !handle_event(&sender, &client, &mut captured, event).await {
+                    let mut guard = sender.lock().await;
+                    let _ = guard.send(Message::Close(None)).await;
+                    return;
+                }
+            }
+        }
+        let mut guard = sender.lock().await;
+        let _ = guard.send(Message::Close(None)).await;
+    })
+}
+
+/// Forward one control-mode event to the browser. Returns false once tmux exits.
+async fn handle_event(
+    sender: &Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    client: &TmuxClient,
+    captured: &mut HashSet<u32>,
+    event: ControlEvent,
+) -> bool {
+    match event {
+        ControlEvent::Output { pane, data } => {
+            send_pane_output(sender, pane, &data).await;
+        }
+        ControlEvent::Reply { own, error, lines } => {
+            if !own {
+                return true;
+            }
+            let pending = client.take_pending();
+            if error {
+                send_error(sender, &lines.join("\n")).await;
+                return true;
+            }
+            match pending {
+                Some(PendingCommand::ListWindows) => {
+                    let windows = tmux::parse_windows(&lines);
+                    send_json(sender, json!({"event":"windows","windows":windows})).await;
+                    // Seed newly seen panes with their current screen contents.
+                    for pane in windows.iter().flat_map(|w| w.layout.panes()) {
+                        if !captured.insert(pane) {
+                            continue;
+                        }
+                        if let Err(err) = client.capture_pane(pane).await {
+                            tracing::warn!("failed to capture pane %{pane}: {err:?}");
+                        }
+                    }
+                }
+                Some(PendingCommand::CapturePane(pane)) => {
+                    let used = lines
+                        .iter()
+                        .rposition(|l| !l.is_empty())
+                        .map_or(0, |i| i + 1);
+                    let screen = lines[..used].join("\r\n");
+                    send_pane_output(sender, pane, screen.as_bytes()).await;
+                }
+                Some(PendingCommand::Ignore) | None => {}
+            }
+        }
+        ControlEvent::LayoutChange { window, layout } => match TmuxLayout::parse(&layout) {
+            Some(layout) => {
+                send_json(
+                    sender,
+                    json!({"event":"layout","window":window,"layout":layout}),
+                )
+                .await;
+            }
+            None => tracing::warn!("unparseable tmux layout: {layout}"),
+        },
+        ControlEvent::WindowAdd { window } => {
+            send_json(sender, json!({"event":"window_add","window":window})).await;
+            if let Err(err) = client.list_windows().await {
+                tracing::warn!("failed to query tmux windows: {err:?}");
+            }
+        }
+        ControlEvent::SessionChanged { name } => {
+            send_json(sender, json!({"event":"session_changed","name":name})).await;
+            if let Err(err) = client.list_windows().await {
+                tracing::warn!("failed to query tmux windows: {err:?}");
+            }
+        }
+        ControlEvent::WindowClose { window } => {
+            send_json(sender, json!({"event":"window_close","window":window})).await;
+        }
+        ControlEvent::WindowRenamed { window, name } => {
+            send_json(
+                sender,
+                json!({"event":"window_renamed","window":window,"name":name}),
+            )
+            .await;
+        }
+        ControlEvent::Exit { reason } => {
+            send_json(sender, json!({"event":"exit","reason":reason})).await;
+            return false;
+        }
+    }
+    true
+}
+
+/// Pane output frame: 0x01, pane id (u32 BE), then raw terminal bytes.
+async fn send_pane_output(
+    sender: &Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    pane: u32,
+    data: &[u8],
+) {
+    let mut frame = Vec::with_capacity(data.len() + 5);
+    frame.push(0x01);
+    frame.extend_from_slice(&pane.to_be_bytes());
+    frame.extend_from_slice(data);
+    let mut guard = sender.lock().await;
+    let _ = guard.send(Message::Binary(frame)).await;
+}
+
+async fn send_error(sender: &Arc<Mutex<SplitSink<WebSocket, Message>>>, message: &str) {
+    send_json(sender, json!({"event":"error","message":message})).await;
+}
+
+async fn send_json(sender: &Arc<Mutex<SplitSink<WebSocket, Message>>>, value: serde_json::Value) {
+    let mut guard = sender.lock().await;
+    let _ = guard.send(Message::Text(value.to_string())).await;
+}